@@ -0,0 +1,343 @@
+//! The app set of the Solo 2 firmware, shared by all runners.
+//!
+//! Which apps exist, their Trussed client IDs, how they are constructed,
+//! and in which order the dispatches offer them requests, lives here,
+//! so the LPC55 and the PC runner (and any future board) run the same thing.
+//! What differs between runners (how a syscall reaches the Trussed service,
+//! how to reboot, the internal filesystem) comes in through `Runner`.
+//!
+//! Apps are compiled in via features named like the app crates, and can be
+//! left out at runtime via `AppSelection` (the LPC55 runner selects them all).
+
+#![no_std]
+
+use apdu_dispatch::{command::SIZE as CommandSize, response::SIZE as ResponseSize, App as ApduApp};
+use ctaphid_dispatch::app::App as CtaphidApp;
+use trussed::client::Syscall;
+use trussed::platform::Platform;
+use trussed::ClientImplementation;
+
+/// What the apps need to know about the runner they are composed into.
+pub trait Runner {
+    /// How clients hand their requests to the Trussed service.
+    type Syscall: Syscall + Default;
+    /// How the admin and provisioner apps reboot the device.
+    type Reboot: admin_app::Reboot;
+
+    /// The Trussed store, which the provisioner app writes to directly.
+    #[cfg(feature = "provisioner-app")]
+    type Store: trussed::store::Store;
+    /// The internal filesystem, which the provisioner app can reformat.
+    #[cfg(feature = "provisioner-app")]
+    type Filesystem: 'static + trussed::types::LfsStorage;
+
+    /// Firmware version, as reported by the admin app.
+    const VERSION: u32;
+}
+
+pub type TrussedClient<R> = ClientImplementation<<R as Runner>::Syscall>;
+
+#[cfg(feature = "admin-app")]
+pub type AdminApp<R> = admin_app::App<TrussedClient<R>, <R as Runner>::Reboot>;
+#[cfg(feature = "piv-authenticator")]
+pub type PivApp<R> = piv_authenticator::Authenticator<TrussedClient<R>, { apdu_dispatch::command::SIZE }>;
+#[cfg(feature = "oath-authenticator")]
+pub type OathApp<R> = oath_authenticator::Authenticator<TrussedClient<R>>;
+#[cfg(feature = "fido-authenticator")]
+pub type FidoApp<R> = fido_authenticator::Authenticator<fido_authenticator::Conforming, TrussedClient<R>>;
+#[cfg(feature = "fido-authenticator")]
+pub type FidoConfig = fido_authenticator::Config;
+#[cfg(feature = "ndef-app")]
+pub type NdefApp<R> = ndef_app::App<TrussedClient<R>>;
+#[cfg(feature = "provisioner-app")]
+pub type ProvisionerApp<R> = provisioner_app::Provisioner<
+    <R as Runner>::Store,
+    <R as Runner>::Filesystem,
+    TrussedClient<R>,
+    <R as Runner>::Reboot,
+>;
+
+pub trait TrussedApp: Sized {
+    /// how this app's client reaches the Trussed service
+    type Syscall: Syscall + Default;
+
+    /// non-portable resources needed by this Trussed app
+    type NonPortable;
+
+    /// the desired client ID
+    const CLIENT_ID: &'static [u8];
+
+    fn with_client(trussed: ClientImplementation<Self::Syscall>, non_portable: Self::NonPortable) -> Self;
+
+    fn with<P: Platform>(trussed: &mut trussed::Service<P>, non_portable: Self::NonPortable) -> Self {
+        let client_id = core::str::from_utf8(Self::CLIENT_ID).unwrap();
+        let client = trussed
+            .try_new_client(client_id, <Self::Syscall>::default())
+            .unwrap();
+        Self::with_client(client, non_portable)
+    }
+}
+
+#[cfg(feature = "oath-authenticator")]
+impl<S: Syscall + Default> TrussedApp for oath_authenticator::Authenticator<ClientImplementation<S>> {
+    const CLIENT_ID: &'static [u8] = b"oath\0";
+
+    type Syscall = S;
+    type NonPortable = ();
+    fn with_client(trussed: ClientImplementation<S>, _: ()) -> Self {
+        Self::new(trussed)
+    }
+}
+
+#[cfg(feature = "piv-authenticator")]
+impl<S: Syscall + Default> TrussedApp for piv_authenticator::Authenticator<ClientImplementation<S>, { apdu_dispatch::command::SIZE }> {
+    const CLIENT_ID: &'static [u8] = b"piv\0";
+
+    type Syscall = S;
+    type NonPortable = ();
+    fn with_client(trussed: ClientImplementation<S>, _: ()) -> Self {
+        Self::new(trussed)
+    }
+}
+
+#[cfg(feature = "admin-app")]
+impl<S: Syscall + Default, R: admin_app::Reboot> TrussedApp for admin_app::App<ClientImplementation<S>, R> {
+    const CLIENT_ID: &'static [u8] = b"admin\0";
+
+    type Syscall = S;
+    /// the device UUID, and the firmware version
+    type NonPortable = ([u8; 16], u32);
+    fn with_client(trussed: ClientImplementation<S>, (uuid, version): Self::NonPortable) -> Self {
+        Self::new(trussed, uuid, version)
+    }
+}
+
+#[cfg(feature = "fido-authenticator")]
+impl<S: Syscall + Default> TrussedApp for fido_authenticator::Authenticator<fido_authenticator::Conforming, ClientImplementation<S>> {
+    const CLIENT_ID: &'static [u8] = b"fido\0";
+
+    type Syscall = S;
+    type NonPortable = ();
+    fn with_client(trussed: ClientImplementation<S>, _: ()) -> Self {
+        fido_authenticator::Authenticator::new(
+            trussed,
+            fido_authenticator::Conforming {},
+            FidoConfig {
+                max_msg_size: usbd_ctaphid::constants::MESSAGE_SIZE,
+                // max_creds_in_list: ctap_types::sizes::MAX_CREDENTIAL_COUNT_IN_LIST,
+                // max_cred_id_length: ctap_types::sizes::MAX_CREDENTIAL_ID_LENGTH,
+                skip_up_timeout: None,
+            },
+        )
+    }
+}
+
+#[cfg(feature = "ndef-app")]
+impl<S: Syscall + Default> TrussedApp for ndef_app::App<ClientImplementation<S>> {
+    const CLIENT_ID: &'static [u8] = b"ndef\0";
+
+    type Syscall = S;
+    type NonPortable = ();
+    fn with_client(trussed: ClientImplementation<S>, _: ()) -> Self {
+        Self::new(trussed)
+    }
+}
+
+#[cfg(feature = "provisioner-app")]
+pub struct ProvisionerNonPortable<ST, FS: 'static> {
+    pub store: ST,
+    pub stolen_filesystem: &'static mut FS,
+    pub nfc_powered: bool,
+}
+
+#[cfg(feature = "provisioner-app")]
+impl<ST, FS, S, R> TrussedApp for provisioner_app::Provisioner<ST, FS, ClientImplementation<S>, R>
+where
+    ST: trussed::store::Store,
+    FS: 'static + trussed::types::LfsStorage,
+    S: Syscall + Default,
+    R: admin_app::Reboot,
+{
+    const CLIENT_ID: &'static [u8] = b"attn\0";
+
+    type Syscall = S;
+    /// the non-portable resources, and the device UUID
+    type NonPortable = (ProvisionerNonPortable<ST, FS>, [u8; 16]);
+    fn with_client(
+        trussed: ClientImplementation<S>,
+        (ProvisionerNonPortable {
+            store,
+            stolen_filesystem,
+            nfc_powered,
+        }, uuid): Self::NonPortable,
+    ) -> Self {
+        Self::new(trussed, store, stolen_filesystem, nfc_powered, uuid)
+    }
+}
+
+/// Which of the compiled-in apps to run.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AppSelection {
+    pub admin: bool,
+    pub fido: bool,
+    pub oath: bool,
+    pub ndef: bool,
+    pub piv: bool,
+    pub provisioner: bool,
+}
+
+impl AppSelection {
+    pub const ALL: Self = Self {
+        admin: true,
+        fido: true,
+        oath: true,
+        ndef: true,
+        piv: true,
+        provisioner: true,
+    };
+    pub const NONE: Self = Self {
+        admin: false,
+        fido: false,
+        oath: false,
+        ndef: false,
+        piv: false,
+        provisioner: false,
+    };
+}
+
+impl Default for AppSelection {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl core::str::FromStr for AppSelection {
+    type Err = &'static str;
+    /// Comma-separated list of app names, or `all`.
+    fn from_str(apps: &str) -> Result<Self, Self::Err> {
+        let mut selection = Self::NONE;
+        for app in apps.split(',').map(str::trim) {
+            match app {
+                "all" => selection = Self::ALL,
+                "admin" => selection.admin = true,
+                "fido" => selection.fido = true,
+                "oath" => selection.oath = true,
+                "ndef" => selection.ndef = true,
+                "piv" => selection.piv = true,
+                "provisioner" => selection.provisioner = true,
+                _ => return Err("unknown app, use admin, fido, oath, ndef, piv, provisioner or all"),
+            }
+        }
+        Ok(selection)
+    }
+}
+
+pub struct Apps<R: Runner> {
+    #[cfg(feature = "admin-app")]
+    pub admin: Option<AdminApp<R>>,
+    #[cfg(feature = "fido-authenticator")]
+    pub fido: Option<FidoApp<R>>,
+    #[cfg(feature = "oath-authenticator")]
+    pub oath: Option<OathApp<R>>,
+    #[cfg(feature = "ndef-app")]
+    pub ndef: Option<NdefApp<R>>,
+    #[cfg(feature = "piv-authenticator")]
+    pub piv: Option<PivApp<R>>,
+    #[cfg(feature = "provisioner-app")]
+    pub provisioner: Option<ProvisionerApp<R>>,
+    _runner: core::marker::PhantomData<R>,
+}
+
+/// There are six apps, so a dispatch never offers requests to more.
+const MAX_APPS: usize = 6;
+
+impl<R: Runner> Apps<R> {
+    pub fn new<P: Platform>(
+        trussed: &mut trussed::Service<P>,
+        selection: AppSelection,
+        uuid: [u8; 16],
+        #[cfg(feature = "provisioner-app")] provisioner: ProvisionerNonPortable<R::Store, R::Filesystem>,
+    ) -> Self {
+        // not every app needs every resource
+        let _ = (&trussed, &selection, &uuid);
+
+        #[cfg(feature = "admin-app")]
+        let admin = selection.admin.then(|| AdminApp::<R>::with(trussed, (uuid, R::VERSION)));
+        #[cfg(feature = "fido-authenticator")]
+        let fido = selection.fido.then(|| FidoApp::<R>::with(trussed, ()));
+        #[cfg(feature = "oath-authenticator")]
+        let oath = selection.oath.then(|| OathApp::<R>::with(trussed, ()));
+        #[cfg(feature = "piv-authenticator")]
+        let piv = selection.piv.then(|| PivApp::<R>::with(trussed, ()));
+        #[cfg(feature = "ndef-app")]
+        let ndef = selection.ndef.then(|| NdefApp::<R>::with(trussed, ()));
+        #[cfg(feature = "provisioner-app")]
+        let provisioner = selection.provisioner.then(|| ProvisionerApp::<R>::with(trussed, (provisioner, uuid)));
+
+        Self {
+            #[cfg(feature = "admin-app")]
+            admin,
+            #[cfg(feature = "fido-authenticator")]
+            fido,
+            #[cfg(feature = "oath-authenticator")]
+            oath,
+            #[cfg(feature = "ndef-app")]
+            ndef,
+            #[cfg(feature = "piv-authenticator")]
+            piv,
+            #[cfg(feature = "provisioner-app")]
+            provisioner,
+            _runner: Default::default(),
+        }
+    }
+
+    #[inline(never)]
+    pub fn apdu_dispatch<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut [&mut dyn ApduApp<CommandSize, ResponseSize>]) -> T,
+    {
+        let mut apps: heapless::Vec<&mut dyn ApduApp<CommandSize, ResponseSize>, MAX_APPS> = heapless::Vec::new();
+        #[cfg(feature = "ndef-app")]
+        if let Some(ndef) = self.ndef.as_mut() {
+            apps.push(ndef).ok();
+        }
+        #[cfg(feature = "piv-authenticator")]
+        if let Some(piv) = self.piv.as_mut() {
+            apps.push(piv).ok();
+        }
+        #[cfg(feature = "oath-authenticator")]
+        if let Some(oath) = self.oath.as_mut() {
+            apps.push(oath).ok();
+        }
+        #[cfg(feature = "fido-authenticator")]
+        if let Some(fido) = self.fido.as_mut() {
+            apps.push(fido).ok();
+        }
+        #[cfg(feature = "admin-app")]
+        if let Some(admin) = self.admin.as_mut() {
+            apps.push(admin).ok();
+        }
+        #[cfg(feature = "provisioner-app")]
+        if let Some(provisioner) = self.provisioner.as_mut() {
+            apps.push(provisioner).ok();
+        }
+        f(&mut apps)
+    }
+
+    #[inline(never)]
+    pub fn ctaphid_dispatch<F, T>(&mut self, f: F) -> T
+    where
+        F: FnOnce(&mut [&mut dyn CtaphidApp]) -> T,
+    {
+        let mut apps: heapless::Vec<&mut dyn CtaphidApp, MAX_APPS> = heapless::Vec::new();
+        #[cfg(feature = "fido-authenticator")]
+        if let Some(fido) = self.fido.as_mut() {
+            apps.push(fido).ok();
+        }
+        #[cfg(feature = "admin-app")]
+        if let Some(admin) = self.admin.as_mut() {
+            apps.push(admin).ok();
+        }
+        f(&mut apps)
+    }
+}
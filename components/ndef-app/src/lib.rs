@@ -1,4 +1,7 @@
 #![no_std]
 
 pub mod ndef;
-pub use ndef::*;
\ No newline at end of file
+pub mod otp;
+pub mod record;
+pub mod tag;
+pub use ndef::*;
@@ -1,51 +1,358 @@
 use iso7816::{Instruction, Status};
 use apdu_dispatch::{Command, response, app, command::SIZE as CommandSize, response::SIZE as ResponseSize};
+use heapless::Vec;
+use trussed::types::{Location, Message, PathBuf};
+use trussed::{client, try_syscall};
 
-pub struct App<'a>{
-    reader: &'a [u8]
+use crate::otp::{self, Otp};
+use crate::record::{self, Record};
+use crate::tag::{self, CapabilityContainer, ACCESS_DENIED, ACCESS_GRANTED};
+
+/// The NDEF tag application (NFC Forum Type 4 Tag, 5.4.2).
+const NDEF_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+
+/// Where the NDEF file is kept, if it differs from `App::NDEF`.
+const FILENAME_NDEF: &[u8] = b"ndef";
+/// Where the one-time code configuration is kept, see `otp`.
+const FILENAME_OTP: &[u8] = b"otp";
+/// Where the write access to the NDEF file is kept, it is read-only unless it exists.
+const FILENAME_WRITE_ACCESS: &[u8] = b"access";
+
+/// UPDATE BINARY, which the NFC Forum Type 4 Tag writes with.
+const UPDATE_BINARY: u8 = 0xd6;
+
+/// Replaces the NDEF file, over the contact interface only.
+///
+/// P1 picks what the data is, the file is then built from it:
+///
+/// | P1   | data                                                              |
+/// |------|-------------------------------------------------------------------|
+/// | `00` | an NDEF message, or nothing to go back to `App::NDEF`             |
+/// | `01` | a URI                                                             |
+/// | `02` | the language code, of length P2, then the text                    |
+/// | `03` | the MIME type, of length P2, then the payload                     |
+///
+/// The proprietary instructions are kept clear of those iso7816 parses (e.g. PUT DATA, `DA`/`DB`).
+const WRITE_NDEF: u8 = 0x10;
+
+/// Configures one-time codes in the NDEF URI, over the contact interface only.
+///
+/// P1 `00` turns them off, P1 `01` on, with P2 digits (6 to 8) per code,
+/// the data being the secret length, the HMAC-SHA1 secret and the base URL.
+/// The secret is kept as a persistent Trussed key, the counter starts over at zero.
+const CONFIGURE_OTP: u8 = 0x11;
+
+/// Sets the write access to the NDEF file over UPDATE BINARY, over the contact interface only.
+///
+/// P1 is the write access as in the capability container: `00` granted, `FF` denied (the default,
+/// so a passing phone can't redirect the tag). Writing is always denied with one-time codes on.
+const SET_WRITE_ACCESS: u8 = 0xdc;
+
+/// The NDEF file, with the message length (NLEN) up front, is kept in a single Trussed file.
+pub const MAX_NDEF_SIZE: usize = 1024;
+
+/// MLe and MLc, as much as a short APDU carries.
+const MAX_READ: usize = 0xff;
+const MAX_UPDATE: usize = 0xff;
+
+enum File {
+    CapabilityContainer,
+    Ndef,
 }
 
-impl<'a> App<'a> {
-    pub const CAPABILITY_CONTAINER: [u8; 15] = [
-        0x00, 0x0f, /* CCEN_HI, CCEN_LOW */
-        0x20,       /* VERSION */
-        0x00, 0x7f, /* MLe_HI, MLe_LOW */
-        0x00, 0x7f, /* MLc_HI, MLc_LOW */
-        /* TLV */
-        0x04,0x06,
-        0xe1,0x04,
-        0x00,0x7f,
-        0x00,0x00,
-    ];
+pub struct App<T> {
+    trussed: T,
+    /// No file is selected right after the application is.
+    reader: Option<File>,
+    capability_container: Vec<u8, 17>,
+    ndef: Vec<u8, MAX_NDEF_SIZE>,
+}
 
+impl<T> App<T>
+where T: trussed::Client + client::HmacSha1,
+{
     // Externally crafted NDEF URL for "https://solokeys.com/"
     pub const NDEF : [u8; 20] = [
         0x00, 0x12, 0xd1, 0x01, 0x0e, 0x55, 0x04, 0x73, 0x6f, 0x6c,
         0x6f, 0x6b, 0x65, 0x79, 0x73, 0x2e, 0x63, 0x6f, 0x6d, 0x2f
     ];
 
-    pub fn new() -> App<'a> {
+    pub fn new(trussed: T) -> App<T> {
         App{
-            reader: &Self::NDEF,
+            trussed,
+            reader: None,
+            capability_container: Self::capability_container(ACCESS_DENIED),
+            ndef: Vec::from_slice(&Self::NDEF).unwrap(),
+        }
+    }
+
+    fn capability_container(write_access: u8) -> Vec<u8, 17> {
+        CapabilityContainer {
+            max_read: MAX_READ as u16,
+            max_update: MAX_UPDATE as u16,
+            ndef_file_id: tag::NDEF_FILE_ID,
+            max_ndef_size: MAX_NDEF_SIZE as u32,
+            read_access: ACCESS_GRANTED,
+            write_access,
+        }.encode()
+    }
+
+    fn reader(&self) -> Result<&[u8], Status> {
+        match self.reader {
+            Some(File::CapabilityContainer) => Ok(&self.capability_container),
+            Some(File::Ndef) => Ok(&self.ndef),
+            None => Err(Status::ConditionsOfUseNotSatisfied),
+        }
+    }
+
+    /// Loads the NDEF file from the store, falling back to `NDEF`.
+    fn load(&mut self) {
+        self.ndef = try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(FILENAME_NDEF)))
+            .ok()
+            .and_then(|reply| Vec::from_slice(&reply.data).ok())
+            .unwrap_or_else(|| Vec::from_slice(&Self::NDEF).unwrap());
+        self.capability_container = Self::capability_container(match self.is_writable() {
+            true => ACCESS_GRANTED,
+            false => ACCESS_DENIED,
+        });
+    }
+
+    /// Whether the NDEF file may be written with UPDATE BINARY.
+    fn is_writable(&mut self) -> bool {
+        let granted = try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(FILENAME_WRITE_ACCESS)))
+            .map(|reply| reply.data.as_slice() == [ACCESS_GRANTED])
+            .unwrap_or(false);
+        let dynamic = try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(FILENAME_OTP))).is_ok();
+        granted && !dynamic
+    }
+
+    fn set_write_access(&mut self, apdu: &Command) -> app::Result {
+        match apdu.p1 {
+            ACCESS_GRANTED => try_syscall!(self.trussed.write_file(
+                Location::Internal,
+                PathBuf::from(FILENAME_WRITE_ACCESS),
+                Message::from_slice(&[ACCESS_GRANTED]).unwrap(),
+                None,
+            )).map_err(|_| Status::NotEnoughMemory)?,
+            ACCESS_DENIED => {
+                try_syscall!(self.trussed.remove_file(Location::Internal, PathBuf::from(FILENAME_WRITE_ACCESS))).ok();
+            }
+            _ => return Err(Status::IncorrectP1OrP2Parameter),
+        };
+        self.load();
+        Ok(())
+    }
+
+    /// Writes to the NDEF file, which is saved whenever the message length (NLEN) is set.
+    ///
+    /// Readers set NLEN to zero, write the message, then set NLEN (NFC Forum Type 4 Tag, 7.5.4),
+    /// so a message cut short leaves an empty one, as on a tag.
+    fn update_binary(&mut self, offset: usize, data: &[u8]) -> app::Result {
+        match self.reader {
+            Some(File::Ndef) => {}
+            Some(File::CapabilityContainer) => return Err(Status::SecurityStatusNotSatisfied),
+            None => return Err(Status::ConditionsOfUseNotSatisfied),
+        }
+        if !self.is_writable() {
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+        if data.len() > MAX_UPDATE {
+            return Err(Status::WrongLength);
+        }
+        let range = tag::update_range(MAX_NDEF_SIZE, offset, data.len())?;
+        if offset >= 2 {
+            // the message is being written
+            self.write(range, data);
+            return Ok(());
+        }
+
+        // NLEN as it is after this write, checked before anything changes
+        let mut nlen = [0; 2];
+        for (position, byte) in nlen.iter_mut().enumerate() {
+            *byte = match range.contains(&position) {
+                true => data[position - offset],
+                false => self.ndef.get(position).copied().unwrap_or(0),
+            };
+        }
+        let length = u16::from_be_bytes(nlen) as usize;
+        if 2 + length > MAX_NDEF_SIZE {
+            return Err(Status::IncorrectDataParameter);
         }
+        self.write(range, data);
+
+        let file = match length {
+            // there is no message while it is being written
+            0 => &self.ndef[..2],
+            _ => {
+                self.ndef.resize_default(2 + length).unwrap();
+                &self.ndef[..]
+            }
+        };
+        try_syscall!(self.trussed.write_file(
+            Location::Internal,
+            PathBuf::from(FILENAME_NDEF),
+            Message::from_slice(file).unwrap(),
+            None,
+        )).map_err(|_| Status::NotEnoughMemory)?;
+        Ok(())
+    }
+
+    /// Writes `data` to `range` of the NDEF file kept in memory.
+    fn write(&mut self, range: core::ops::Range<usize>, data: &[u8]) {
+        if range.end > self.ndef.len() {
+            // the file has its maximum size, whatever was not written is zero
+            self.ndef.resize_default(range.end).unwrap();
+        }
+        self.ndef[range].copy_from_slice(data);
+    }
+
+    fn write_ndef(&mut self, apdu: &Command) -> app::Result {
+        let data = apdu.data();
+        let ndef: Vec<u8, MAX_NDEF_SIZE> = match apdu.p1 {
+            0x00 if data.is_empty() => {
+                try_syscall!(self.trussed.remove_file(Location::Internal, PathBuf::from(FILENAME_NDEF))).ok();
+                self.ndef = Vec::from_slice(&Self::NDEF).unwrap();
+                return Ok(());
+            }
+            0x00 => {
+                let length = u16::try_from(data.len()).map_err(|_| Status::WrongLength)?;
+                let mut ndef = Vec::new();
+                ndef.extend_from_slice(&length.to_be_bytes()).ok();
+                ndef.extend_from_slice(data).map_err(|_| Status::WrongLength)?;
+                Ok(ndef)
+            }
+            0x01 => record::ndef_file(&[Record::Uri(text(data)?)]),
+            0x02 => {
+                let (language, rest) = split(data, apdu.p2)?;
+                record::ndef_file(&[Record::Text { language, text: text(rest)? }])
+            }
+            0x03 => {
+                let (media_type, payload) = split(data, apdu.p2)?;
+                record::ndef_file(&[Record::Mime { media_type, payload }])
+            }
+            _ => return Err(Status::IncorrectP1OrP2Parameter),
+        }.map_err(|_| Status::WrongLength)?;
+
+        try_syscall!(self.trussed.write_file(
+            Location::Internal,
+            PathBuf::from(FILENAME_NDEF),
+            Message::from_slice(&ndef).unwrap(),
+            None,
+        )).map_err(|_| Status::NotEnoughMemory)?;
+        self.ndef = ndef;
+        Ok(())
+    }
+
+    fn configure_otp(&mut self, apdu: &Command) -> app::Result {
+        let otp = match apdu.p1 {
+            0x00 => None,
+            0x01 => {
+                let (secret, base_url) = otp::configuration(apdu.p2, apdu.data())
+                    .map_err(|_| Status::IncorrectDataParameter)?;
+                // kept in Trussed from here on, only the key ID goes into the configuration
+                let key = try_syscall!(self.trussed.unsafe_inject_shared_key(secret, Location::Internal))
+                    .map_err(|_| Status::NotEnoughMemory)?
+                    .key;
+                Some(Otp { digits: apdu.p2, counter: 0, key, base_url })
+            }
+            _ => return Err(Status::IncorrectP1OrP2Parameter),
+        };
+
+        // a configuration that can't be read has no key to delete either
+        let previous = self.load_otp().ok().flatten();
+        match &otp {
+            Some(otp) => {
+                if let Err(status) = self.save_otp(otp) {
+                    try_syscall!(self.trussed.delete(otp.key)).ok();
+                    return Err(status);
+                }
+            }
+            None => {
+                try_syscall!(self.trussed.remove_file(Location::Internal, PathBuf::from(FILENAME_OTP))).ok();
+            }
+        }
+        if let Some(previous) = previous {
+            try_syscall!(self.trussed.delete(previous.key)).ok();
+        }
+        self.load();
+        Ok(())
+    }
+
+    /// The one-time code configuration, if there is one.
+    fn load_otp(&mut self) -> Result<Option<Otp>, Status> {
+        match try_syscall!(self.trussed.read_file(Location::Internal, PathBuf::from(FILENAME_OTP))) {
+            Ok(reply) => Otp::deserialize(&reply.data)
+                .map(Some)
+                .map_err(|_| Status::UnspecifiedCheckingError),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn save_otp(&mut self, otp: &Otp) -> app::Result {
+        let serialized: Vec<u8, 256> = otp.serialize();
+        try_syscall!(self.trussed.write_file(
+            Location::Internal,
+            PathBuf::from(FILENAME_OTP),
+            Message::from_slice(&serialized).unwrap(),
+            None,
+        )).map_err(|_| Status::NotEnoughMemory)?;
+        Ok(())
+    }
+
+    /// Replaces the NDEF file by a URI with the next one-time code, if they are configured.
+    fn tap(&mut self) -> app::Result {
+        let mut otp = match self.load_otp()? {
+            Some(otp) => otp,
+            None => return Ok(()),
+        };
+        otp.counter = otp.counter.checked_add(1).ok_or(Status::ConditionsOfUseNotSatisfied)?;
+        // a code must never be shown twice, even if the tap is cut short
+        self.save_otp(&otp)?;
+
+        let hmac = try_syscall!(self.trussed.sign_hmacsha1(otp.key, &(otp.counter as u64).to_be_bytes()))
+            .map_err(|_| Status::UnspecifiedCheckingError)?
+            .signature;
+        let hmac = <[u8; 20]>::try_from(hmac.as_slice()).map_err(|_| Status::UnspecifiedCheckingError)?;
+
+        let uri = otp.uri(otp.code(&hmac));
+        self.ndef = record::ndef_file(&[Record::Uri(core::str::from_utf8(&uri).unwrap())])
+            .map_err(|_| Status::NotEnoughMemory)?;
+        Ok(())
     }
 }
 
-impl<'a> iso7816::App for App<'a> {
+fn text(data: &[u8]) -> Result<&str, Status> {
+    core::str::from_utf8(data).map_err(|_| Status::IncorrectDataParameter)
+}
+
+/// Splits off the first `length` bytes, as text.
+fn split(data: &[u8], length: u8) -> Result<(&str, &[u8]), Status> {
+    if data.len() < length as usize {
+        return Err(Status::IncorrectDataParameter);
+    }
+    let (first, rest) = data.split_at(length as usize);
+    Ok((text(first)?, rest))
+}
+
+impl<T> iso7816::App for App<T> {
     fn aid(&self) -> iso7816::Aid {
-        iso7816::Aid::new(&[0xD2u8, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01])
+        iso7816::Aid::new(&NDEF_AID)
     }
 }
 
-impl<'a> app::App<CommandSize, ResponseSize> for App<'a> {
+impl<T> app::App<CommandSize, ResponseSize> for App<T>
+where T: trussed::Client + client::HmacSha1,
+{
 
     fn select(&mut self, _apdu: &Command, _reply: &mut response::Data) -> app::Result {
+        self.reader = None;
+        self.load();
         Ok(())
     }
 
     fn deselect(&mut self) {}
 
-    fn call(&mut self, _type: app::Interface, apdu: &Command, reply: &mut response::Data) -> app::Result {
+    fn call(&mut self, interface: app::Interface, apdu: &Command, reply: &mut response::Data) -> app::Result {
         let instruction = apdu.instruction();
         let p1 = apdu.p1;
         let p2 = apdu.p2;
@@ -54,33 +361,61 @@ impl<'a> app::App<CommandSize, ResponseSize> for App<'a> {
 
 
         match instruction {
-            Instruction::Select => {
-
-                if payload.starts_with(&[0xE1u8, 0x03]) {
-                    self.reader = &Self::CAPABILITY_CONTAINER;
-                    Ok(())
-                } else if payload.starts_with(&[0xE1u8, 0x04]) {
-                    self.reader = &Self::NDEF;
+            Instruction::Select => match (p1, p2) {
+                // by file identifier, without FCI (T4T 1.0 readers send P2 = 00)
+                (0x00, 0x0c) | (0x00, 0x00) => {
+                    if payload.len() != 2 {
+                        return Err(Status::WrongLength);
+                    }
+                    if payload == tag::CAPABILITY_CONTAINER_ID {
+                        self.reader = Some(File::CapabilityContainer);
+                        Ok(())
+                    } else if payload == tag::NDEF_FILE_ID {
+                        self.reader = Some(File::Ndef);
+                        self.tap()
+                    } else {
+                        Err(Status::NotFound)
+                    }
+                }
+                // by name, the NDEF tag application is the only one here
+                (0x04, 0x00) => {
+                    if payload != NDEF_AID {
+                        return Err(Status::NotFound);
+                    }
+                    self.reader = None;
+                    self.load();
                     Ok(())
-                } else {
-                    Err(Status::NotFound)
                 }
-            }
+                _ => Err(Status::IncorrectP1OrP2Parameter),
+            },
             Instruction::ReadBinary => {
-                let offset = (((p1 & 0xef) as usize) << 8) | p2 as usize;
-                let len_to_read =
-                    if expected as usize > (self.reader.len() - offset) {
-                        self.reader.len() - offset
-                    } else {
-                        if expected > 0 {
-                            expected as usize
-                        } else {
-                            self.reader.len() - offset
-                        }
-                    };
-
-                reply.extend_from_slice(& self.reader[offset .. offset + len_to_read]).ok();
-                Ok(())
+                let offset = tag::offset(p1, p2)?;
+                let reader = self.reader()?;
+                let range = tag::read_range(reader.len(), offset, expected as usize, MAX_READ)?;
+                reply.extend_from_slice(&reader[range]).map_err(|_| Status::NotEnoughMemory)
+            }
+            Instruction::Unknown(UPDATE_BINARY) => {
+                let offset = tag::offset(p1, p2)?;
+                self.update_binary(offset, payload)
+            }
+            Instruction::Unknown(WRITE_NDEF) => {
+                // not for anyone passing by with a phone
+                if !matches!(interface, app::Interface::Contact) {
+                    return Err(Status::SecurityStatusNotSatisfied);
+                }
+                self.write_ndef(apdu)
+            }
+            Instruction::Unknown(CONFIGURE_OTP) => {
+                if !matches!(interface, app::Interface::Contact) {
+                    return Err(Status::SecurityStatusNotSatisfied);
+                }
+                self.configure_otp(apdu)
+            }
+            Instruction::Unknown(SET_WRITE_ACCESS) => {
+                if !matches!(interface, app::Interface::Contact) {
+                    return Err(Status::SecurityStatusNotSatisfied);
+                }
+                self.set_write_access(apdu)
             }
             _ => {
                 Err(Status::ConditionsOfUseNotSatisfied)
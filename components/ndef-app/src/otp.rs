@@ -0,0 +1,105 @@
+//! One-time codes in the NDEF URI, fresh on every tap, so phones can authenticate without an app.
+//!
+//! Once configured, selecting the NDEF file bumps a counter and replaces the NDEF file by a URI
+//! record of the base URL, followed by the counter (8 hex digits) and its HOTP code (RFC 4226).
+//! The server keeps the secret and the last counter it saw, and accepts only larger counters.
+//!
+//! The secret is kept as a Trussed key, the configuration in the store as
+//!
+//! ```text
+//! digits (1) || counter (4) || key ID (32, in hex) || base URL
+//! ```
+
+use heapless::Vec;
+use trussed::types::KeyId;
+
+/// The longest HMAC-SHA1 secret, RFC 4226 asks for at least 16 bytes and recommends 20.
+pub const MAX_SECRET_LENGTH: usize = 64;
+/// Leaves room for the record header and the code, in the NDEF file.
+pub const MAX_BASE_URL_LENGTH: usize = 96;
+
+/// Length of a key ID in hex.
+const KEY_ID_LENGTH: usize = 32;
+
+pub struct Otp {
+    pub digits: u8,
+    pub counter: u32,
+    pub key: KeyId,
+    pub base_url: Vec<u8, MAX_BASE_URL_LENGTH>,
+}
+
+/// The configuration is not valid.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Invalid;
+
+/// Checks the number of digits, and splits `data` (the secret length, the secret and the base URL)
+/// into the secret and the base URL.
+pub fn configuration(digits: u8, data: &[u8]) -> Result<(&[u8], Vec<u8, MAX_BASE_URL_LENGTH>), Invalid> {
+    if !(6..=8).contains(&digits) {
+        return Err(Invalid);
+    }
+    let (secret_length, rest) = data.split_first().ok_or(Invalid)?;
+    let secret_length = *secret_length as usize;
+    if secret_length > MAX_SECRET_LENGTH || rest.len() < secret_length {
+        return Err(Invalid);
+    }
+    let (secret, base_url) = rest.split_at(secret_length);
+    Ok((secret, base_url_from(base_url)?))
+}
+
+fn base_url_from(base_url: &[u8]) -> Result<Vec<u8, MAX_BASE_URL_LENGTH>, Invalid> {
+    // the URI goes into an NDEF record as is
+    core::str::from_utf8(base_url).map_err(|_| Invalid)?;
+    Vec::from_slice(base_url).map_err(|_| Invalid)
+}
+
+impl Otp {
+    pub fn deserialize(serialized: &[u8]) -> Result<Self, Invalid> {
+        if serialized.len() < 5 + KEY_ID_LENGTH {
+            return Err(Invalid);
+        }
+        let (header, rest) = serialized.split_at(5);
+        let digits = header[0];
+        if !(6..=8).contains(&digits) {
+            return Err(Invalid);
+        }
+        let counter = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
+        let (key, base_url) = rest.split_at(KEY_ID_LENGTH);
+
+        Ok(Self {
+            digits,
+            counter,
+            key: KeyId::try_from_hex(key).map_err(|_| Invalid)?,
+            base_url: base_url_from(base_url)?,
+        })
+    }
+
+    pub fn serialize<const N: usize>(&self) -> Vec<u8, N> {
+        let mut serialized = Vec::new();
+        serialized.push(self.digits).ok();
+        serialized.extend_from_slice(&self.counter.to_be_bytes()).ok();
+        serialized.extend_from_slice(&self.key.hex()).ok();
+        serialized.extend_from_slice(&self.base_url).ok();
+        serialized
+    }
+
+    /// The code from the HMAC-SHA1 of the counter, by dynamic truncation (RFC 4226, section 5.3).
+    pub fn code(&self, hmac: &[u8; 20]) -> u32 {
+        let offset = (hmac[19] & 0xf) as usize;
+        let truncated = u32::from_be_bytes([hmac[offset], hmac[offset + 1], hmac[offset + 2], hmac[offset + 3]]);
+        (truncated & 0x7fff_ffff) % 10u32.pow(self.digits as u32)
+    }
+
+    /// The base URL, followed by the counter and the code.
+    pub fn uri(&self, code: u32) -> Vec<u8, { MAX_BASE_URL_LENGTH + 16 }> {
+        let mut uri = Vec::from_slice(&self.base_url).unwrap();
+        for shift in (0..8).rev() {
+            let nibble = (self.counter >> (4 * shift)) & 0xf;
+            uri.push(b"0123456789abcdef"[nibble as usize]).unwrap();
+        }
+        for position in (0..self.digits as u32).rev() {
+            uri.push(b'0' + (code / 10u32.pow(position) % 10) as u8).unwrap();
+        }
+        uri
+    }
+}
@@ -0,0 +1,130 @@
+//! Building NDEF messages (NFC Forum NDEF 1.0) of well-known URI and Text records, and MIME records.
+
+use heapless::Vec;
+
+/// Type name format of NFC Forum well-known types
+const TNF_WELL_KNOWN: u8 = 0x01;
+/// Type name format of MIME media types (RFC 2046)
+const TNF_MEDIA: u8 = 0x02;
+
+const MESSAGE_BEGIN: u8 = 0x80;
+const MESSAGE_END: u8 = 0x40;
+const SHORT_RECORD: u8 = 0x10;
+
+/// URI prefixes that the URI record type abbreviates, the index is the identifier code.
+const URI_PREFIXES: [&str; 36] = [
+    "", "http://www.", "https://www.", "http://", "https://", "tel:", "mailto:",
+    "ftp://anonymous:anonymous@", "ftp://ftp.", "ftps://", "sftp://", "smb://", "nfs://",
+    "ftp://", "dav://", "news:", "telnet://", "imap:", "rtsp://", "urn:", "pop:", "sip:",
+    "sips:", "tftp:", "btspp://", "btl2cap://", "btgoep://", "tcpobex://", "irdaobex://",
+    "file://", "urn:epc:id:", "urn:epc:tag:", "urn:epc:pat:", "urn:epc:raw:", "urn:epc:",
+    "urn:nfc:",
+];
+
+/// The message did not fit its buffer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Overflow;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Record<'a> {
+    /// Well-known type "U", common prefixes are abbreviated
+    Uri(&'a str),
+    /// Well-known type "T", UTF-8 text in the language with the given IANA code, e.g. "en"
+    Text { language: &'a str, text: &'a str },
+    /// The payload, of the media type, e.g. "text/vcard"
+    Mime { media_type: &'a str, payload: &'a [u8] },
+}
+
+impl Record<'_> {
+    fn type_name_format(&self) -> u8 {
+        match self {
+            Record::Uri(_) | Record::Text { .. } => TNF_WELL_KNOWN,
+            Record::Mime { .. } => TNF_MEDIA,
+        }
+    }
+
+    fn record_type(&self) -> &[u8] {
+        match self {
+            Record::Uri(_) => b"U",
+            Record::Text { .. } => b"T",
+            Record::Mime { media_type, .. } => media_type.as_bytes(),
+        }
+    }
+
+    /// The payload, as a header byte (if any) and up to two parts.
+    fn payload(&self) -> Result<(Option<u8>, [&[u8]; 2]), Overflow> {
+        Ok(match self {
+            Record::Uri(uri) => {
+                let (code, rest) = abbreviate(uri);
+                (Some(code), [rest.as_bytes(), &[]])
+            }
+            Record::Text { language, text } => {
+                // UTF-8, the status byte has room for six bits of language code length
+                if language.len() >= 0x40 {
+                    return Err(Overflow);
+                }
+                (Some(language.len() as u8), [language.as_bytes(), text.as_bytes()])
+            }
+            Record::Mime { payload, .. } => (None, [payload, &[]]),
+        })
+    }
+}
+
+/// The identifier code of the longest prefix of the URI that can be abbreviated, and the rest.
+fn abbreviate(uri: &str) -> (u8, &str) {
+    // "" matches all
+    let (code, prefix) = URI_PREFIXES.iter()
+        .enumerate()
+        .filter(|(_, prefix)| uri.starts_with(*prefix))
+        .max_by_key(|(_, prefix)| prefix.len())
+        .unwrap();
+    (code as u8, &uri[prefix.len()..])
+}
+
+/// Appends the NDEF message of the records to `out`.
+pub fn encode<const N: usize>(records: &[Record], out: &mut Vec<u8, N>) -> Result<(), Overflow> {
+    for (index, record) in records.iter().enumerate() {
+        let record_type = record.record_type();
+        let (first, parts) = record.payload()?;
+        let length = first.iter().count() + parts.iter().map(|part| part.len()).sum::<usize>();
+        if record_type.len() > 0xff || length > u32::MAX as usize {
+            return Err(Overflow);
+        }
+
+        let mut header = record.type_name_format();
+        if index == 0 {
+            header |= MESSAGE_BEGIN;
+        }
+        if index + 1 == records.len() {
+            header |= MESSAGE_END;
+        }
+        if length <= 0xff {
+            header |= SHORT_RECORD;
+        }
+
+        out.extend_from_slice(&[header, record_type.len() as u8]).map_err(|_| Overflow)?;
+        if length <= 0xff {
+            out.push(length as u8).map_err(|_| Overflow)?;
+        } else {
+            out.extend_from_slice(&(length as u32).to_be_bytes()).map_err(|_| Overflow)?;
+        }
+        out.extend_from_slice(record_type).map_err(|_| Overflow)?;
+        if let Some(first) = first {
+            out.push(first).map_err(|_| Overflow)?;
+        }
+        for part in parts {
+            out.extend_from_slice(part).map_err(|_| Overflow)?;
+        }
+    }
+    Ok(())
+}
+
+/// The contents of an NDEF file: the message length (NLEN), then the message.
+pub fn ndef_file<const N: usize>(records: &[Record]) -> Result<Vec<u8, N>, Overflow> {
+    let mut file = Vec::new();
+    file.extend_from_slice(&[0, 0]).map_err(|_| Overflow)?;
+    encode(records, &mut file)?;
+    let length = u16::try_from(file.len() - 2).map_err(|_| Overflow)?;
+    file[..2].copy_from_slice(&length.to_be_bytes());
+    Ok(file)
+}
@@ -0,0 +1,91 @@
+//! NFC Forum Type 4 Tag (T4T) files: the capability container, and offsets into files.
+//!
+//! Mapping version 2.0 describes NDEF files of up to `0xFFFE` bytes, with a two byte NLEN.
+//! Past that, mapping version 3.0 describes them with an ENDEF file control TLV instead,
+//! the file then starting with a four byte ENLEN.
+
+use core::ops::Range;
+
+use heapless::Vec;
+use iso7816::Status;
+
+pub const CAPABILITY_CONTAINER_ID: [u8; 2] = [0xe1, 0x03];
+pub const NDEF_FILE_ID: [u8; 2] = [0xe1, 0x04];
+
+/// Read and write access values of the capability container
+pub const ACCESS_GRANTED: u8 = 0x00;
+pub const ACCESS_DENIED: u8 = 0xff;
+
+const NDEF_FILE_CONTROL: u8 = 0x04;
+const ENDEF_FILE_CONTROL: u8 = 0x06;
+
+/// The largest NDEF file mapping version 2.0 can describe.
+pub const MAX_SHORT_NDEF_SIZE: u32 = 0xfffe;
+
+pub struct CapabilityContainer {
+    /// MLe, the most data READ BINARY replies with
+    pub max_read: u16,
+    /// MLc, the most data UPDATE BINARY takes
+    pub max_update: u16,
+    pub ndef_file_id: [u8; 2],
+    pub max_ndef_size: u32,
+    pub read_access: u8,
+    pub write_access: u8,
+}
+
+impl CapabilityContainer {
+    pub fn encode(&self) -> Vec<u8, 17> {
+        let extended = self.max_ndef_size > MAX_SHORT_NDEF_SIZE;
+        let (version, tag, length) = match extended {
+            false => (0x20, NDEF_FILE_CONTROL, 6),
+            true => (0x30, ENDEF_FILE_CONTROL, 8),
+        };
+
+        let mut cc = Vec::new();
+        cc.extend_from_slice(&[0, 0, version]).unwrap();
+        cc.extend_from_slice(&self.max_read.to_be_bytes()).unwrap();
+        cc.extend_from_slice(&self.max_update.to_be_bytes()).unwrap();
+        cc.extend_from_slice(&[tag, length]).unwrap();
+        cc.extend_from_slice(&self.ndef_file_id).unwrap();
+        match extended {
+            false => cc.extend_from_slice(&(self.max_ndef_size as u16).to_be_bytes()).unwrap(),
+            true => cc.extend_from_slice(&self.max_ndef_size.to_be_bytes()).unwrap(),
+        }
+        cc.extend_from_slice(&[self.read_access, self.write_access]).unwrap();
+        let length = cc.len() as u16;
+        cc[..2].copy_from_slice(&length.to_be_bytes());
+        cc
+    }
+}
+
+/// The offset of READ BINARY and UPDATE BINARY, refusing short EF identifiers (P1 bit 8).
+pub fn offset(p1: u8, p2: u8) -> Result<usize, Status> {
+    if p1 & 0x80 != 0 {
+        return Err(Status::IncorrectP1OrP2Parameter);
+    }
+    Ok(u16::from_be_bytes([p1, p2]) as usize)
+}
+
+/// What READ BINARY replies with, for up to `expected` bytes (all there are if zero) from `offset`.
+pub fn read_range(file_size: usize, offset: usize, expected: usize, max_read: usize) -> Result<Range<usize>, Status> {
+    if offset >= file_size {
+        return Err(Status::IncorrectP1OrP2Parameter);
+    }
+    let available = file_size - offset;
+    let length = match expected {
+        0 => available,
+        expected => expected.min(available),
+    };
+    Ok(offset..offset + length.min(max_read))
+}
+
+/// Where UPDATE BINARY writes `length` bytes at `offset`, into a file of at most `max_size` bytes.
+pub fn update_range(max_size: usize, offset: usize, length: usize) -> Result<Range<usize>, Status> {
+    if offset >= max_size {
+        return Err(Status::IncorrectP1OrP2Parameter);
+    }
+    if length > max_size - offset {
+        return Err(Status::WrongLength);
+    }
+    Ok(offset..offset + length)
+}
@@ -0,0 +1,75 @@
+use heapless::Vec;
+use ndef_app::record::{self, Overflow, Record};
+
+fn encode(records: &[Record]) -> Result<Vec<u8, 1024>, Overflow> {
+    let mut message = Vec::new();
+    record::encode(records, &mut message)?;
+    Ok(message)
+}
+
+#[test]
+fn uri_records() {
+    // NFC Forum URI RTD, the example record of "http://www.nfc.com"
+    assert_eq!(encode(&[Record::Uri("http://www.nfc.com")]).unwrap(), [
+        0xd1, 0x01, 0x08, 0x55, 0x01, b'n', b'f', b'c', b'.', b'c', b'o', b'm',
+    ]);
+    // the longest prefix wins
+    assert_eq!(encode(&[Record::Uri("https://www.example.com")]).unwrap()[4], 0x02);
+    assert_eq!(encode(&[Record::Uri("urn:epc:id:sgtin")]).unwrap()[4], 0x1e);
+    // not abbreviated
+    assert_eq!(encode(&[Record::Uri("gopher://x")]).unwrap(), [
+        0xd1, 0x01, 0x0b, 0x55, 0x00, b'g', b'o', b'p', b'h', b'e', b'r', b':', b'/', b'/', b'x',
+    ]);
+}
+
+#[test]
+fn text_records() {
+    // NFC Forum Text RTD, "Hello, world!" in English
+    let mut expected = std::vec![0xd1, 0x01, 0x10, 0x54, 0x02, b'e', b'n'];
+    expected.extend_from_slice(b"Hello, world!");
+    let message = encode(&[Record::Text { language: "en", text: "Hello, world!" }]).unwrap();
+    assert_eq!(message[..], expected[..]);
+
+    let long = [b'a'; 0x40];
+    let language = core::str::from_utf8(&long).unwrap();
+    assert_eq!(encode(&[Record::Text { language, text: "" }]), Err(Overflow));
+}
+
+#[test]
+fn mime_records() {
+    let message = encode(&[Record::Mime { media_type: "text/plain", payload: b"hi" }]).unwrap();
+    let mut expected = std::vec![0xd2, 0x0a, 0x02];
+    expected.extend_from_slice(b"text/plainhi");
+    assert_eq!(message[..], expected[..]);
+
+    // past 255 bytes of payload, the length takes four bytes
+    let payload = [0u8; 256];
+    let message = encode(&[Record::Mime { media_type: "a/b", payload: &payload }]).unwrap();
+    assert_eq!(message[..9], [0xc2, 0x03, 0x00, 0x00, 0x01, 0x00, b'a', b'/', b'b']);
+    assert_eq!(message.len(), 9 + 256);
+}
+
+#[test]
+fn messages() {
+    let message = encode(&[
+        Record::Uri("https://solokeys.com/"),
+        Record::Text { language: "en", text: "Solo 2" },
+        Record::Mime { media_type: "a/b", payload: b"" },
+    ]).unwrap();
+    // message begin on the first record, message end on the last
+    assert_eq!(message[0], 0x91);
+    assert_eq!(message[18], 0x11);
+    assert_eq!(message[31], 0x52);
+    assert_eq!(message.len(), 37);
+}
+
+#[test]
+fn ndef_files() {
+    // the file the tag served before it was configurable
+    let file: Vec<u8, 32> = record::ndef_file(&[Record::Uri("https://solokeys.com/")]).unwrap();
+    assert_eq!(file, [
+        0x00, 0x12, 0xd1, 0x01, 0x0e, 0x55, 0x04, 0x73, 0x6f, 0x6c,
+        0x6f, 0x6b, 0x65, 0x79, 0x73, 0x2e, 0x63, 0x6f, 0x6d, 0x2f,
+    ]);
+    assert_eq!(record::ndef_file::<16>(&[Record::Uri("https://solokeys.com/")]), Err(Overflow));
+}
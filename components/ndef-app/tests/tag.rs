@@ -0,0 +1,82 @@
+use iso7816::Status;
+use ndef_app::tag::{self, CapabilityContainer, ACCESS_DENIED, ACCESS_GRANTED};
+
+#[test]
+fn capability_container_of_the_spec_example() {
+    // NFC Forum Type 4 Tag 2.0, the capability container of the example tag
+    let cc = CapabilityContainer {
+        max_read: 0x003b,
+        max_update: 0x0034,
+        ndef_file_id: tag::NDEF_FILE_ID,
+        max_ndef_size: 0x0032,
+        read_access: ACCESS_GRANTED,
+        write_access: ACCESS_GRANTED,
+    };
+    assert_eq!(cc.encode(), [
+        0x00, 0x0f, 0x20, 0x00, 0x3b, 0x00, 0x34,
+        0x04, 0x06, 0xe1, 0x04, 0x00, 0x32, 0x00, 0x00,
+    ]);
+}
+
+#[test]
+fn extended_capability_container() {
+    let cc = |max_ndef_size| CapabilityContainer {
+        max_read: 0x00ff,
+        max_update: 0x00ff,
+        ndef_file_id: tag::NDEF_FILE_ID,
+        max_ndef_size,
+        read_access: ACCESS_GRANTED,
+        write_access: ACCESS_DENIED,
+    }.encode();
+
+    // mapping version 2.0 goes up to 0xfffe
+    assert_eq!(cc(0x0400)[..], [
+        0x00, 0x0f, 0x20, 0x00, 0xff, 0x00, 0xff,
+        0x04, 0x06, 0xe1, 0x04, 0x04, 0x00, 0x00, 0xff,
+    ]);
+    assert_eq!(cc(0xfffe)[11..13], [0xff, 0xfe]);
+    // then 3.0, with an ENDEF file control TLV
+    assert_eq!(cc(0x0001_0000)[..], [
+        0x00, 0x11, 0x30, 0x00, 0xff, 0x00, 0xff,
+        0x06, 0x08, 0xe1, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0xff,
+    ]);
+}
+
+#[test]
+fn offsets() {
+    assert_eq!(tag::offset(0x00, 0x00), Ok(0));
+    assert_eq!(tag::offset(0x01, 0x02), Ok(0x0102));
+    assert_eq!(tag::offset(0x7f, 0xff), Ok(0x7fff));
+    // P1 bit 8 announces a short EF identifier, which the T4T does not use
+    assert_eq!(tag::offset(0x80, 0x00), Err(Status::IncorrectP1OrP2Parameter));
+    assert_eq!(tag::offset(0xef, 0x00), Err(Status::IncorrectP1OrP2Parameter));
+}
+
+#[test]
+fn read_ranges() {
+    // reading the CC, then NLEN, then the message, as readers do
+    assert_eq!(tag::read_range(15, 0, 15, 0xff), Ok(0..15));
+    assert_eq!(tag::read_range(20, 0, 2, 0xff), Ok(0..2));
+    assert_eq!(tag::read_range(20, 2, 18, 0xff), Ok(2..20));
+
+    // Le past the end, or absent
+    assert_eq!(tag::read_range(20, 15, 0xff, 0xff), Ok(15..20));
+    assert_eq!(tag::read_range(20, 15, 0, 0xff), Ok(15..20));
+    // MLe
+    assert_eq!(tag::read_range(300, 0, 256, 0xff), Ok(0..0xff));
+
+    assert_eq!(tag::read_range(20, 20, 1, 0xff), Err(Status::IncorrectP1OrP2Parameter));
+    assert_eq!(tag::read_range(20, 0x7fff, 1, 0xff), Err(Status::IncorrectP1OrP2Parameter));
+    assert_eq!(tag::read_range(0, 0, 0, 0xff), Err(Status::IncorrectP1OrP2Parameter));
+}
+
+#[test]
+fn update_ranges() {
+    assert_eq!(tag::update_range(0x400, 0, 2), Ok(0..2));
+    assert_eq!(tag::update_range(0x400, 2, 0xff), Ok(2..0x101));
+    assert_eq!(tag::update_range(0x400, 0x3ff, 1), Ok(0x3ff..0x400));
+
+    assert_eq!(tag::update_range(0x400, 0x3ff, 2), Err(iso7816::Status::WrongLength));
+    assert_eq!(tag::update_range(0x400, 0x400, 0), Err(Status::IncorrectP1OrP2Parameter));
+    assert_eq!(tag::update_range(0x400, 0x7fff, 0xff), Err(Status::IncorrectP1OrP2Parameter));
+}
@@ -0,0 +1,133 @@
+//! Just enough CBOR (RFC 8949) for the provisioner's reports and manifests.
+//!
+//! Only definite lengths, and arguments up to 32 bits.
+
+use heapless::Vec;
+
+const UNSIGNED: u8 = 0;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const SIMPLE: u8 = 7;
+
+const FALSE: u8 = 20;
+const TRUE: u8 = 21;
+
+/// The encoding did not fit its buffer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Overflow;
+
+fn push_header<const N: usize>(out: &mut Vec<u8, N>, major: u8, argument: u32) -> Result<(), Overflow> {
+    let major = major << 5;
+    let result = match argument {
+        0..=23 => out.push(major | argument as u8).map_err(drop),
+        0x18..=0xff => out.extend_from_slice(&[major | 24, argument as u8]),
+        0x100..=0xffff => out.push(major | 25).map_err(drop)
+            .and_then(|_| out.extend_from_slice(&(argument as u16).to_be_bytes())),
+        _ => out.push(major | 26).map_err(drop)
+            .and_then(|_| out.extend_from_slice(&argument.to_be_bytes())),
+    };
+    result.map_err(|_| Overflow)
+}
+
+pub fn push_unsigned<const N: usize>(out: &mut Vec<u8, N>, value: u32) -> Result<(), Overflow> {
+    push_header(out, UNSIGNED, value)
+}
+
+pub fn push_bool<const N: usize>(out: &mut Vec<u8, N>, value: bool) -> Result<(), Overflow> {
+    push_header(out, SIMPLE, if value { TRUE } else { FALSE } as u32)
+}
+
+pub fn push_bytes<const N: usize>(out: &mut Vec<u8, N>, value: &[u8]) -> Result<(), Overflow> {
+    push_header(out, BYTES, value.len() as u32)?;
+    out.extend_from_slice(value).map_err(|_| Overflow)
+}
+
+pub fn push_text<const N: usize>(out: &mut Vec<u8, N>, value: &str) -> Result<(), Overflow> {
+    push_header(out, TEXT, value.len() as u32)?;
+    out.extend_from_slice(value.as_bytes()).map_err(|_| Overflow)
+}
+
+/// Starts an array, the `length` items have to follow.
+pub fn push_array<const N: usize>(out: &mut Vec<u8, N>, length: usize) -> Result<(), Overflow> {
+    push_header(out, ARRAY, length as u32)
+}
+
+/// Starts a map, the `length` key value pairs have to follow.
+pub fn push_map<const N: usize>(out: &mut Vec<u8, N>, length: usize) -> Result<(), Overflow> {
+    push_header(out, MAP, length as u32)
+}
+
+/// The input is not (supported) CBOR, or not what was expected.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Malformed;
+
+/// Reads CBOR items off the front of its input.
+pub struct Decoder<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Malformed> {
+        if self.input.len() < length {
+            return Err(Malformed);
+        }
+        let (taken, rest) = self.input.split_at(length);
+        self.input = rest;
+        Ok(taken)
+    }
+
+    fn header(&mut self, major: u8) -> Result<u32, Malformed> {
+        let initial = self.take(1)?[0];
+        if initial >> 5 != major {
+            return Err(Malformed);
+        }
+        match initial & 0x1f {
+            argument @ 0..=23 => Ok(argument as u32),
+            24 => Ok(self.take(1)?[0] as u32),
+            25 => {
+                let bytes = self.take(2)?;
+                Ok(u16::from_be_bytes([bytes[0], bytes[1]]) as u32)
+            }
+            26 => {
+                let bytes = self.take(4)?;
+                Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+            // 64 bit arguments and indefinite lengths
+            _ => Err(Malformed),
+        }
+    }
+
+    pub fn unsigned(&mut self) -> Result<u32, Malformed> {
+        self.header(UNSIGNED)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], Malformed> {
+        let length = self.header(BYTES)? as usize;
+        self.take(length)
+    }
+
+    pub fn text(&mut self) -> Result<&'a str, Malformed> {
+        let length = self.header(TEXT)? as usize;
+        core::str::from_utf8(self.take(length)?).map_err(|_| Malformed)
+    }
+
+    /// Starts an array, returns the number of items that follow.
+    pub fn array(&mut self) -> Result<usize, Malformed> {
+        self.header(ARRAY).map(|length| length as usize)
+    }
+
+    /// Starts a map, returns the number of key value pairs that follow.
+    pub fn map(&mut self) -> Result<usize, Malformed> {
+        self.header(MAP).map(|length| length as usize)
+    }
+}
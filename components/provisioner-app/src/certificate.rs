@@ -0,0 +1,172 @@
+//! Checking attestation certificates before they are saved.
+//!
+//! A certificate is only saved if it certifies the attestation key of its slot,
+//! and, once the T1 intermediate public key is saved, if that key signed it.
+//!
+//! | status | meaning                                                        |
+//! |--------|----------------------------------------------------------------|
+//! | `6A80` | not a DER certificate, or no attestation key was generated yet |
+//! | `6985` | the certificate is for a different public key                  |
+//! | `6300` | the certificate is not signed by the T1 intermediate           |
+
+use core::convert::TryFrom;
+
+use defmt::info;
+use littlefs2::path::PathBuf;
+use trussed::key::{Key, Kind as KeyKind};
+use trussed::store::{self, Store};
+use trussed::types::{LfsStorage, Location, Message};
+use trussed::{client, Client as TrussedClient};
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::app::Result as ResponseResult;
+
+use crate::chain::clear_intermediates;
+use crate::der::{self, Malformed};
+use crate::{public_key, Provisioner, Reboot, FILENAME_T1_PUBLIC};
+
+/// The parts of an X.509 certificate that are checked.
+pub struct Certificate<'a> {
+    /// The encoded `TBSCertificate`, which is what the issuer signed
+    pub tbs: &'a [u8],
+    /// The encoded `SubjectPublicKeyInfo`
+    pub public_key_info: &'a [u8],
+    /// Contents of the outer `AlgorithmIdentifier`
+    pub signature_algorithm: &'a [u8],
+    pub signature: &'a [u8],
+}
+
+impl<'a> Certificate<'a> {
+    pub fn parse(encoded: &'a [u8]) -> Result<Self, Malformed> {
+        let (certificate, rest) = der::expect(encoded, der::SEQUENCE)?;
+        if !rest.is_empty() {
+            return Err(Malformed);
+        }
+
+        let (tbs, rest) = der::expect(certificate.value, der::SEQUENCE)?;
+        let (signature_algorithm, rest) = der::expect(rest, der::SEQUENCE)?;
+        let (signature, rest) = der::expect(rest, der::BIT_STRING)?;
+        if !rest.is_empty() {
+            return Err(Malformed);
+        }
+        let signature = match signature.value.split_first() {
+            Some((0, signature)) => signature,
+            _ => return Err(Malformed),
+        };
+
+        // the version is optional
+        let mut fields = tbs.value;
+        let (first, rest) = der::take(fields)?;
+        if first.tag == der::CONTEXT_0 {
+            fields = rest;
+        }
+        // serial number, signature, issuer, validity, subject
+        for tag in [der::INTEGER, der::SEQUENCE, der::SEQUENCE, der::SEQUENCE, der::SEQUENCE] {
+            fields = der::expect(fields, tag)?.1;
+        }
+        let (public_key_info, _) = der::expect(fields, der::SEQUENCE)?;
+
+        Ok(Self {
+            tbs: tbs.encoded,
+            public_key_info: public_key_info.encoded,
+            signature_algorithm: signature_algorithm.value,
+            signature,
+        })
+    }
+
+    /// The subject's public key, as it is encoded in the `SubjectPublicKeyInfo`.
+    pub fn public_key(&self) -> Result<&'a [u8], Malformed> {
+        let (info, _) = der::expect(self.public_key_info, der::SEQUENCE)?;
+        let (_algorithm, rest) = der::expect(info.value, der::SEQUENCE)?;
+        let (public_key, _) = der::expect(rest, der::BIT_STRING)?;
+        match public_key.value.split_first() {
+            Some((0, public_key)) => Ok(public_key),
+            _ => Err(Malformed),
+        }
+    }
+
+    fn is_signed_with_ed25519(&self) -> bool {
+        match der::expect(self.signature_algorithm, der::OBJECT_IDENTIFIER) {
+            Ok((oid, rest)) => oid.value == der::OID_ED25519 && rest.is_empty(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
+where S: Store,
+      FS: 'static + LfsStorage,
+      T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
+{
+    /// Saves the certificate in the command data to `cert_path`,
+    /// if it is valid for the attestation key of the given kind at `secret_path`.
+    pub(crate) fn save_certificate(
+        &mut self,
+        kind: KeyKind,
+        secret_path: &[u8],
+        cert_path: &[u8],
+        encoded: &[u8],
+    ) -> ResponseResult {
+        self.check_certificate(kind, secret_path, encoded)?;
+
+        info!("saving certificate, {} bytes", encoded.len());
+        store::store(self.store, Location::Internal, &PathBuf::from(cert_path), encoded)
+            .map_err(|_| Status::NotEnoughMemory)?;
+        // the old intermediates need not have issued the new leaf
+        clear_intermediates(self.store, cert_path);
+        Ok(())
+    }
+
+    /// Checks the certificate is valid for the attestation key of the given kind at `secret_path`.
+    pub(crate) fn check_certificate(&mut self, kind: KeyKind, secret_path: &[u8], encoded: &[u8]) -> ResponseResult {
+        let certificate = Certificate::parse(encoded).map_err(|_| Status::IncorrectDataParameter)?;
+
+        let seed = self.load_secret(secret_path)?;
+        let public_key_info = der::public_key_info(kind, &public_key(kind, &seed))
+            .map_err(|_| Status::UnspecifiedCheckingError)?;
+        if certificate.public_key_info != &public_key_info[..] {
+            info!("certificate is for a different key");
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+
+        self.verify_issuer(&certificate)
+    }
+
+    /// Verifies the certificate was signed by the T1 intermediate, if its public key was saved.
+    fn verify_issuer(&mut self, certificate: &Certificate) -> ResponseResult {
+        let public_key = match self.t1_public_key()? {
+            Some(public_key) => public_key,
+            None => return Ok(()),
+        };
+
+        if !certificate.is_signed_with_ed25519() {
+            info!("certificate can't be signed by the T1 intermediate");
+            return Err(Status::VerificationFailed);
+        }
+        let signature = <[u8; 64]>::try_from(certificate.signature)
+            .map_err(|_| Status::VerificationFailed)?;
+        public_key.verify(certificate.tbs, &salty::Signature::from(&signature))
+            .map_err(|_| {
+                info!("certificate is not signed by the T1 intermediate");
+                Status::VerificationFailed
+            })
+    }
+
+    /// The T1 intermediate public key, if it was saved.
+    pub(crate) fn t1_public_key(&self) -> Result<Option<salty::PublicKey>, Status> {
+        let path = PathBuf::from(FILENAME_T1_PUBLIC);
+        if !path.exists(&self.store.ifs()) {
+            return Ok(None);
+        }
+
+        let serialized: Message = store::read(self.store, Location::Internal, &path)
+            .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+        let key = Key::try_deserialize(&serialized).map_err(|_| Status::UnspecifiedCheckingError)?;
+        let public_key = <[u8; 32]>::try_from(key.material.as_slice())
+            .map_err(|_| Status::UnspecifiedCheckingError)?;
+        salty::PublicKey::try_from(&public_key)
+            .map(Some)
+            .map_err(|_| Status::UnspecifiedCheckingError)
+    }
+}
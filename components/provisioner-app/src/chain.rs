@@ -0,0 +1,95 @@
+//! Certificate chains (x5c) for the attestation keys.
+//!
+//! The leaf stays at `/attn/x5c/0N`, where the authenticators expect it,
+//! intermediates are appended in order as `/attn/x5c/0N.1`, `/attn/x5c/0N.2`, ...
+//! Saving a new leaf certificate drops the intermediates of its slot.
+//!
+//! Slots are selected by P1: 1 for P-256, 2 for Ed25519, 3 for X25519.
+
+use defmt::info;
+use heapless::Vec;
+use littlefs2::io::Read;
+use littlefs2::path::PathBuf;
+use trussed::store::{self, Store};
+use trussed::types::{LfsStorage, Location};
+use trussed::{client, Client as TrussedClient};
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::app::Result as ResponseResult;
+use apdu_dispatch::{Command, response};
+
+use crate::certificate::Certificate;
+use crate::{attestation_slot, Provisioner, Reboot};
+
+/// Intermediates per slot, the leaf not counted.
+const MAX_INTERMEDIATES: u8 = 4;
+
+fn leaf_path(slot: u8) -> Result<&'static [u8], Status> {
+    attestation_slot(slot)
+        .map(|(_, _, cert)| cert)
+        .ok_or(Status::IncorrectP1OrP2Parameter)
+}
+
+/// The path of the certificate at `index` in the chain, 0 being the leaf.
+fn chain_path(leaf: &[u8], index: u8) -> PathBuf {
+    let mut path: Vec<u8, 16> = Vec::from_slice(leaf).unwrap();
+    if index > 0 {
+        path.extend_from_slice(&[b'.', b'0' + index]).unwrap();
+    }
+    PathBuf::from(path.as_slice())
+}
+
+/// Removes the intermediates following the leaf at `leaf`.
+pub(crate) fn clear_intermediates<S: Store>(store: S, leaf: &[u8]) {
+    for index in 1..=MAX_INTERMEDIATES {
+        store.ifs().remove(&chain_path(leaf, index)).ok();
+    }
+}
+
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
+where S: Store,
+      FS: 'static + LfsStorage,
+      T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
+{
+    /// Appends the certificate in the command data to the chain of the slot in P1.
+    pub(crate) fn append_intermediate(&mut self, command: &Command) -> ResponseResult {
+        let leaf = leaf_path(command.p1)?;
+        Certificate::parse(command.data()).map_err(|_| Status::IncorrectDataParameter)?;
+
+        if !chain_path(leaf, 0).exists(&self.store.ifs()) {
+            info!("no leaf certificate to append to");
+            return Err(Status::NotFound);
+        }
+        let index = (1..=MAX_INTERMEDIATES)
+            .find(|index| !chain_path(leaf, *index).exists(&self.store.ifs()))
+            .ok_or(Status::NotEnoughMemory)?;
+
+        info!("appending intermediate {}, {} bytes", index, command.data().len());
+        store::store(self.store, Location::Internal, &chain_path(leaf, index), command.data())
+            .map_err(|_| Status::NotEnoughMemory)
+    }
+
+    /// Replies with the chain of the slot in P1, the DER certificates concatenated, leaf first.
+    pub(crate) fn read_chain(&mut self, command: &Command, reply: &mut response::Data) -> ResponseResult {
+        let leaf = leaf_path(command.p1)?;
+        for index in 0..=MAX_INTERMEDIATES {
+            let path = chain_path(leaf, index);
+            let metadata = match self.store.ifs().metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) if index == 0 => return Err(Status::NotFound),
+                Err(_) => break,
+            };
+
+            let start = reply.len();
+            if start + metadata.len() > reply.capacity() {
+                return Err(Status::NotEnoughMemory);
+            }
+            reply.resize_default(start + metadata.len()).ok();
+            self.store.ifs()
+                .open_file_and_then(&path, |file| file.read(&mut reply[start..]))
+                .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! Certificate signing requests (PKCS #10) for freshly generated attestation keys.
+//!
+//! The subject carries the device UUID as serial number, so a standard CA
+//! can issue the attestation certificate without further input, which is then
+//! stored with the `Save*AttestationCertificate` instructions.
+
+use core::convert::TryFrom;
+
+use defmt::info;
+use heapless::Vec;
+use trussed::key::Kind as KeyKind;
+use trussed::store::Store;
+use trussed::types::LfsStorage;
+use trussed::{client, Client as TrussedClient};
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::app::Result as ResponseResult;
+use apdu_dispatch::{Command, response};
+
+use crate::der::{self, Overflow};
+use crate::{Provisioner, Reboot, FILENAME_ED255_SECRET, FILENAME_P256_SECRET};
+
+const ORGANIZATION: &[u8] = b"SoloKeys";
+/// Required by FIDO for attestation certificates.
+const ORGANIZATIONAL_UNIT: &[u8] = b"Authenticator Attestation";
+const COMMON_NAME: &[u8] = b"Solo 2";
+
+/// Which key to generate and request a certificate for, P1 of `GenerateCsr`.
+///
+/// Same numbering as the key slots, X25519 keys can't sign their request.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Algorithm {
+    P256 = 0x01,
+    Ed255 = 0x02,
+}
+
+impl TryFrom<u8> for Algorithm {
+    type Error = ();
+    fn try_from(p1: u8) -> Result<Self, Self::Error> {
+        match p1 {
+            0x01 => Ok(Algorithm::P256),
+            0x02 => Ok(Algorithm::Ed255),
+            _ => Err(()),
+        }
+    }
+}
+
+fn hex(bytes: &[u8; 16]) -> [u8; 32] {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut hex = [0u8; 32];
+    for (i, byte) in bytes.iter().enumerate() {
+        hex[2 * i] = DIGITS[(byte >> 4) as usize];
+        hex[2 * i + 1] = DIGITS[(byte & 0xf) as usize];
+    }
+    hex
+}
+
+fn push_name_attribute<const N: usize>(name: &mut Vec<u8, N>, oid: &[u8], tag: u8, value: &[u8]) -> Result<(), Overflow> {
+    let mut attribute: Vec<u8, 64> = Vec::new();
+    der::push(&mut attribute, der::OBJECT_IDENTIFIER, oid)?;
+    der::push(&mut attribute, tag, value)?;
+    let mut sequence: Vec<u8, 66> = Vec::new();
+    der::push(&mut sequence, der::SEQUENCE, &attribute)?;
+    der::push(name, der::SET, &sequence)
+}
+
+/// O=SoloKeys, OU=Authenticator Attestation, CN=Solo 2, serialNumber=<UUID in hex>
+pub fn subject(uuid: &[u8; 16]) -> Result<Vec<u8, 192>, Overflow> {
+    let mut name: Vec<u8, 189> = Vec::new();
+    push_name_attribute(&mut name, der::OID_ORGANIZATION, der::UTF8_STRING, ORGANIZATION)?;
+    push_name_attribute(&mut name, der::OID_ORGANIZATIONAL_UNIT, der::UTF8_STRING, ORGANIZATIONAL_UNIT)?;
+    push_name_attribute(&mut name, der::OID_COMMON_NAME, der::UTF8_STRING, COMMON_NAME)?;
+    push_name_attribute(&mut name, der::OID_SERIAL_NUMBER, der::PRINTABLE_STRING, &hex(uuid))?;
+    let mut subject = Vec::new();
+    der::push(&mut subject, der::SEQUENCE, &name)?;
+    Ok(subject)
+}
+
+/// `AlgorithmIdentifier` of the signature.
+pub fn signature_algorithm(algorithm: Algorithm) -> Result<Vec<u8, 16>, Overflow> {
+    let mut identifier: Vec<u8, 13> = Vec::new();
+    der::push(&mut identifier, der::OBJECT_IDENTIFIER, match algorithm {
+        Algorithm::P256 => der::OID_ECDSA_WITH_SHA256,
+        Algorithm::Ed255 => der::OID_ED25519,
+    })?;
+    let mut sequence = Vec::new();
+    der::push(&mut sequence, der::SEQUENCE, &identifier)?;
+    Ok(sequence)
+}
+
+/// `CertificationRequestInfo`, the part that gets signed.
+pub fn request_info(subject: &[u8], public_key_info: &[u8]) -> Result<Vec<u8, 320>, Overflow> {
+    let mut info: Vec<u8, 316> = Vec::new();
+    // version 1 (encoded as 0)
+    der::push(&mut info, der::INTEGER, &[0])?;
+    info.extend_from_slice(subject).map_err(|_| Overflow)?;
+    info.extend_from_slice(public_key_info).map_err(|_| Overflow)?;
+    // no attributes
+    der::push(&mut info, der::CONTEXT_0, &[])?;
+    let mut sequence = Vec::new();
+    der::push(&mut sequence, der::SEQUENCE, &info)?;
+    Ok(sequence)
+}
+
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
+where S: Store,
+      FS: 'static + LfsStorage,
+      T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
+{
+    /// Generates the attestation key selected by P1 (replacing any existing one),
+    /// and replies with a certificate request signed by it.
+    pub(crate) fn generate_csr(&mut self, command: &Command, reply: &mut response::Data) -> ResponseResult {
+        let algorithm = Algorithm::try_from(command.p1).map_err(|_| Status::IncorrectP1OrP2Parameter)?;
+        info!("GenerateCsr");
+
+        let subject = subject(&self.uuid).map_err(|_| Status::UnspecifiedCheckingError)?;
+
+        let mut signature: Vec<u8, 72> = Vec::new();
+        let info = match algorithm {
+            Algorithm::P256 => {
+                let seed = self.generate_secret(KeyKind::P256, FILENAME_P256_SECRET)?;
+                let keypair = nisty::Keypair::generate_patiently(&seed);
+                let public_key_info = der::public_key_info(KeyKind::P256, keypair.public.as_bytes())
+                    .map_err(|_| Status::UnspecifiedCheckingError)?;
+                let info = request_info(&subject, &public_key_info)
+                    .map_err(|_| Status::UnspecifiedCheckingError)?;
+                let raw = keypair.sign(&info).to_bytes();
+                signature = der::ecdsa_signature(&raw).map_err(|_| Status::UnspecifiedCheckingError)?;
+                info
+            }
+            Algorithm::Ed255 => {
+                let seed = self.generate_secret(KeyKind::Ed255, FILENAME_ED255_SECRET)?;
+                let keypair = salty::Keypair::from(&seed);
+                let public_key_info = der::public_key_info(KeyKind::Ed255, keypair.public.as_bytes())
+                    .map_err(|_| Status::UnspecifiedCheckingError)?;
+                let info = request_info(&subject, &public_key_info)
+                    .map_err(|_| Status::UnspecifiedCheckingError)?;
+                signature.extend_from_slice(&keypair.sign(&info).to_bytes()).ok();
+                info
+            }
+        };
+
+        let mut request: Vec<u8, 512> = Vec::new();
+        request.extend_from_slice(&info).ok();
+        request.extend_from_slice(&signature_algorithm(algorithm).map_err(|_| Status::UnspecifiedCheckingError)?).ok();
+        der::push_bit_string(&mut request, &signature).map_err(|_| Status::UnspecifiedCheckingError)?;
+
+        der::push(reply, der::SEQUENCE, &request).map_err(|_| Status::UnspecifiedCheckingError)
+    }
+}
@@ -0,0 +1,151 @@
+//! Just enough DER for attestation certificates and certificate requests.
+
+use heapless::Vec;
+use trussed::key::Kind as KeyKind;
+
+pub const INTEGER: u8 = 0x02;
+pub const BIT_STRING: u8 = 0x03;
+pub const OBJECT_IDENTIFIER: u8 = 0x06;
+pub const UTF8_STRING: u8 = 0x0c;
+pub const PRINTABLE_STRING: u8 = 0x13;
+pub const SEQUENCE: u8 = 0x30;
+pub const SET: u8 = 0x31;
+/// `[0]`, constructed
+pub const CONTEXT_0: u8 = 0xa0;
+
+// object identifiers, DER encoded (without tag and length)
+pub const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+pub const OID_PRIME256V1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+pub const OID_ECDSA_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+pub const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+pub const OID_X25519: &[u8] = &[0x2b, 0x65, 0x6e];
+pub const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+pub const OID_SERIAL_NUMBER: &[u8] = &[0x55, 0x04, 0x05];
+pub const OID_ORGANIZATION: &[u8] = &[0x55, 0x04, 0x0a];
+pub const OID_ORGANIZATIONAL_UNIT: &[u8] = &[0x55, 0x04, 0x0b];
+
+/// The encoding did not fit its buffer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Overflow;
+
+/// The input is not DER, or not what was expected.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Malformed;
+
+/// A parsed TLV.
+#[derive(Copy, Clone, Debug)]
+pub struct Tlv<'a> {
+    pub tag: u8,
+    pub value: &'a [u8],
+    /// The entire TLV, as it was encoded
+    pub encoded: &'a [u8],
+}
+
+/// Parses the TLV at the start of `input`, and returns it with the remaining input.
+///
+/// Only single byte tags and lengths up to 0xffff are supported,
+/// non-minimal lengths are refused as DER demands.
+pub fn take(input: &[u8]) -> Result<(Tlv<'_>, &[u8]), Malformed> {
+    let (&tag, rest) = input.split_first().ok_or(Malformed)?;
+    let (&first, rest) = rest.split_first().ok_or(Malformed)?;
+    let (length, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        0x81 => match rest.split_first() {
+            Some((&length, rest)) if length >= 0x80 => (length as usize, rest),
+            _ => return Err(Malformed),
+        },
+        0x82 if rest.len() >= 2 => {
+            let length = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+            if length < 0x100 {
+                return Err(Malformed);
+            }
+            (length, &rest[2..])
+        }
+        _ => return Err(Malformed),
+    };
+    if rest.len() < length {
+        return Err(Malformed);
+    }
+    let header = input.len() - rest.len();
+    let (value, rest) = rest.split_at(length);
+    Ok((Tlv { tag, value, encoded: &input[..header + length] }, rest))
+}
+
+/// Like `take`, but the TLV must have the given tag.
+pub fn expect(input: &[u8], tag: u8) -> Result<(Tlv<'_>, &[u8]), Malformed> {
+    let (tlv, rest) = take(input)?;
+    if tlv.tag != tag {
+        return Err(Malformed);
+    }
+    Ok((tlv, rest))
+}
+
+/// Appends a TLV to `out`.
+pub fn push<const N: usize>(out: &mut Vec<u8, N>, tag: u8, value: &[u8]) -> Result<(), Overflow> {
+    out.push(tag).map_err(|_| Overflow)?;
+    let length = value.len();
+    let length_bytes = (length as u32).to_be_bytes();
+    match length {
+        0..=0x7f => out.push(length as u8).map_err(|_| Overflow)?,
+        0x80..=0xff => out.extend_from_slice(&[0x81, length_bytes[3]]).map_err(|_| Overflow)?,
+        _ => out.extend_from_slice(&[0x82, length_bytes[2], length_bytes[3]]).map_err(|_| Overflow)?,
+    }
+    out.extend_from_slice(value).map_err(|_| Overflow)
+}
+
+/// Appends an unsigned big endian integer, such as an ECDSA signature's r or s.
+pub fn push_unsigned<const N: usize>(out: &mut Vec<u8, N>, integer: &[u8]) -> Result<(), Overflow> {
+    let first = integer.iter().position(|byte| *byte != 0).unwrap_or(integer.len() - 1);
+    let integer = &integer[first..];
+    let mut value: Vec<u8, 33> = Vec::new();
+    if integer[0] & 0x80 != 0 {
+        // would be negative otherwise
+        value.push(0).map_err(|_| Overflow)?;
+    }
+    value.extend_from_slice(integer).map_err(|_| Overflow)?;
+    push(out, INTEGER, &value)
+}
+
+/// Appends a bit string without unused bits.
+pub fn push_bit_string<const N: usize>(out: &mut Vec<u8, N>, bits: &[u8]) -> Result<(), Overflow> {
+    let mut value: Vec<u8, 128> = Vec::new();
+    value.push(0).map_err(|_| Overflow)?;
+    value.extend_from_slice(bits).map_err(|_| Overflow)?;
+    push(out, BIT_STRING, &value)
+}
+
+/// Encodes a raw (r || s) P-256 signature as `Ecdsa-Sig-Value`.
+pub fn ecdsa_signature(raw: &[u8; 64]) -> Result<Vec<u8, 72>, Overflow> {
+    let mut integers: Vec<u8, 70> = Vec::new();
+    push_unsigned(&mut integers, &raw[..32])?;
+    push_unsigned(&mut integers, &raw[32..])?;
+    let mut signature = Vec::new();
+    push(&mut signature, SEQUENCE, &integers)?;
+    Ok(signature)
+}
+
+/// `SubjectPublicKeyInfo` of an attestation key: an uncompressed P-256 point
+/// (without the leading 0x04), or an Ed25519 or X25519 key.
+pub fn public_key_info(kind: KeyKind, public_key: &[u8]) -> Result<Vec<u8, 96>, Overflow> {
+    let mut identifier: Vec<u8, 24> = Vec::new();
+    let mut key: Vec<u8, 65> = Vec::new();
+    match kind {
+        KeyKind::P256 => {
+            push(&mut identifier, OBJECT_IDENTIFIER, OID_EC_PUBLIC_KEY)?;
+            push(&mut identifier, OBJECT_IDENTIFIER, OID_PRIME256V1)?;
+            key.push(0x04).map_err(|_| Overflow)?;
+        }
+        KeyKind::Ed255 => push(&mut identifier, OBJECT_IDENTIFIER, OID_ED25519)?,
+        KeyKind::X255 => push(&mut identifier, OBJECT_IDENTIFIER, OID_X25519)?,
+        // not an attestation key
+        _ => return Err(Overflow),
+    }
+    key.extend_from_slice(public_key).map_err(|_| Overflow)?;
+
+    let mut info: Vec<u8, 93> = Vec::new();
+    push(&mut info, SEQUENCE, &identifier)?;
+    push_bit_string(&mut info, &key)?;
+    let mut sequence = Vec::new();
+    push(&mut sequence, SEQUENCE, &info)?;
+    Ok(sequence)
+}
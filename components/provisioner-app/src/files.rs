@@ -0,0 +1,151 @@
+//! Inspecting the internal filesystem: reading files, listing directories, deleting.
+//!
+//! Paths are passed as command data. Secret keys never leave the device:
+//! anything below `/attn/sec/`, and any file that is a Trussed key marked
+//! `SENSITIVE`, is refused by `ReadFile`.
+
+use defmt::info;
+use littlefs2::io::{Read, Seek, SeekFrom};
+use littlefs2::path::PathBuf;
+use trussed::key::{Flags, Key};
+use trussed::store::Store;
+use trussed::types::LfsStorage;
+use trussed::{client, Client as TrussedClient};
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::app::Result as ResponseResult;
+use apdu_dispatch::{Command, response};
+
+use crate::{Provisioner, Reboot};
+
+const SECRET_DIRECTORY: &[u8] = b"/attn/sec/";
+
+/// Serialized Trussed keys are a four byte header and at most this much key material.
+const MAX_SERIALIZED_KEY_LENGTH: usize = 4 + 128;
+
+/// Entry types in a directory listing.
+const ENTRY_FILE: u8 = 0;
+const ENTRY_DIRECTORY: u8 = 1;
+
+/// Parses an absolute path from command data, refusing what `PathBuf::from` would panic on.
+///
+/// Only the root may end in `/`, and `.`, `..` and empty components are refused:
+/// littlefs resolves them, which would get around the checks on the path's prefix.
+pub(crate) fn path_from(data: &[u8]) -> Result<PathBuf, Status> {
+    let valid = data.first() == Some(&b'/')
+        && data.len() <= littlefs2::consts::PATH_MAX
+        && data.is_ascii()
+        && !data.contains(&0)
+        && (data == b"/" || data[1..].split(|byte| *byte == b'/')
+            .all(|component| !matches!(component, b"" | b"." | b"..")));
+    if valid {
+        Ok(PathBuf::from(data))
+    } else {
+        Err(Status::IncorrectDataParameter)
+    }
+}
+
+/// Whether the contents are secret: written below `/attn/sec/`, or a Trussed key marked `SENSITIVE`.
+pub(crate) fn is_secret(path: &[u8], contents: &[u8]) -> bool {
+    path.starts_with(SECRET_DIRECTORY) || is_sensitive(contents)
+}
+
+fn is_sensitive(contents: &[u8]) -> bool {
+    match Key::try_deserialize(contents) {
+        Ok(key) => key.flags.contains(Flags::SENSITIVE),
+        Err(_) => false,
+    }
+}
+
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
+where S: Store,
+      FS: 'static + LfsStorage,
+      T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
+{
+    /// Reads (part of) the file at the path in the command data.
+    ///
+    /// P1/P2 is the offset, the response is as long as the expected length (Le) allows,
+    /// a shorter response than asked for means the end of the file was reached.
+    pub(crate) fn read_file(&mut self, command: &Command, reply: &mut response::Data) -> ResponseResult {
+        let path = path_from(command.data())?;
+        let offset = u16::from_be_bytes([command.p1, command.p2]) as usize;
+
+        if command.data().starts_with(SECRET_DIRECTORY) || self.is_sensitive_key(&path)? {
+            info!("refusing to read secret");
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+
+        let metadata = self.store.ifs().metadata(&path).map_err(|_| Status::NotFound)?;
+        if metadata.is_dir() {
+            return Err(Status::IncorrectDataParameter);
+        }
+        if offset > metadata.len() {
+            return Err(Status::IncorrectP1OrP2Parameter);
+        }
+
+        let available = reply.capacity() - reply.len();
+        let length = command.expected().min(available).min(metadata.len() - offset);
+        let start = reply.len();
+        reply.resize_default(start + length).ok();
+        let read = self.store.ifs().open_file_and_then(&path, |file| {
+            file.seek(SeekFrom::Start(offset as u32))?;
+            file.read(&mut reply[start..])
+        }).map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+        reply.truncate(start + read);
+        Ok(())
+    }
+
+    /// Lists the directory at the path in the command data.
+    ///
+    /// Each entry is its type (0 file, 1 directory), its size as u32 (big endian),
+    /// the length of its name, and the name. P1/P2 is the number of entries to skip,
+    /// entries are listed as long as they fit the expected length (Le).
+    pub(crate) fn list_directory(&mut self, command: &Command, reply: &mut response::Data) -> ResponseResult {
+        let path = path_from(command.data())?;
+        let skip = u16::from_be_bytes([command.p1, command.p2]) as usize;
+        let limit = reply.len() + command.expected().min(reply.capacity() - reply.len());
+
+        self.store.ifs().read_dir_and_then(&path, |entries| {
+            // skip "." and ".."
+            for entry in entries.skip(2 + skip) {
+                let entry = entry?;
+                let name: &str = entry.file_name().as_ref();
+                let metadata = entry.metadata();
+                if reply.len() + 6 + name.len() > limit {
+                    break;
+                }
+                reply.push(if metadata.is_dir() { ENTRY_DIRECTORY } else { ENTRY_FILE }).ok();
+                reply.extend_from_slice(&(metadata.len() as u32).to_be_bytes()).ok();
+                reply.push(name.len() as u8).ok();
+                reply.extend_from_slice(name.as_bytes()).ok();
+            }
+            Ok(())
+        }).map_err(|_| Status::NotFound)
+    }
+
+    /// Deletes the file, or empty directory, at the path in the command data.
+    pub(crate) fn delete_file(&mut self, command: &Command) -> ResponseResult {
+        let path = path_from(command.data())?;
+        let metadata = self.store.ifs().metadata(&path).map_err(|_| Status::NotFound)?;
+        let removed = if metadata.is_dir() {
+            self.store.ifs().remove_dir(&path)
+        } else {
+            self.store.ifs().remove(&path)
+        };
+        removed.map_err(|_| Status::ConditionsOfUseNotSatisfied)
+    }
+
+    fn is_sensitive_key(&self, path: &PathBuf) -> Result<bool, Status> {
+        let metadata = self.store.ifs().metadata(path).map_err(|_| Status::NotFound)?;
+        if metadata.is_dir() || metadata.len() > MAX_SERIALIZED_KEY_LENGTH {
+            return Ok(false);
+        }
+
+        let mut contents = [0u8; MAX_SERIALIZED_KEY_LENGTH];
+        let length = self.store.ifs()
+            .open_file_and_then(path, |file| file.read(&mut contents))
+            .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+        Ok(is_sensitive(&contents[..length]))
+    }
+}
@@ -0,0 +1,157 @@
+//! Importing attestation keys generated elsewhere, e.g. in an HSM, wrapped to the device.
+//!
+//! The key is wrapped to the X25519 attestation key (slot 3), which has to be generated first.
+//! The host agrees on a secret with an ephemeral X25519 key and encrypts the 32 byte seed with
+//! an AEAD, the command data being
+//!
+//! ```text
+//! ephemeral public key (32) || nonce (12) || encrypted seed (32) || tag (16)
+//! ```
+//!
+//! The AEAD key is `SHA-256(shared secret || ephemeral public key || device public key)`,
+//! the associated data is the slot followed by the UUID, so a wrapped key can only be imported
+//! into the slot and device it was wrapped for. P1 is the slot (1 or 2, as for `GenerateCsr`),
+//! P2 the AEAD: 0 for ChaCha20-Poly1305, 1 for AES-256-GCM.
+//!
+//! Unlike asked for initially, the key is not unwrapped in Trussed: Trussed only unwraps its
+//! own ChaCha8-Poly1305 format, into keys under fresh random ids rather than the slots.
+//! So it is unwrapped here, the same way the provisioner generates keys outside of Trussed.
+//! The seeds, the shared secret and the AEAD key are zeroized once done with, and so are the
+//! salty X25519 secret key and shared secret and the AEAD instance with its key schedule,
+//! whatever their crates do on drop (see `wipe`). The SHA-256 state deriving the AEAD key
+//! is not. Imported keys are marked sensitive, but not local. The reply is the public key,
+//! as for generated keys.
+//!
+//! | status | meaning                                                   |
+//! |--------|-----------------------------------------------------------|
+//! | `6985` | there is no X25519 attestation key to unwrap with         |
+//! | `6300` | the key was not wrapped for this slot and device          |
+
+use core::convert::TryFrom;
+use core::mem::{size_of, ManuallyDrop};
+
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::aead::{AeadInPlace, NewAead};
+use defmt::info;
+use sha2::digest::generic_array::GenericArray;
+use sha2::digest::FixedOutput;
+use sha2::{Digest, Sha256};
+use trussed::key::Flags;
+use trussed::store::Store;
+use trussed::types::LfsStorage;
+use trussed::{client, Client as TrussedClient};
+use zeroize::{Zeroize, Zeroizing};
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::app::Result as ResponseResult;
+use apdu_dispatch::{Command, response};
+
+use crate::{attestation_slot, public_key, Provisioner, Reboot, FILENAME_X255_SECRET};
+
+const WRAPPED_LENGTH: usize = 32 + 12 + 32 + 16;
+
+#[derive(Copy, Clone)]
+enum Aead {
+    ChaCha20Poly1305 = 0x00,
+    Aes256Gcm = 0x01,
+}
+
+impl TryFrom<u8> for Aead {
+    type Error = Status;
+
+    fn try_from(p2: u8) -> Result<Self, Status> {
+        match p2 {
+            0x00 => Ok(Aead::ChaCha20Poly1305),
+            0x01 => Ok(Aead::Aes256Gcm),
+            _ => Err(Status::IncorrectP1OrP2Parameter),
+        }
+    }
+}
+
+impl Aead {
+    fn decrypt(self, key: &[u8; 32], nonce: &[u8], aad: &[u8], buffer: &mut [u8], tag: &[u8]) -> Result<(), ()> {
+        match self {
+            Aead::ChaCha20Poly1305 => {
+                let mut cipher = ManuallyDrop::new(ChaCha20Poly1305::new(key.into()));
+                let result = cipher.decrypt_in_place_detached(nonce.into(), aad, buffer, tag.into());
+                unsafe { wipe(&mut cipher) };
+                result
+            }
+            Aead::Aes256Gcm => {
+                let mut cipher = ManuallyDrop::new(Aes256Gcm::new(key.into()));
+                let result = cipher.decrypt_in_place_detached(nonce.into(), aad, buffer, tag.into());
+                unsafe { wipe(&mut cipher) };
+                result
+            }
+        }.map_err(drop)
+    }
+}
+
+/// Zeroizes `value` in place, for key material in types that don't zeroize themselves.
+///
+/// # Safety
+///
+/// The zeroized value may not be valid, so it must not be used afterwards
+/// (`ManuallyDrop` makes sure it is not dropped either).
+unsafe fn wipe<T>(value: &mut ManuallyDrop<T>) {
+    let bytes = unsafe {
+        core::slice::from_raw_parts_mut(value as *mut ManuallyDrop<T> as *mut u8, size_of::<T>())
+    };
+    bytes.zeroize();
+}
+
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
+where S: Store,
+      FS: 'static + LfsStorage,
+      T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
+{
+    pub(crate) fn import_attestation_key(&mut self, command: &Command, reply: &mut response::Data) -> ResponseResult {
+        let (kind, secret_path, _) = match command.p1 {
+            // the X25519 key can't unwrap itself
+            0x01 | 0x02 => attestation_slot(command.p1).unwrap(),
+            _ => return Err(Status::IncorrectP1OrP2Parameter),
+        };
+        let aead = Aead::try_from(command.p2)?;
+        let data = command.data();
+        if data.len() != WRAPPED_LENGTH {
+            return Err(Status::WrongLength);
+        }
+        let (ephemeral_public, rest) = data.split_at(32);
+        let (nonce, rest) = rest.split_at(12);
+        let (encrypted, tag) = rest.split_at(32);
+
+        let device_seed = Zeroizing::new(self.load_secret(FILENAME_X255_SECRET)
+            .map_err(|_| Status::ConditionsOfUseNotSatisfied)?);
+        let mut device_secret = ManuallyDrop::new(salty::agreement::SecretKey::from_seed(&device_seed));
+        let device_public = salty::agreement::PublicKey::from(&*device_secret);
+        let mut ephemeral = [0u8; 32];
+        ephemeral.copy_from_slice(ephemeral_public);
+        let mut shared_secret = ManuallyDrop::new(device_secret.agree(&salty::agreement::PublicKey::from(ephemeral)));
+        unsafe { wipe(&mut device_secret) };
+        let shared = Zeroizing::new(shared_secret.to_bytes());
+        unsafe { wipe(&mut shared_secret) };
+
+        let mut key = Zeroizing::new([0u8; 32]);
+        Sha256::new()
+            .chain(&*shared)
+            .chain(ephemeral_public)
+            .chain(device_public.to_bytes())
+            .finalize_into(GenericArray::from_mut_slice(&mut key[..]));
+        let mut aad = [0u8; 17];
+        aad[0] = command.p1;
+        aad[1..].copy_from_slice(&self.uuid);
+
+        let mut seed = Zeroizing::new([0u8; 32]);
+        seed.copy_from_slice(encrypted);
+        aead.decrypt(&key, nonce, &aad, &mut seed[..], tag).map_err(|_| {
+            info!("key was not wrapped for this device");
+            Status::VerificationFailed
+        })?;
+
+        info!("importing attestation key into slot {}", command.p1);
+        self.store_secret(kind, Flags::SENSITIVE, &seed, secret_path)?;
+        reply.extend_from_slice(&public_key(kind, &seed)).map_err(|_| Status::NotEnoughMemory)
+    }
+}
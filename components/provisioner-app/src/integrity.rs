@@ -0,0 +1,57 @@
+//! Integrity checks for files transferred with `WriteBinary`, before `WriteFile` stores them.
+//!
+//! On flaky (NFC) links a corrupted transfer would otherwise be stored as is.
+//! P1 of `WriteFile` selects the check, the command data is the expected value.
+
+use core::convert::TryFrom;
+
+use apdu_dispatch::iso7816::Status;
+use sha2::{Digest, Sha256};
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Check {
+    /// Plain `WriteFile`, as before (any command data is ignored).
+    None = 0x00,
+    /// CRC-32 (as in zlib and Ethernet), big endian.
+    Crc32 = 0x01,
+    Sha256 = 0x02,
+}
+
+impl TryFrom<u8> for Check {
+    type Error = ();
+    fn try_from(p1: u8) -> Result<Self, Self::Error> {
+        Ok(match p1 {
+            0x00 => Check::None,
+            0x01 => Check::Crc32,
+            0x02 => Check::Sha256,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl Check {
+    pub fn verify(self, contents: &[u8], expected: &[u8]) -> Result<(), Status> {
+        let matches = match self {
+            Check::None => true,
+            Check::Crc32 => expected.len() == 4 && crc32(contents).to_be_bytes() == expected,
+            Check::Sha256 => expected.len() == 32 && Sha256::digest(contents).as_slice() == expected,
+        };
+        if matches {
+            Ok(())
+        } else {
+            Err(Status::VerificationFailed)
+        }
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
@@ -6,6 +6,19 @@
 //! attestation keys.
 //! It allows generating Trussed device attestation keys and obtaining their public keys,
 //! to then generate and inject attn certs from a given root or intermedidate CA.
+//! Keys held elsewhere can be imported, wrapped to the device's X25519 attestation key.
+//! Alternatively, it generates a PKCS #10 certificate request, signed by the new key.
+//! Certificates are only saved if they match the attestation key (and the T1 intermediate),
+//! intermediates can be appended to form a chain.
+//! `SelfTest` checks the keys against their certificates on the device.
+//! `ImportManifest` applies a whole batch of the above at once, or nothing of it.
+//! Mutating instructions are recorded in a provisioning history (see `log`).
+//!
+//! Once `LockProvisioning` was called, all instructions but the read-only ones
+//! (`GetUuid`, `ReadFile`, `ListDirectory`, `ReadAttestationChain`) are refused,
+//! so a firmware including the provisioner can be shipped.
+//! Before that, once there is a factory key, they need an authenticated session (see `session`).
+//! Files can be read back (except secrets), listed and deleted.
 //!
 //! See `solo2-cli` for usage.
 #![no_std]
@@ -14,6 +27,19 @@ use core::convert::TryFrom;
 
 use trussed::types::LfsStorage;
 
+mod cbor;
+mod certificate;
+mod chain;
+mod csr;
+mod der;
+mod files;
+mod import;
+mod integrity;
+mod log;
+mod manifest;
+mod self_test;
+mod session;
+
 use defmt::info;
 use littlefs2::path::{PathBuf};
 use trussed::store::{self, Store};
@@ -28,7 +54,7 @@ use apdu_dispatch::iso7816::{Status, Instruction};
 use apdu_dispatch::app::Result as ResponseResult;
 use apdu_dispatch::{Command, response, command::SIZE as CommandSize, response::SIZE as ResponseSize};
 
-use lpc55_hal as hal;
+pub use admin_app::Reboot;
 
 //
 const SOLO_PROVISIONER_AID: [u8; 9] = [ 0xA0, 0x00, 0x00, 0x08, 0x47, 0x01, 0x00, 0x00, 0x01];
@@ -40,6 +66,9 @@ const TESTER_FILE_ID: [u8; 2] = [0xe1,0x02];
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Instructions {
     WriteFile = 0xbf,
+    ReadFile = 0xb4,
+    ListDirectory = 0xb3,
+    DeleteFile = 0xb2,
 
     BootToBootrom = 0x51,
     ReformatFilesystem = 0xbd,
@@ -48,13 +77,36 @@ pub enum Instructions {
     GenerateP256Key = 0xbc,
     GenerateEd255Key = 0xbb,
     GenerateX255Key = 0xb7,
+    /// Generates a P-256 (P1 = 1) or Ed25519 (P1 = 2) key, replies with a CSR for it
+    GenerateCsr = 0xb1,
 
     SaveP256AttestationCertificate = 0xba,
     SaveEd255AttestationCertificate = 0xb9,
     SaveX255AttestationCertificate = 0xb6,
+    /// Appends an intermediate certificate to the chain of the slot in P1
+    /// (0xb0 would be READ BINARY to the dispatch)
+    AppendAttestationIntermediate = 0xa7,
+    /// Reads the chain of the slot in P1
+    ReadAttestationChain = 0xaf,
 
     SaveT1IntermediatePublicKey = 0xb5,
 
+    /// Permanently refuses all mutating instructions
+    LockProvisioning = 0xae,
+
+    GetChallenge = 0xad,
+    Authenticate = 0xac,
+
+    /// Checks all attestation keys against their certificates, replies with a CBOR report
+    SelfTest = 0xab,
+    /// Reads the provisioning history
+    ReadLog = 0xaa,
+
+    /// Imports a CBOR manifest of files, keys to generate and certificates, atomically
+    ImportManifest = 0xa9,
+    /// Imports an attestation key wrapped to the X25519 attestation key
+    ImportAttestationKey = 0xa8,
+
     #[cfg(feature = "test-attestation")]
     TestAttestation = 0xb8,
 }
@@ -65,6 +117,9 @@ impl TryFrom<u8> for Instructions {
         use Instructions::*;
         Ok(match ins {
             0xbf => WriteFile,
+            0xb4 => ReadFile,
+            0xb3 => ListDirectory,
+            0xb2 => DeleteFile,
 
             0x51 => BootToBootrom,
             0xbd => ReformatFilesystem,
@@ -73,13 +128,27 @@ impl TryFrom<u8> for Instructions {
             0xbc => GenerateP256Key,
             0xbb => GenerateEd255Key,
             0xb7 => GenerateX255Key,
+            0xb1 => GenerateCsr,
 
             0xba => SaveP256AttestationCertificate,
             0xb9 => SaveEd255AttestationCertificate,
             0xb6 => SaveX255AttestationCertificate,
+            0xa7 => AppendAttestationIntermediate,
+            0xaf => ReadAttestationChain,
 
             0xb5 => SaveT1IntermediatePublicKey,
 
+            0xae => LockProvisioning,
+
+            0xad => GetChallenge,
+            0xac => Authenticate,
+
+            0xab => SelfTest,
+            0xaa => ReadLog,
+
+            0xa9 => ImportManifest,
+            0xa8 => ImportAttestationKey,
+
             #[cfg(feature = "test-attestation")]
             0xb8 => TestAttestation,
             _ => return Err(()),
@@ -87,6 +156,20 @@ impl TryFrom<u8> for Instructions {
     }
 }
 
+impl Instructions {
+    /// Whether the instruction changes the device, and is refused once provisioning is locked.
+    pub fn is_mutating(&self) -> bool {
+        use Instructions::*;
+        match self {
+            ReadFile | ListDirectory | GetUuid | ReadAttestationChain => false,
+            GetChallenge | Authenticate | SelfTest | ReadLog => false,
+            #[cfg(feature = "test-attestation")]
+            TestAttestation => false,
+            _ => true,
+        }
+    }
+}
+
 #[cfg(feature = "test-attestation")]
 #[derive(Copy,Clone)]
 enum TestAttestationP1 {
@@ -105,6 +188,9 @@ const FILENAME_P256_SECRET: &'static [u8] = b"/attn/sec/01";
 const FILENAME_ED255_SECRET: &'static [u8] = b"/attn/sec/02";
 const FILENAME_X255_SECRET: &'static [u8] = b"/attn/sec/03";
 
+/// Exists once provisioning is locked.
+const FILENAME_LOCKED: &'static [u8] = b"/attn/locked";
+
 const FILENAME_P256_CERT: &'static [u8] = b"/attn/x5c/01";
 const FILENAME_ED255_CERT: &'static [u8] = b"/attn/x5c/02";
 const FILENAME_X255_CERT: &'static [u8] = b"/attn/x5c/03";
@@ -116,14 +202,18 @@ enum SelectedBuffer {
     File,
 }
 
-pub struct Provisioner<S, FS, T>
+pub struct Provisioner<S, FS, T, R>
 where S: Store,
       FS: 'static + LfsStorage,
       T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
 {
     trussed: T,
+    uuid: [u8; 16],
 
     selected_buffer: SelectedBuffer,
+    challenge: Option<[u8; 32]>,
+    authenticated: bool,
     buffer_filename: Vec<u8, 128>,
     buffer_file_contents: Vec<u8, 8192>,
 
@@ -131,44 +221,88 @@ where S: Store,
     stolen_filesystem: &'static mut FS,
     #[allow(dead_code)]
     is_passive: bool,
+    _reboot: core::marker::PhantomData<R>,
 }
 
-impl<S, FS, T> Provisioner<S, FS, T>
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
 where S: Store,
       FS: 'static + LfsStorage,
       T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
 {
     pub fn new(
         trussed: T,
         store: S,
         stolen_filesystem: &'static mut FS,
         is_passive: bool,
-    ) -> Provisioner<S, FS, T> {
+        uuid: [u8; 16],
+    ) -> Provisioner<S, FS, T, R> {
 
 
         return Self {
             trussed,
+            uuid,
 
             selected_buffer: SelectedBuffer::Filename,
+            challenge: None,
+            authenticated: false,
             buffer_filename: Vec::new(),
             buffer_file_contents: Vec::new(),
             store,
             stolen_filesystem,
             is_passive,
+            _reboot: Default::default(),
         }
     }
 
     fn handle(&mut self, command: &Command, reply: &mut response::Data) -> ResponseResult {
 
+        let is_mutating = match command.instruction() {
+            Instruction::WriteBinary => true,
+            Instruction::Unknown(ins) => Instructions::try_from(ins)
+                .map(|instruction| instruction.is_mutating())
+                .unwrap_or(false),
+            _ => false,
+        };
+        if is_mutating {
+            if self.is_locked() {
+                info!("provisioning is locked");
+                return Err(Status::SecurityStatusNotSatisfied);
+            }
+            self.check_session()?;
+        }
+
+        let logged = match command.instruction() {
+            Instruction::Unknown(ins) => Instructions::try_from(ins).ok()
+                .filter(|instruction| instruction.is_mutating()),
+            _ => None,
+        };
+        let entry = logged.map(|instruction| self.log_entry(instruction, command));
+        if let (Some(Instructions::BootToBootrom), Some(entry)) = (logged, &entry) {
+            // there is no coming back to log afterwards
+            self.log(entry, &Ok(()));
+        }
+
+        let result = self.dispatch(command, reply);
+        match (logged, &entry) {
+            (Some(Instructions::BootToBootrom), _) => {}
+            // an entry per item, see `log`
+            (Some(Instructions::ImportManifest), _) => self.log_manifest(command.data(), &result),
+            (_, Some(entry)) => self.log(entry, &result),
+            _ => {}
+        }
+        result
+    }
+
+    fn dispatch(&mut self, command: &Command, reply: &mut response::Data) -> ResponseResult {
         match command.instruction() {
             Instruction::Select => self.select(command, reply),
             Instruction::WriteBinary => {
-                let _offset: u16 = ((command.p1 as u16) << 8) | command.p2 as u16;
+                let offset = u16::from_be_bytes([command.p1, command.p2]) as usize;
                 match self.selected_buffer {
-                    SelectedBuffer::Filename => self.buffer_filename.extend_from_slice(command.data()).unwrap(),
-                    SelectedBuffer::File => self.buffer_file_contents.extend_from_slice(command.data()).unwrap(),
-                };
-                Ok(())
+                    SelectedBuffer::Filename => write_at(&mut self.buffer_filename, offset, command.data()),
+                    SelectedBuffer::File => write_at(&mut self.buffer_file_contents, offset, command.data()),
+                }
             }
             Instruction::Unknown(ins) => {
                 if let Ok(instruction) = Instructions::try_from(ins) {
@@ -182,8 +316,15 @@ where S: Store,
                             Ok(())
                         }
                         WriteFile => {
+                            let check = integrity::Check::try_from(command.p1)
+                                .map_err(|_| Status::IncorrectP1OrP2Parameter)?;
                             if self.buffer_file_contents.len() == 0 || self.buffer_filename.len() == 0 {
                                 Err(Status::IncorrectDataParameter)
+                            } else if let Err(status) = check.verify(&self.buffer_file_contents, command.data()) {
+                                info!("transfer corrupted, not writing file");
+                                self.buffer_file_contents.clear();
+                                self.buffer_filename.clear();
+                                Err(status)
                             } else {
                                 // self.buffer_filename.push(0);
                                 let _filename = unsafe{ core::str::from_utf8_unchecked(self.buffer_filename.as_slice()) };
@@ -207,155 +348,41 @@ where S: Store,
                                 }
                             }
                         }
+                        ReadFile => self.read_file(command, reply),
+                        ListDirectory => self.list_directory(command, reply),
+                        DeleteFile => self.delete_file(command),
                         GenerateP256Key => {
                             info!("GenerateP256Key");
-                            let mut seed = [0u8; 32];
-                            seed.copy_from_slice(
-                                &syscall!(self.trussed.random_bytes(32)).bytes.as_slice()
-                            );
-
-                            let serialized_key = Key {
-                                flags: Flags::LOCAL | Flags::SENSITIVE,
-                                kind: KeyKind::P256,
-                                material: Vec::from_slice(&seed).unwrap(),
-                            };
-
-                            let serialized_bytes = serialized_key.serialize();
-
-                            store::store(
-                                self.store,
-                                trussed::types::Location::Internal,
-                                &PathBuf::from(FILENAME_P256_SECRET),
-                                &serialized_bytes
-                            ).map_err(|_| Status::NotEnoughMemory)?;
-                            info!("stored to {}", core::str::from_utf8(FILENAME_P256_SECRET).unwrap());
-
-                            let keypair = nisty::Keypair::generate_patiently(&seed);
-
-                            reply.extend_from_slice(keypair.public.as_bytes()).unwrap();
+                            let seed = self.generate_secret(KeyKind::P256, FILENAME_P256_SECRET)?;
+                            reply.extend_from_slice(&public_key(KeyKind::P256, &seed)).unwrap();
                             Ok(())
                         }
                         GenerateEd255Key => {
-
                             info!("GenerateEd255Key");
-                            let mut seed = [0u8; 32];
-                            seed.copy_from_slice(
-                                &syscall!(self.trussed.random_bytes(32)).bytes.as_slice()
-                            );
-
-                            let serialized_key = Key {
-                                flags: Flags::LOCAL | Flags::SENSITIVE,
-                                kind: KeyKind::Ed255,
-                                material: Vec::from_slice(&seed).unwrap(),
-                            };
-
-                            // let serialized_key = Key::try_deserialize(&seed[..])
-                                // .map_err(|_| Status::WrongLength)?;
-
-                            let serialized_bytes = serialized_key.serialize();
-
-                            store::store(
-                                self.store,
-                                trussed::types::Location::Internal,
-                                &PathBuf::from(FILENAME_ED255_SECRET),
-                                &serialized_bytes
-                            ).map_err(|_| Status::NotEnoughMemory)?;
-
-                            let keypair = salty::Keypair::from(&seed);
-
-                            reply.extend_from_slice(keypair.public.as_bytes()).unwrap();
+                            let seed = self.generate_secret(KeyKind::Ed255, FILENAME_ED255_SECRET)?;
+                            reply.extend_from_slice(&public_key(KeyKind::Ed255, &seed)).unwrap();
                             Ok(())
                         },
 
                         GenerateX255Key => {
-
                             info!("GenerateX255Key");
-                            let mut seed = [0u8; 32];
-                            seed.copy_from_slice(
-                                &syscall!(self.trussed.random_bytes(32)).bytes.as_slice()
-                            );
-
-                            let serialized_key = Key {
-                                flags: Flags::LOCAL | Flags::SENSITIVE,
-                                kind: KeyKind::X255,
-                                material: Vec::from_slice(&seed).unwrap(),
-                            };
-
-                            // let serialized_key = Key::try_deserialize(&seed[..])
-                                // .map_err(|_| Status::WrongLength)?;
-
-                            let serialized_bytes = serialized_key.serialize();
-
-                            store::store(
-                                self.store,
-                                trussed::types::Location::Internal,
-                                &PathBuf::from(FILENAME_X255_SECRET),
-                                &serialized_bytes
-                            ).map_err(|_| Status::NotEnoughMemory)?;
-
-                            let secret_key = salty::agreement::SecretKey::from_seed(&seed);
-                            let public_key = salty::agreement::PublicKey::from(&secret_key);
-
-                            reply.extend_from_slice(&public_key.to_bytes()).unwrap();
+                            let seed = self.generate_secret(KeyKind::X255, FILENAME_X255_SECRET)?;
+                            reply.extend_from_slice(&public_key(KeyKind::X255, &seed)).unwrap();
                             Ok(())
                         },
-
-                        SaveP256AttestationCertificate => {
-                            let secret_path = PathBuf::from(FILENAME_P256_SECRET);
-                            if !secret_path.exists(&self.store.ifs()) {
-                                Err(Status::IncorrectDataParameter)
-                            } else if command.data().len() < 100 {
-                                // Assuming certs will always be >100 bytes
-                                Err(Status::IncorrectDataParameter)
-                            } else {
-                                info!("saving P256 CERT, {} bytes", command.data().len());
-                                store::store(
-                                    self.store,
-                                    trussed::types::Location::Internal,
-                                    &PathBuf::from(FILENAME_P256_CERT),
-                                    command.data()
-                                ).map_err(|_| Status::NotEnoughMemory)?;
-                                Ok(())
-                            }
-                        },
-
-                        SaveEd255AttestationCertificate => {
-                            let secret_path = PathBuf::from(FILENAME_ED255_SECRET);
-                            if !secret_path.exists(&self.store.ifs()) {
-                                Err(Status::IncorrectDataParameter)
-                            } else if command.data().len() < 100 {
-                                // Assuming certs will always be >100 bytes
-                                Err(Status::IncorrectDataParameter)
-                            } else {
-                                info!("saving ED25519 CERT, {} bytes", command.data().len());
-                                store::store(
-                                    self.store,
-                                    trussed::types::Location::Internal,
-                                    &PathBuf::from(FILENAME_ED255_CERT),
-                                    command.data()
-                                ).map_err(|_| Status::NotEnoughMemory)?;
-                                Ok(())
-                            }
-                        },
-
-                        SaveX255AttestationCertificate => {
-                            let secret_path = PathBuf::from(FILENAME_X255_SECRET);
-                            if !secret_path.exists(&self.store.ifs()) {
-                                Err(Status::IncorrectDataParameter)
-                            } else if command.data().len() < 100 {
-                                // Assuming certs will always be >100 bytes
-                                Err(Status::IncorrectDataParameter)
-                            } else {
-                                info!("saving X25519 CERT, {} bytes", command.data().len());
-                                store::store(
-                                    self.store,
-                                    trussed::types::Location::Internal,
-                                    &PathBuf::from(FILENAME_X255_CERT),
-                                    command.data()
-                                ).map_err(|_| Status::NotEnoughMemory)?;
-                                Ok(())
-                            }
-                        },
+                        GenerateCsr => self.generate_csr(command, reply),
+
+                        SaveP256AttestationCertificate => self.save_certificate(
+                            KeyKind::P256, FILENAME_P256_SECRET, FILENAME_P256_CERT, command.data(),
+                        ),
+                        SaveEd255AttestationCertificate => self.save_certificate(
+                            KeyKind::Ed255, FILENAME_ED255_SECRET, FILENAME_ED255_CERT, command.data(),
+                        ),
+                        SaveX255AttestationCertificate => self.save_certificate(
+                            KeyKind::X255, FILENAME_X255_SECRET, FILENAME_X255_CERT, command.data(),
+                        ),
+                        AppendAttestationIntermediate => self.append_intermediate(command),
+                        ReadAttestationChain => self.read_chain(command, reply),
 
                         SaveT1IntermediatePublicKey => {
                             info!("saving T1 INTERMEDIATE PUBLIC KEY, {} bytes", command.data().len());
@@ -380,6 +407,22 @@ where S: Store,
                             }
                         },
 
+                        SelfTest => self.self_test(reply),
+                        ReadLog => self.read_log(command, reply),
+                        ImportManifest => self.import_manifest(command, reply),
+                        ImportAttestationKey => self.import_attestation_key(command, reply),
+                        GetChallenge => self.get_challenge(reply),
+                        Authenticate => self.authenticate(command),
+                        LockProvisioning => {
+                            info!("locking provisioning");
+                            store::store(
+                                self.store,
+                                trussed::types::Location::Internal,
+                                &PathBuf::from(FILENAME_LOCKED),
+                                &[1],
+                            ).map_err(|_| Status::NotEnoughMemory)
+                        },
+
                         #[cfg(feature = "test-attestation")]
                         TestAttestation => {
                             // This is only exposed for development and testing.
@@ -502,17 +545,12 @@ where S: Store,
 
                         GetUuid => {
                             // Get UUID
-                            reply.extend_from_slice(&hal::uuid()).unwrap();
+                            reply.extend_from_slice(&self.uuid).unwrap();
                             Ok(())
                         },
                         BootToBootrom => {
                             // Boot to bootrom via flash 0 page erase
-                            use hal::traits::flash::WriteErase;
-                            let flash = unsafe { hal::peripherals::flash::Flash::steal() }.enabled(
-                                &mut unsafe { hal::peripherals::syscon::Syscon::steal()}
-                            );
-                            hal::drivers::flash::FlashGordon::new(flash).erase_page(0).ok();
-                            hal::raw::SCB::sys_reset()
+                            R::reboot_to_firmware_update_destructive()
                         },
 
                     }
@@ -524,6 +562,49 @@ where S: Store,
         }
     }
 
+    fn is_locked(&self) -> bool {
+        PathBuf::from(FILENAME_LOCKED).exists(&self.store.ifs())
+    }
+
+    /// Generates a fresh seed, and stores it as attestation key of the given kind at `path`.
+    fn generate_secret(&mut self, kind: KeyKind, path: &[u8]) -> Result<[u8; 32], Status> {
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(
+            &syscall!(self.trussed.random_bytes(32)).bytes.as_slice()
+        );
+        self.store_secret(kind, Flags::LOCAL | Flags::SENSITIVE, &seed, path)?;
+        Ok(seed)
+    }
+
+    /// Stores the seed as attestation key of the given kind at `path`.
+    fn store_secret(&mut self, kind: KeyKind, flags: Flags, seed: &[u8; 32], path: &[u8]) -> ResponseResult {
+        let serialized_key = Key {
+            flags,
+            kind,
+            material: Vec::from_slice(seed).unwrap(),
+        };
+
+        store::store(
+            self.store,
+            trussed::types::Location::Internal,
+            &PathBuf::from(path),
+            &serialized_key.serialize()
+        ).map_err(|_| Status::NotEnoughMemory)?;
+        info!("stored to {}", core::str::from_utf8(path).unwrap());
+        Ok(())
+    }
+
+    /// Loads the seed of the attestation key at `path`.
+    fn load_secret(&self, path: &[u8]) -> Result<[u8; 32], Status> {
+        let serialized: trussed::types::Message = store::read(
+            self.store,
+            trussed::types::Location::Internal,
+            &PathBuf::from(path),
+        ).map_err(|_| Status::IncorrectDataParameter)?;
+        let key = Key::try_deserialize(&serialized).map_err(|_| Status::UnspecifiedCheckingError)?;
+        <[u8; 32]>::try_from(key.material.as_slice()).map_err(|_| Status::UnspecifiedCheckingError)
+    }
+
     fn select(&mut self, command: &Command, _reply: &mut response::Data) -> ResponseResult {
 
         if command.data().starts_with(&TESTER_FILENAME_ID) {
@@ -543,10 +624,52 @@ where S: Store,
 
 }
 
-impl<S, FS, T> apdu_dispatch::iso7816::App for Provisioner<S, FS, T>
+/// Attestation key slots, as numbered in P1: key kind, secret key and certificate paths.
+fn attestation_slot(slot: u8) -> Option<(KeyKind, &'static [u8], &'static [u8])> {
+    match slot {
+        0x01 => Some((KeyKind::P256, FILENAME_P256_SECRET, FILENAME_P256_CERT)),
+        0x02 => Some((KeyKind::Ed255, FILENAME_ED255_SECRET, FILENAME_ED255_CERT)),
+        0x03 => Some((KeyKind::X255, FILENAME_X255_SECRET, FILENAME_X255_CERT)),
+        _ => None,
+    }
+}
+
+/// The public key of an attestation key, P-256 keys as uncompressed point without the leading 0x04.
+fn public_key(kind: KeyKind, seed: &[u8; 32]) -> Vec<u8, 64> {
+    match kind {
+        KeyKind::P256 => Vec::from_slice(nisty::Keypair::generate_patiently(seed).public.as_bytes()).unwrap(),
+        KeyKind::Ed255 => Vec::from_slice(salty::Keypair::from(seed).public.as_bytes()).unwrap(),
+        KeyKind::X255 => {
+            let secret_key = salty::agreement::SecretKey::from_seed(seed);
+            Vec::from_slice(&salty::agreement::PublicKey::from(&secret_key).to_bytes()).unwrap()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Writes `data` at `offset` into `buffer`, which grows as needed.
+///
+/// Writing past the current end would leave a gap, so it is refused.
+fn write_at<const N: usize>(buffer: &mut Vec<u8, N>, offset: usize, data: &[u8]) -> ResponseResult {
+    if offset > buffer.len() {
+        return Err(Status::IncorrectP1OrP2Parameter);
+    }
+    let end = offset + data.len();
+    if end > buffer.capacity() {
+        return Err(Status::WrongLength);
+    }
+    if end > buffer.len() {
+        buffer.resize_default(end).ok();
+    }
+    buffer[offset..end].copy_from_slice(data);
+    Ok(())
+}
+
+impl<S, FS, T, R> apdu_dispatch::iso7816::App for Provisioner<S, FS, T, R>
 where S: Store,
       FS: 'static + LfsStorage,
       T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
 {
     fn aid(&self) -> apdu_dispatch::iso7816::Aid {
         apdu_dispatch::iso7816::Aid::new(&SOLO_PROVISIONER_AID)
@@ -554,20 +677,26 @@ where S: Store,
 }
 
 
-impl<S, FS, T> apdu_dispatch::app::App<CommandSize, ResponseSize> for Provisioner<S, FS, T>
+impl<S, FS, T, R> apdu_dispatch::app::App<CommandSize, ResponseSize> for Provisioner<S, FS, T, R>
 where S: Store,
       FS: 'static + LfsStorage,
       T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
 {
     fn select(&mut self, _apdu: &Command, reply: &mut response::Data) -> apdu_dispatch::app::Result {
         self.buffer_file_contents.clear();
         self.buffer_filename.clear();
+        self.challenge = None;
+        self.authenticated = false;
+        self.recover_manifest();
         // For manufacture speed, return uuid on select
-        reply.extend_from_slice(&hal::uuid()).unwrap();
+        reply.extend_from_slice(&self.uuid).unwrap();
         Ok(())
     }
 
     fn deselect(&mut self) -> () {
+        self.challenge = None;
+        self.authenticated = false;
     }
 
     fn call(&mut self, _interface_type: apdu_dispatch::app::Interface, apdu: &Command, reply: &mut response::Data) -> apdu_dispatch::app::Result {
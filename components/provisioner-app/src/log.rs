@@ -0,0 +1,226 @@
+//! The provisioning history, for reconstructing what happened to a returned unit.
+//!
+//! Every mutating instruction (except `WriteBinary`, which only fills a buffer)
+//! appends an entry to `/attn/log`, a CBOR map with integer keys:
+//!
+//! | key | value                                                     |
+//! |-----|-----------------------------------------------------------|
+//! | 1   | instruction                                               |
+//! | 2   | path, if any                                              |
+//! | 3   | length of the written content                             |
+//! | 4   | SHA-256 of the written content, if any (never of secrets) |
+//! | 5   | uptime in milliseconds                                    |
+//! | 6   | status word                                               |
+//!
+//! `ImportManifest` appends an entry per item of the manifest, each with the outcome of
+//! the whole import.
+//!
+//! Once the log would exceed `MAX_LOG_SIZE`, it is rotated to `/attn/log.1`,
+//! replacing the previous one. `ReadLog` reads both, oldest entries first.
+
+use defmt::info;
+use heapless::Vec;
+use littlefs2::fs::OpenOptions;
+use littlefs2::io::{Read, Seek, SeekFrom, Write};
+use littlefs2::path::PathBuf;
+use sha2::{Digest, Sha256};
+use trussed::store::Store;
+use trussed::types::LfsStorage;
+use trussed::{client, syscall, Client as TrussedClient};
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::app::Result as ResponseResult;
+use apdu_dispatch::{Command, response};
+
+use crate::files::is_secret;
+use crate::manifest::{visit, Item};
+use crate::{attestation_slot, cbor, Instructions, Provisioner, Reboot};
+use crate::{FILENAME_ED255_SECRET, FILENAME_LOCKED, FILENAME_P256_SECRET, FILENAME_T1_PUBLIC, FILENAME_X255_SECRET};
+use crate::{FILENAME_ED255_CERT, FILENAME_P256_CERT, FILENAME_X255_CERT};
+
+const FILENAME_LOG: &[u8] = b"/attn/log";
+const FILENAME_ROTATED_LOG: &[u8] = b"/attn/log.1";
+
+const MAX_LOG_SIZE: usize = 4096;
+
+const KEY_INSTRUCTION: u32 = 1;
+const KEY_PATH: u32 = 2;
+const KEY_LENGTH: u32 = 3;
+const KEY_SHA256: u32 = 4;
+const KEY_UPTIME: u32 = 5;
+const KEY_STATUS: u32 = 6;
+
+/// What an instruction is about to change, recorded before it runs
+/// (`WriteFile` consumes its buffers).
+pub struct Entry {
+    instruction: u8,
+    path: Vec<u8, 128>,
+    length: usize,
+    digest: Option<[u8; 32]>,
+}
+
+impl Entry {
+    fn new(instruction: Instructions, path: &[u8], content: Option<&[u8]>) -> Self {
+        Self {
+            instruction: instruction as u8,
+            path: Vec::from_slice(&path[..path.len().min(128)]).unwrap(),
+            length: content.map(|content| content.len()).unwrap_or(0),
+            digest: content.map(|content| {
+                let mut digest = [0u8; 32];
+                digest.copy_from_slice(&Sha256::digest(content));
+                digest
+            }),
+        }
+    }
+
+    /// The entry for writing `contents` to `path`, only recording the length of secrets.
+    fn written(instruction: Instructions, path: &[u8], contents: &[u8]) -> Self {
+        let mut entry = Self::new(instruction, path, Some(contents));
+        if is_secret(path, contents) {
+            entry.digest = None;
+        }
+        entry
+    }
+
+    fn manifest_item(item: Item) -> Self {
+        let instruction = Instructions::ImportManifest;
+        let target = item.target().unwrap_or(b"");
+        match item {
+            Item::File { path, contents } => Self::written(instruction, path, contents),
+            Item::Key { .. } => Self::new(instruction, target, None),
+            Item::Certificate { certificate, .. } => Self::new(instruction, target, Some(certificate)),
+        }
+    }
+}
+
+/// The secret key (`cert` false) or certificate path of the slot in P1, if valid.
+fn slot_path(p1: u8, cert: bool) -> &'static [u8] {
+    match attestation_slot(p1) {
+        Some((_, secret, certificate)) => if cert { certificate } else { secret },
+        None => b"",
+    }
+}
+
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
+where S: Store,
+      FS: 'static + LfsStorage,
+      T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
+{
+    /// The entry for a mutating instruction, secrets are only recorded by their path.
+    pub(crate) fn log_entry(&self, instruction: Instructions, command: &Command) -> Entry {
+        use Instructions::*;
+        let data = command.data();
+        match instruction {
+            WriteFile => Entry::written(instruction, &self.buffer_filename, &self.buffer_file_contents),
+            DeleteFile => Entry::new(instruction, data, None),
+            GenerateP256Key => Entry::new(instruction, FILENAME_P256_SECRET, None),
+            GenerateEd255Key => Entry::new(instruction, FILENAME_ED255_SECRET, None),
+            GenerateX255Key => Entry::new(instruction, FILENAME_X255_SECRET, None),
+            GenerateCsr => Entry::new(instruction, slot_path(command.p1, false), None),
+            SaveP256AttestationCertificate => Entry::new(instruction, FILENAME_P256_CERT, Some(data)),
+            SaveEd255AttestationCertificate => Entry::new(instruction, FILENAME_ED255_CERT, Some(data)),
+            SaveX255AttestationCertificate => Entry::new(instruction, FILENAME_X255_CERT, Some(data)),
+            AppendAttestationIntermediate => Entry::new(instruction, slot_path(command.p1, true), Some(data)),
+            ImportAttestationKey => Entry::new(instruction, slot_path(command.p1, false), None),
+            SaveT1IntermediatePublicKey => Entry::new(instruction, FILENAME_T1_PUBLIC, Some(data)),
+            LockProvisioning => Entry::new(instruction, FILENAME_LOCKED, None),
+            _ => Entry::new(instruction, b"", None),
+        }
+    }
+
+    /// Appends an entry per item of the manifest, or a single one if there are none.
+    pub(crate) fn log_manifest(&mut self, manifest: &[u8], result: &ResponseResult) {
+        let mut logged = false;
+        visit(manifest, |item| {
+            self.log(&Entry::manifest_item(item), result);
+            logged = true;
+            Ok(())
+        }).ok();
+        if !logged {
+            self.log(&Entry::new(Instructions::ImportManifest, b"", None), result);
+        }
+    }
+
+    /// Appends the entry with the outcome of its instruction, rotating the log if needed.
+    ///
+    /// Failing to log must not fail provisioning, so errors are only reported.
+    pub(crate) fn log(&mut self, entry: &Entry, result: &ResponseResult) {
+        let uptime = syscall!(self.trussed.uptime()).uptime.as_millis() as u32;
+        let status: u16 = match result {
+            Ok(()) => 0x9000,
+            Err(status) => (*status).into(),
+        };
+
+        let mut encoded: Vec<u8, 256> = Vec::new();
+        let fields = 4 + !entry.path.is_empty() as usize + entry.digest.is_some() as usize;
+        cbor::push_map(&mut encoded, fields).ok();
+        cbor::push_unsigned(&mut encoded, KEY_INSTRUCTION).ok();
+        cbor::push_unsigned(&mut encoded, entry.instruction as u32).ok();
+        if !entry.path.is_empty() {
+            cbor::push_unsigned(&mut encoded, KEY_PATH).ok();
+            // paths are ASCII, see `files::path_from`
+            cbor::push_text(&mut encoded, core::str::from_utf8(&entry.path).unwrap_or("?")).ok();
+        }
+        cbor::push_unsigned(&mut encoded, KEY_LENGTH).ok();
+        cbor::push_unsigned(&mut encoded, entry.length as u32).ok();
+        if let Some(digest) = &entry.digest {
+            cbor::push_unsigned(&mut encoded, KEY_SHA256).ok();
+            cbor::push_bytes(&mut encoded, digest).ok();
+        }
+        cbor::push_unsigned(&mut encoded, KEY_UPTIME).ok();
+        cbor::push_unsigned(&mut encoded, uptime).ok();
+        cbor::push_unsigned(&mut encoded, KEY_STATUS).ok();
+        cbor::push_unsigned(&mut encoded, status as u32).ok();
+
+        let fs = self.store.ifs();
+        let log = PathBuf::from(FILENAME_LOG);
+        let size = fs.metadata(&log).map(|metadata| metadata.len()).unwrap_or(0);
+        if size + encoded.len() > MAX_LOG_SIZE {
+            fs.rename(&log, &PathBuf::from(FILENAME_ROTATED_LOG)).ok();
+        }
+
+        // after a reformat, the directory is gone
+        fs.create_dir_all(&PathBuf::from(&b"/attn"[..])).ok();
+        let appended = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(true)
+            .open_and_then(&fs, &log, |file| file.write(&encoded));
+        if appended.is_err() {
+            info!("could not append to the provisioning log");
+        }
+    }
+
+    /// Reads (part of) the log, rotated entries first.
+    ///
+    /// P1/P2 is the offset, the response is as long as the expected length (Le) allows,
+    /// a shorter response than asked for means the end of the log was reached.
+    pub(crate) fn read_log(&mut self, command: &Command, reply: &mut response::Data) -> ResponseResult {
+        let mut offset = u16::from_be_bytes([command.p1, command.p2]) as usize;
+        let limit = reply.len() + command.expected().min(reply.capacity() - reply.len());
+
+        for path in [FILENAME_ROTATED_LOG, FILENAME_LOG] {
+            let path = PathBuf::from(path);
+            let size = match self.store.ifs().metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            if offset >= size {
+                offset -= size;
+                continue;
+            }
+
+            let start = reply.len();
+            let length = (size - offset).min(limit - start);
+            reply.resize_default(start + length).ok();
+            let read = self.store.ifs().open_file_and_then(&path, |file| {
+                file.seek(SeekFrom::Start(offset as u32))?;
+                file.read(&mut reply[start..])
+            }).map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+            reply.truncate(start + read);
+            offset = 0;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! Importing files, keys and certificates in one go, all or nothing.
+//!
+//! The manifest is the command data (sent as chained APDUs), a CBOR map:
+//!
+//! ```text
+//! {
+//!     "files": {"/path": h'contents', ...},
+//!     "keys": [slot, ...],
+//!     "certs": {slot: h'certificate', ...},
+//! }
+//! ```
+//!
+//! All entries are optional, slots are numbered as for `GenerateCsr`.
+//! Everything is first staged next to its target as `<path>.new`: files are written,
+//! keys generated, and certificates checked (against a key generated by the same
+//! manifest, if any). Only once all of that succeeded, the targets are replaced by
+//! renaming, keeping replaced files as `<path>.old` to restore them if a rename fails.
+//!
+//! Power can fail at any point, so the manifest is first copied to a journal, whose name
+//! tells how far the import got: `/attn/manifest` while staging, renamed to
+//! `/attn/manifest.commit` before the first target is replaced, and to `/attn/manifest.done`
+//! once all are. The next select or import finds the journal and, respectively, removes
+//! the staged files, restores the replaced files from `<path>.old`, or removes the `.old`
+//! files. Only then is the journal removed.
+//!
+//! The reply maps the slots of the generated keys to their public keys, in CBOR.
+
+use defmt::info;
+use heapless::Vec;
+use littlefs2::io::Read;
+use littlefs2::path::PathBuf;
+use trussed::store::{self, Store};
+use trussed::types::{LfsStorage, Location};
+use trussed::{client, Client as TrussedClient};
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::app::Result as ResponseResult;
+use apdu_dispatch::{Command, response};
+
+use crate::chain::clear_intermediates;
+use crate::files::path_from;
+use crate::{attestation_slot, cbor, public_key, Provisioner, Reboot};
+
+const NEW: &[u8] = b".new";
+const OLD: &[u8] = b".old";
+
+const FILENAME_JOURNAL: &[u8] = b"/attn/manifest";
+const FILENAME_JOURNAL_COMMIT: &[u8] = b"/attn/manifest.commit";
+const FILENAME_JOURNAL_DONE: &[u8] = b"/attn/manifest.done";
+
+#[derive(Copy, Clone)]
+pub(crate) enum Item<'a> {
+    File { path: &'a [u8], contents: &'a [u8] },
+    Key { slot: u8 },
+    Certificate { slot: u8, certificate: &'a [u8] },
+}
+
+impl Item<'_> {
+    /// The file the item replaces.
+    pub(crate) fn target(&self) -> Result<&[u8], Status> {
+        let slot = |slot| attestation_slot(slot).ok_or(Status::IncorrectDataParameter);
+        match *self {
+            Item::File { path, .. } => Ok(path),
+            Item::Key { slot: number } => slot(number).map(|(_, secret, _)| secret),
+            Item::Certificate { slot: number, .. } => slot(number).map(|(_, _, cert)| cert),
+        }
+    }
+}
+
+fn with_suffix(path: &[u8], suffix: &[u8]) -> Result<PathBuf, Status> {
+    let mut extended: Vec<u8, { littlefs2::consts::PATH_MAX }> = Vec::new();
+    extended.extend_from_slice(path).map_err(|_| Status::IncorrectDataParameter)?;
+    extended.extend_from_slice(suffix).map_err(|_| Status::IncorrectDataParameter)?;
+    path_from(&extended)
+}
+
+fn slot_number(number: u32) -> Result<u8, Status> {
+    match number {
+        0x01..=0x03 => Ok(number as u8),
+        _ => Err(Status::IncorrectDataParameter),
+    }
+}
+
+/// Calls `f` with the items of the manifest, in order.
+pub(crate) fn visit<'a>(manifest: &'a [u8], mut f: impl FnMut(Item<'a>) -> ResponseResult) -> ResponseResult {
+    let malformed = |_| Status::IncorrectDataParameter;
+    let mut decoder = cbor::Decoder::new(manifest);
+    for _ in 0..decoder.map().map_err(malformed)? {
+        match decoder.text().map_err(malformed)? {
+            "files" => for _ in 0..decoder.map().map_err(malformed)? {
+                let path = decoder.text().map_err(malformed)?.as_bytes();
+                let contents = decoder.bytes().map_err(malformed)?;
+                f(Item::File { path, contents })?;
+            },
+            "keys" => for _ in 0..decoder.array().map_err(malformed)? {
+                let slot = slot_number(decoder.unsigned().map_err(malformed)?)?;
+                f(Item::Key { slot })?;
+            },
+            "certs" => for _ in 0..decoder.map().map_err(malformed)? {
+                let slot = slot_number(decoder.unsigned().map_err(malformed)?)?;
+                let certificate = decoder.bytes().map_err(malformed)?;
+                f(Item::Certificate { slot, certificate })?;
+            },
+            _ => return Err(Status::IncorrectDataParameter),
+        }
+    }
+    if !decoder.is_empty() {
+        return Err(Status::IncorrectDataParameter);
+    }
+    Ok(())
+}
+
+/// Removes `<target><suffix>`, if there is one.
+fn remove_with_suffix<S: Store>(store: S, item: Item, suffix: &[u8]) {
+    if let Ok(path) = item.target().and_then(|target| with_suffix(target, suffix)) {
+        store.ifs().remove(&path).ok();
+    }
+}
+
+/// Moves `<target>.new` to the target, keeping the replaced file as `<target>.old`.
+fn commit<S: Store>(store: S, item: Item) -> ResponseResult {
+    let target = item.target()?;
+    let (new, old, target) = (with_suffix(target, NEW)?, with_suffix(target, OLD)?, path_from(target)?);
+    let fs = store.ifs();
+    if target.exists(&fs) {
+        fs.rename(&target, &old).map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+    }
+    fs.rename(&new, &target).map_err(|_| Status::UnspecifiedPersistentExecutionError)
+}
+
+/// Undoes `commit`, or `stage`, whichever happened last.
+fn roll_back<S: Store>(store: S, item: Item) {
+    let target = match item.target() {
+        Ok(target) => target,
+        Err(_) => return,
+    };
+    let (new, old, target) = match (with_suffix(target, NEW), with_suffix(target, OLD), path_from(target)) {
+        (Ok(new), Ok(old), Ok(target)) => (new, old, target),
+        _ => return,
+    };
+    let fs = store.ifs();
+    if old.exists(&fs) {
+        fs.rename(&old, &target).ok();
+    } else if !new.exists(&fs) {
+        // committed, and there was nothing to replace
+        fs.remove(&target).ok();
+    }
+    fs.remove(&new).ok();
+}
+
+/// Removes what `commit` replaced, and the intermediates of replaced leaf certificates.
+fn finish<S: Store>(store: S, item: Item) {
+    remove_with_suffix(store, item, OLD);
+    if let (Item::Certificate { .. }, Ok(target)) = (item, item.target()) {
+        clear_intermediates(store, target);
+    }
+}
+
+/// Moves the journal to its next state.
+fn advance<S: Store>(store: S, from: &[u8], to: &[u8]) -> ResponseResult {
+    store.ifs().rename(&PathBuf::from(from), &PathBuf::from(to))
+        .map_err(|_| Status::UnspecifiedPersistentExecutionError)
+}
+
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
+where S: Store,
+      FS: 'static + LfsStorage,
+      T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
+{
+    pub(crate) fn import_manifest(&mut self, command: &Command, reply: &mut response::Data) -> ResponseResult {
+        let manifest = command.data();
+        self.recover_manifest();
+        // the journal is read back into the file buffer
+        if manifest.len() > self.buffer_file_contents.capacity() {
+            return Err(Status::WrongLength);
+        }
+        // leftovers of an import from before the journal
+        visit(manifest, |item| {
+            remove_with_suffix(self.store, item, NEW);
+            remove_with_suffix(self.store, item, OLD);
+            Ok(())
+        })?;
+
+        store::store(self.store, Location::Internal, &PathBuf::from(FILENAME_JOURNAL), manifest)
+            .map_err(|_| Status::NotEnoughMemory)?;
+
+        let mut public_keys: Vec<(u8, Vec<u8, 64>), 3> = Vec::new();
+        // certificates last, their keys may be in the same manifest
+        let staged = visit(manifest, |item| match item {
+            Item::Certificate { .. } => Ok(()),
+            _ => self.stage(item, &mut public_keys),
+        }).and_then(|_| visit(manifest, |item| match item {
+            Item::Certificate { .. } => self.stage(item, &mut public_keys),
+            _ => Ok(()),
+        })).and_then(|_| advance(self.store, FILENAME_JOURNAL, FILENAME_JOURNAL_COMMIT));
+        if let Err(status) = staged {
+            visit(manifest, |item| {
+                remove_with_suffix(self.store, item, NEW);
+                Ok(())
+            }).ok();
+            self.store.ifs().remove(&PathBuf::from(FILENAME_JOURNAL)).ok();
+            return Err(status);
+        }
+
+        let committed = visit(manifest, |item| commit(self.store, item))
+            .and_then(|_| advance(self.store, FILENAME_JOURNAL_COMMIT, FILENAME_JOURNAL_DONE));
+        if let Err(status) = committed {
+            visit(manifest, |item| {
+                roll_back(self.store, item);
+                Ok(())
+            }).ok();
+            self.store.ifs().remove(&PathBuf::from(FILENAME_JOURNAL_COMMIT)).ok();
+            return Err(status);
+        }
+        visit(manifest, |item| {
+            finish(self.store, item);
+            Ok(())
+        })?;
+        self.store.ifs().remove(&PathBuf::from(FILENAME_JOURNAL_DONE)).ok();
+
+        let mut encoded: Vec<u8, 208> = Vec::new();
+        cbor::push_map(&mut encoded, public_keys.len()).ok();
+        for (slot, public_key) in &public_keys {
+            cbor::push_unsigned(&mut encoded, *slot as u32).ok();
+            cbor::push_bytes(&mut encoded, public_key).ok();
+        }
+        reply.extend_from_slice(&encoded).map_err(|_| Status::NotEnoughMemory)
+    }
+
+    /// Completes or undoes an import that was interrupted, as its journal says.
+    ///
+    /// Uses the file buffer, which select clears anyway.
+    pub(crate) fn recover_manifest(&mut self) {
+        let store = self.store;
+        let journals: [(&[u8], fn(S, Item)); 3] = [
+            (FILENAME_JOURNAL, |store, item| remove_with_suffix(store, item, NEW)),
+            (FILENAME_JOURNAL_COMMIT, roll_back),
+            (FILENAME_JOURNAL_DONE, finish),
+        ];
+        for (journal, recover) in journals {
+            let journal = PathBuf::from(journal);
+            let size = match store.ifs().metadata(&journal) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            info!("recovering an interrupted manifest import");
+
+            let manifest = &mut self.buffer_file_contents;
+            manifest.clear();
+            if size <= manifest.capacity() {
+                manifest.resize_default(size).ok();
+                let read = store.ifs().open_file_and_then(&journal, |file| file.read(&mut manifest[..]));
+                if matches!(read, Ok(read) if read == size) {
+                    visit(manifest.as_slice(), |item| {
+                        recover(store, item);
+                        Ok(())
+                    }).ok();
+                }
+            }
+            manifest.clear();
+            store.ifs().remove(&journal).ok();
+        }
+    }
+
+    /// Writes the item to `<target>.new`, generating keys and checking certificates.
+    fn stage(&mut self, item: Item, public_keys: &mut Vec<(u8, Vec<u8, 64>), 3>) -> ResponseResult {
+        let target = item.target()?;
+        path_from(target)?;
+        let new = with_suffix(target, NEW)?;
+        if new.exists(&self.store.ifs()) {
+            // listed twice
+            return Err(Status::IncorrectDataParameter);
+        }
+
+        match item {
+            Item::File { contents, .. } => store::store(self.store, Location::Internal, &new, contents)
+                .map_err(|_| Status::NotEnoughMemory),
+            Item::Key { slot } => {
+                let (kind, _, _) = attestation_slot(slot).unwrap();
+                let mut new_path: Vec<u8, { littlefs2::consts::PATH_MAX }> = Vec::from_slice(target).unwrap();
+                new_path.extend_from_slice(NEW).unwrap();
+                let seed = self.generate_secret(kind, &new_path)?;
+                public_keys.push((slot, public_key(kind, &seed))).ok();
+                Ok(())
+            }
+            Item::Certificate { slot, certificate } => {
+                let (kind, secret, _) = attestation_slot(slot).unwrap();
+                let mut secret_path: Vec<u8, { littlefs2::consts::PATH_MAX }> = Vec::from_slice(secret).unwrap();
+                if with_suffix(secret, NEW)?.exists(&self.store.ifs()) {
+                    secret_path.extend_from_slice(NEW).unwrap();
+                }
+                self.check_certificate(kind, &secret_path, certificate)?;
+                store::store(self.store, Location::Internal, &new, certificate)
+                    .map_err(|_| Status::NotEnoughMemory)
+            }
+        }
+    }
+}
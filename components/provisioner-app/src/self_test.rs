@@ -0,0 +1,141 @@
+//! Checking the attestation keys against their certificates, in production firmware.
+//!
+//! For each slot, the stored key signs (or, for X25519, agrees on a secret with an
+//! ephemeral key), and the result is verified against the public key in the stored
+//! certificate. The report is a CBOR array with a map per slot:
+//!
+//! ```text
+//! [{"slot": 1, "key": true, "cert": true, "verified": true}, ...]
+//! ```
+
+use core::convert::TryFrom;
+
+use heapless::Vec;
+use littlefs2::path::PathBuf;
+use trussed::store::{self, Store};
+use trussed::types::{
+    KeyId, KeySerialization, LfsStorage, Location, Mechanism, Message, SignatureSerialization,
+    StorageAttributes,
+};
+use trussed::{client, syscall, try_syscall, Client as TrussedClient};
+
+use apdu_dispatch::app::Result as ResponseResult;
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::response;
+
+use crate::certificate::Certificate;
+use crate::{attestation_slot, cbor, Provisioner, Reboot};
+
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
+where S: Store,
+      FS: 'static + LfsStorage,
+      T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
+{
+    pub(crate) fn self_test(&mut self, reply: &mut response::Data) -> ResponseResult {
+        let mut report: Vec<u8, 192> = Vec::new();
+        cbor::push_array(&mut report, 3).ok();
+
+        for slot in 1..=3 {
+            let (_, secret_path, cert_path) = attestation_slot(slot).unwrap();
+            let key = PathBuf::from(secret_path).exists(&self.store.ifs());
+            let cert: Option<Message> = store::read(self.store, Location::Internal, &PathBuf::from(cert_path)).ok();
+            let verified = match (key, &cert) {
+                (true, Some(cert)) => self.verify_slot(slot, cert),
+                _ => false,
+            };
+
+            cbor::push_map(&mut report, 4).ok();
+            cbor::push_text(&mut report, "slot").ok();
+            cbor::push_unsigned(&mut report, slot as u32).ok();
+            cbor::push_text(&mut report, "key").ok();
+            cbor::push_bool(&mut report, key).ok();
+            cbor::push_text(&mut report, "cert").ok();
+            cbor::push_bool(&mut report, cert.is_some()).ok();
+            cbor::push_text(&mut report, "verified").ok();
+            cbor::push_bool(&mut report, verified).ok();
+        }
+
+        reply.extend_from_slice(&report).map_err(|_| Status::NotEnoughMemory)
+    }
+
+    /// Whether the key in the slot matches the certificate.
+    fn verify_slot(&mut self, slot: u8, cert: &[u8]) -> bool {
+        let public_key = match Certificate::parse(cert).and_then(|cert| cert.public_key()) {
+            Ok(public_key) => public_key,
+            Err(_) => return false,
+        };
+
+        let mut challenge = [0u8; 32];
+        challenge.copy_from_slice(
+            &syscall!(self.trussed.random_bytes(32)).bytes.as_slice()
+        );
+        let secret = KeyId::from_special(slot);
+
+        match slot {
+            1 => match public_key.split_first() {
+                Some((0x04, point)) => self.verify_signature(Mechanism::P256, secret, point, &challenge),
+                _ => false,
+            },
+            2 => self.verify_signature(Mechanism::Ed255, secret, public_key, &challenge),
+            _ => self.verify_agreement(secret, public_key, &challenge),
+        }
+    }
+
+    fn verify_signature(&mut self, mechanism: Mechanism, secret: KeyId, public_key: &[u8], challenge: &[u8]) -> bool {
+        let signature = match try_syscall!(self.trussed.sign(
+            mechanism, secret, challenge, SignatureSerialization::Raw
+        )) {
+            Ok(reply) => reply.signature,
+            Err(_) => return false,
+        };
+        let public_key = match try_syscall!(self.trussed.deserialize_key(
+            mechanism,
+            public_key,
+            KeySerialization::Raw,
+            StorageAttributes::new().set_persistence(Location::Volatile),
+        )) {
+            Ok(reply) => reply.key,
+            Err(_) => return false,
+        };
+
+        let valid = try_syscall!(self.trussed.verify(
+            mechanism, public_key, challenge, &signature, SignatureSerialization::Raw
+        )).map(|reply| reply.valid).unwrap_or(false);
+        syscall!(self.trussed.delete(public_key));
+        valid
+    }
+
+    /// Agrees on a secret from both sides, with an ephemeral key,
+    /// and compares MACs keyed with them.
+    fn verify_agreement(&mut self, secret: KeyId, public_key: &[u8], challenge: &[u8]) -> bool {
+        if <[u8; 32]>::try_from(public_key).is_err() {
+            return false;
+        }
+        let public_key = match try_syscall!(self.trussed.deserialize_key(
+            Mechanism::X255,
+            public_key,
+            KeySerialization::Raw,
+            StorageAttributes::new().set_persistence(Location::Volatile),
+        )) {
+            Ok(reply) => reply.key,
+            Err(_) => return false,
+        };
+        let ephemeral = syscall!(self.trussed.generate_x255_secret_key(Location::Volatile)).key;
+        let ephemeral_public = syscall!(self.trussed.derive_x255_public_key(ephemeral, Location::Volatile)).key;
+
+        let mut macs = [None, None];
+        let pairs = [(secret, ephemeral_public), (ephemeral, public_key)];
+        for (mac, (private, public)) in macs.iter_mut().zip(pairs) {
+            if let Ok(reply) = try_syscall!(self.trussed.agree_x255(private, public, Location::Volatile)) {
+                *mac = Some(syscall!(self.trussed.sign_hmacsha256(reply.shared_secret, challenge)).signature);
+                syscall!(self.trussed.delete(reply.shared_secret));
+            }
+        }
+
+        for key in [public_key, ephemeral, ephemeral_public] {
+            syscall!(self.trussed.delete(key));
+        }
+        matches!(macs, [Some(ours), Some(theirs)] if ours == theirs)
+    }
+}
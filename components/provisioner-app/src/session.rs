@@ -0,0 +1,97 @@
+//! Authenticated provisioning sessions.
+//!
+//! Once there is a factory key, mutating instructions are only accepted in an
+//! authenticated session: the host gets a nonce with `GetChallenge`, and returns
+//! the factory key's Ed25519 signature with `Authenticate`, over
+//!
+//! ```text
+//! "solo2 provisioning session\0" || nonce (32) || UUID (16)
+//! ```
+//!
+//! The context string keeps the signature from being valid for anything else the key signs.
+//! The session lasts until the provisioner is selected again.
+//!
+//! The factory key is compiled in with the `factory-key` feature (the path to the
+//! raw 32 byte public key is taken from `SOLO_FACTORY_PUBLIC_KEY` at build time),
+//! otherwise it is the T1 intermediate public key, once saved to `/attn/pub/00`.
+
+use core::convert::TryFrom;
+
+use defmt::info;
+use trussed::store::Store;
+use trussed::types::LfsStorage;
+use trussed::{client, syscall, Client as TrussedClient};
+
+use apdu_dispatch::iso7816::Status;
+use apdu_dispatch::app::Result as ResponseResult;
+use apdu_dispatch::{Command, response};
+
+use crate::{Provisioner, Reboot};
+
+/// Prefixes the signed message.
+const CONTEXT: &[u8] = b"solo2 provisioning session\0";
+
+#[cfg(feature = "factory-key")]
+const FACTORY_PUBLIC_KEY: Option<&[u8; 32]> = Some(include_bytes!(env!("SOLO_FACTORY_PUBLIC_KEY")));
+#[cfg(not(feature = "factory-key"))]
+const FACTORY_PUBLIC_KEY: Option<&[u8; 32]> = None;
+
+impl<S, FS, T, R> Provisioner<S, FS, T, R>
+where S: Store,
+      FS: 'static + LfsStorage,
+      T: TrussedClient + client::X255 + client::HmacSha256,
+      R: Reboot,
+{
+    /// Replies with a fresh nonce, to be signed for `Authenticate`.
+    pub(crate) fn get_challenge(&mut self, reply: &mut response::Data) -> ResponseResult {
+        let mut challenge = [0u8; 32];
+        challenge.copy_from_slice(
+            &syscall!(self.trussed.random_bytes(32)).bytes.as_slice()
+        );
+        self.challenge = Some(challenge);
+        self.authenticated = false;
+        reply.extend_from_slice(&challenge).unwrap();
+        Ok(())
+    }
+
+    /// Verifies the signature in the command data over the context, the last challenge and the UUID.
+    ///
+    /// Each challenge can only be tried once.
+    pub(crate) fn authenticate(&mut self, command: &Command) -> ResponseResult {
+        let challenge = self.challenge.take().ok_or(Status::ConditionsOfUseNotSatisfied)?;
+        let public_key = self.factory_key()?.ok_or(Status::ConditionsOfUseNotSatisfied)?;
+        let signature = <[u8; 64]>::try_from(command.data()).map_err(|_| Status::WrongLength)?;
+
+        let mut message = [0u8; CONTEXT.len() + 48];
+        let (context, rest) = message.split_at_mut(CONTEXT.len());
+        context.copy_from_slice(CONTEXT);
+        rest[..32].copy_from_slice(&challenge);
+        rest[32..].copy_from_slice(&self.uuid);
+        public_key.verify(&message, &salty::Signature::from(&signature)).map_err(|_| {
+            info!("authentication failed");
+            Status::VerificationFailed
+        })?;
+
+        info!("authenticated");
+        self.authenticated = true;
+        Ok(())
+    }
+
+    /// Refuses mutating instructions if there is a factory key, but no session.
+    pub(crate) fn check_session(&self) -> ResponseResult {
+        if !self.authenticated && self.factory_key()?.is_some() {
+            info!("not authenticated");
+            return Err(Status::SecurityStatusNotSatisfied);
+        }
+        Ok(())
+    }
+
+    fn factory_key(&self) -> Result<Option<salty::PublicKey>, Status> {
+        match FACTORY_PUBLIC_KEY {
+            Some(public_key) => salty::PublicKey::try_from(public_key)
+                .map(Some)
+                .map_err(|_| Status::UnspecifiedCheckingError),
+            None => self.t1_public_key(),
+        }
+    }
+}
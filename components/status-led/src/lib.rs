@@ -0,0 +1,89 @@
+//! Colors and animations of the status LED.
+//!
+//! This is the hardware independent part of the board's `trussed::platform::UserInterface`,
+//! so the PC runner can show (and trace) exactly what the device would.
+
+#![no_std]
+
+use core::time::Duration;
+
+use micromath::F32;
+use trussed::platform::ui;
+
+pub mod rgb_led;
+pub use rgb_led::{Intensities, RgbLed};
+
+// color codes Conor picked
+pub const BLACK: Intensities = Intensities { red: 0, green: 0, blue: 0 };
+pub const RED: Intensities = Intensities { red: u8::MAX, green: 0, blue: 0 };
+pub const GREEN: Intensities = Intensities { red: 0, green: 15, blue: 0x02 };
+pub const BLUE: Intensities = Intensities { red: 0, green: 0, blue: 55 };
+pub const TEAL: Intensities = Intensities { red: 0, green: 55, blue: 20 };
+pub const ORANGE: Intensities = Intensities { red: u8::MAX, green: 0x7e, blue: 0 };
+pub const WHITE: Intensities = Intensities { red: u8::MAX, green: u8::MAX, blue: u8::MAX };
+
+/// Color shown as soon as the status is set.
+///
+/// `animate` runs periodically and overwrites this.
+pub fn status_color(status: ui::Status) -> Intensities {
+    match status {
+        ui::Status::Idle => GREEN,
+        // ui::Status::Idle => RED,
+        ui::Status::Processing => TEAL,
+        // ui::Status::Processing => GREEN,
+        // ui::Status::WaitingForUserPresence => ORANGE,
+        ui::Status::WaitingForUserPresence => BLUE,
+        ui::Status::Error => RED,
+    }
+}
+
+/// Color at `uptime` milliseconds, for the periodic refresh.
+pub fn animate(status: ui::Status, uptime: u32, winking: bool, any_button: bool) -> Intensities {
+    let waiting_for_user = status == ui::Status::WaitingForUserPresence;
+    let processing = status == ui::Status::Processing;
+
+    if waiting_for_user {
+
+        // breathe fast, in blue
+
+        let amplitude = calculate_amplitude(uptime, 2, 4, 75);
+        Intensities { red: 0, green: 0, blue: amplitude }
+
+    } else if processing {
+        let on = (((F32(uptime as f32) / 250.0).round().0 as u32) % 2) != 0;
+        if on { GREEN } else { BLACK }
+    } else if winking {
+
+        // blink rapidly
+
+        let on = (((F32(uptime as f32) / 250.0).round().0 as u32) % 2) != 0;
+        if on { BLUE } else { BLACK }
+        // if on { WHITE } else { BLACK }
+
+    } else {
+
+        // regular behaviour: breathe slowly
+
+        let amplitude = calculate_amplitude(uptime, 10, 4, 64);
+
+        if !any_button {
+            // Use green if no button is pressed.
+            Intensities { red: 0, green: amplitude, blue: 0 }
+            // Intensities { red: amplitude, green: 0, blue: 0 }
+        } else {
+            // Use blue if button is pressed.
+            Intensities { red: 0, green: 0, blue: amplitude }
+        }
+    }
+}
+
+fn calculate_amplitude(now_millis: u32, period_secs: u8, min_amplitude: u8, max_amplitude: u8) -> u8 {
+    let period = Duration::new(period_secs as u64, 0).as_millis() as u32;
+    let tau = F32(6.283185);
+    let angle = F32(now_millis as f32) * tau / (period as f32);
+    let rel_amplitude = max_amplitude - min_amplitude;
+
+    // sinoidal wave on top of a baseline brightness
+    let amplitude = min_amplitude + (angle.sin().abs() * (rel_amplitude as f32)).floor().0 as u8;
+    amplitude
+}
@@ -1,4 +1,4 @@
 pub mod buttons;
 pub mod reboot;
 pub use reboot::Reboot;
-pub mod rgb_led;
+pub use status_led::rgb_led;
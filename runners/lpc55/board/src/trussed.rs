@@ -8,9 +8,8 @@ use crate::hal::{
     typestates::init_state,
 };
 use crate::traits::buttons::{Press, Edge};
-use crate::traits::rgb_led::{Intensities, RgbLed};
+use crate::traits::rgb_led::RgbLed;
 use defmt::debug;
-use micromath::F32;
 use trussed::platform::{consent, ui};
 
 // Assuming there will only be one way to
@@ -65,17 +64,6 @@ RGB: RgbLed,
     }
 }
 
-// color codes Conor picked
-const BLACK: Intensities = Intensities { red: 0, green: 0, blue: 0 };
-const RED: Intensities = Intensities { red: u8::MAX, green: 0, blue: 0 };
-const GREEN: Intensities = Intensities { red: 0, green: 15, blue: 0x02 };
-const BLUE: Intensities = Intensities { red: 0, green: 0, blue: 55 };
-const TEAL: Intensities = Intensities { red: 0, green: 55, blue: 20 };
-#[allow(dead_code)]
-const ORANGE: Intensities = Intensities { red: u8::MAX, green: 0x7e, blue: 0 };
-#[allow(dead_code)]
-const WHITE: Intensities = Intensities { red: u8::MAX, green: u8::MAX, blue: u8::MAX };
-
 impl<BUTTONS, RGB> trussed::platform::UserInterface for UserInterface<BUTTONS,RGB>
 where
 BUTTONS: Press + Edge,
@@ -116,15 +104,7 @@ RGB: RgbLed,
 
         // self.refresh runs periodically and would overwrite this
         if let Some(rgb) = &mut self.rgb {
-            rgb.set(match status {
-                ui::Status::Idle => GREEN,
-                // ui::Status::Idle => RED,
-                ui::Status::Processing => TEAL,
-                // ui::Status::Processing => GREEN,
-                // ui::Status::WaitingForUserPresence => ORANGE,
-                ui::Status::WaitingForUserPresence => BLUE,
-                ui::Status::Error => RED,
-            });
+            rgb.set(status_led::status_color(status));
         }
     }
 
@@ -137,47 +117,13 @@ RGB: RgbLed,
 
         if let Some(rgb) = self.rgb.as_mut() {
 
-            let waiting_for_user = self.status == ui::Status::WaitingForUserPresence;
-            let processing = self.status == ui::Status::Processing;
             let winking = uptime < self.wink_until.as_millis() as u32;
             let any_button = self.buttons.as_mut()
                 .map(|buttons| buttons.state())
                 .map(|state| state.a || state.b || state.middle)
                 .unwrap_or(false);
 
-            let color = if waiting_for_user {
-
-                // breathe fast, in blue
-
-                let amplitude = calculate_amplitude(uptime, 2, 4, 75);
-                Intensities { red: 0, green: 0, blue: amplitude }
-
-            } else if processing {
-                let on = (((F32(uptime as f32) / 250.0).round().0 as u32) % 2) != 0;
-                if on { GREEN } else { BLACK }
-            } else if winking {
-
-                // blink rapidly
-
-                let on = (((F32(uptime as f32) / 250.0).round().0 as u32) % 2) != 0;
-                if on { BLUE } else { BLACK }
-                // if on { WHITE } else { BLACK }
-
-            } else {
-
-                // regular behaviour: breathe slowly
-
-                let amplitude = calculate_amplitude(uptime, 10, 4, 64);
-
-                if !any_button {
-                    // Use green if no button is pressed.
-                    Intensities { red: 0, green: amplitude, blue: 0 }
-                    // Intensities { red: amplitude, green: 0, blue: 0 }
-                } else {
-                    // Use blue if button is pressed.
-                    Intensities { red: 0, green: 0, blue: amplitude }
-                }
-            };
+            let color = status_led::animate(self.status, uptime, winking, any_button);
 
             // use logging::hex::*;
             // use logging::hex;
@@ -198,14 +144,3 @@ RGB: RgbLed,
     }
 
 }
-
-fn calculate_amplitude(now_millis: u32, period_secs: u8, min_amplitude: u8, max_amplitude: u8) -> u8 {
-    let period = Duration::new(period_secs as u64, 0).as_millis() as u32;
-    let tau = F32(6.283185);
-    let angle = F32(now_millis as f32) * tau / (period as f32);
-    let rel_amplitude = max_amplitude - min_amplitude;
-
-    // sinoidal wave on top of a baseline brightness
-    let amplitude = min_amplitude + (angle.sin().abs() * (rel_amplitude as f32)).floor().0 as u8;
-    amplitude
-}
@@ -136,6 +136,8 @@ pub fn init_board(
 
     let apps = types::Apps::new(
         &mut everything.trussed,
+        apps::AppSelection::ALL,
+        hal::uuid(),
         #[cfg(feature = "provisioner-app")]
         {
             types::ProvisionerNonPortable {
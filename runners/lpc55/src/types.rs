@@ -237,202 +237,25 @@ pub type ExternalInterrupt = hal::Pint<hal::typestates::init_state::Enabled>;
 pub type ApduDispatch = apdu_dispatch::dispatch::ApduDispatch;
 pub type CtaphidDispatch = ctaphid_dispatch::dispatch::Dispatch;
 
-#[cfg(feature = "admin-app")]
-pub type AdminApp = admin_app::App<TrussedClient, board::Reboot>;
-#[cfg(feature = "piv-authenticator")]
-pub type PivApp = piv_authenticator::Authenticator<TrussedClient, { apdu_dispatch::command::SIZE }>;
-#[cfg(feature = "oath-authenticator")]
-pub type OathApp = oath_authenticator::Authenticator<TrussedClient>;
-#[cfg(feature = "fido-authenticator")]
-pub type FidoApp = fido_authenticator::Authenticator<fido_authenticator::Conforming, TrussedClient>;
-#[cfg(feature = "fido-authenticator")]
-pub type FidoConfig = fido_authenticator::Config;
-#[cfg(feature = "ndef-app")]
-pub type NdefApp = ndef_app::App<'static>;
-#[cfg(feature = "provisioner-app")]
-pub type ProvisionerApp = provisioner_app::Provisioner<Store, FlashStorage, TrussedClient>;
-
-use apdu_dispatch::{command::SIZE as CommandSize, response::SIZE as ResponseSize, App as ApduApp};
-use ctaphid_dispatch::app::App as CtaphidApp;
-
-pub type DynamicClockController = board::clock_controller::DynamicClockController;
-pub type NfcWaitExtender = timer::Timer<ctimer::Ctimer0<hal::typestates::init_state::Enabled>>;
-pub type PerformanceTimer = timer::Timer<ctimer::Ctimer4<hal::typestates::init_state::Enabled>>;
-
-pub trait TrussedApp: Sized {
-    /// non-portable resources needed by this Trussed app
-    type NonPortable;
-
-    /// the desired client ID
-    const CLIENT_ID: &'static [u8];
-
-    fn with_client(trussed: TrussedClient, non_portable: Self::NonPortable) -> Self;
-
-    fn with(trussed: &mut trussed::Service<crate::Board>, non_portable: Self::NonPortable) -> Self {
-        let client_id = core::str::from_utf8(Self::CLIENT_ID).unwrap();
-        let client = trussed
-            .try_new_client(client_id, Syscall::default())
-            .unwrap();
-        Self::with_client(client, non_portable)
-    }
-}
-
-#[cfg(feature = "oath-authenticator")]
-impl TrussedApp for OathApp {
-    const CLIENT_ID: &'static [u8] = b"oath\0";
-
-    type NonPortable = ();
-    fn with_client(trussed: TrussedClient, _: ()) -> Self {
-        Self::new(trussed)
-    }
-}
-
-#[cfg(feature = "piv-authenticator")]
-impl TrussedApp for PivApp {
-    const CLIENT_ID: &'static [u8] = b"piv\0";
-
-    type NonPortable = ();
-    fn with_client(trussed: TrussedClient, _: ()) -> Self {
-        Self::new(trussed)
-    }
-}
-
-#[cfg(feature = "admin-app")]
-impl TrussedApp for AdminApp {
-    const CLIENT_ID: &'static [u8] = b"admin\0";
+/// The LPC55 as seen by the shared app composition.
+pub struct Runner;
 
-    // TODO: declare uuid + version
-    type NonPortable = ();
-    fn with_client(trussed: TrussedClient, _: ()) -> Self {
-        Self::new(trussed, hal::uuid(), build_constants::CARGO_PKG_VERSION)
-    }
-}
+impl apps::Runner for Runner {
+    type Syscall = Syscall;
+    type Reboot = board::Reboot;
 
-#[cfg(feature = "fido-authenticator")]
-impl TrussedApp for FidoApp {
-    const CLIENT_ID: &'static [u8] = b"fido\0";
-
-    type NonPortable = ();
-    fn with_client(trussed: TrussedClient, _: ()) -> Self {
-        let authnr = fido_authenticator::Authenticator::new(
-            trussed,
-            fido_authenticator::Conforming {},
-            FidoConfig {
-                max_msg_size: usbd_ctaphid::constants::MESSAGE_SIZE,
-                // max_creds_in_list: ctap_types::sizes::MAX_CREDENTIAL_COUNT_IN_LIST,
-                // max_cred_id_length: ctap_types::sizes::MAX_CREDENTIAL_ID_LENGTH,
-                skip_up_timeout: None,
-            },
-        );
-
-        // Self::new(authnr)
-        authnr
-    }
-}
+    #[cfg(feature = "provisioner-app")]
+    type Store = Store;
+    #[cfg(feature = "provisioner-app")]
+    type Filesystem = FlashStorage;
 
-pub struct ProvisionerNonPortable {
-    pub store: Store,
-    pub stolen_filesystem: &'static mut FlashStorage,
-    pub nfc_powered: bool,
+    const VERSION: u32 = build_constants::CARGO_PKG_VERSION;
 }
 
+pub type Apps = apps::Apps<Runner>;
 #[cfg(feature = "provisioner-app")]
-impl TrussedApp for ProvisionerApp {
-    const CLIENT_ID: &'static [u8] = b"attn\0";
-
-    type NonPortable = ProvisionerNonPortable;
-    fn with_client(
-        trussed: TrussedClient,
-        ProvisionerNonPortable {
-            store,
-            stolen_filesystem,
-            nfc_powered,
-        }: Self::NonPortable,
-    ) -> Self {
-        Self::new(trussed, store, stolen_filesystem, nfc_powered)
-    }
-}
-
-pub struct Apps {
-    #[cfg(feature = "admin-app")]
-    pub admin: AdminApp,
-    #[cfg(feature = "fido-authenticator")]
-    pub fido: FidoApp,
-    #[cfg(feature = "oath-authenticator")]
-    pub oath: OathApp,
-    #[cfg(feature = "ndef-app")]
-    pub ndef: NdefApp,
-    #[cfg(feature = "piv-authenticator")]
-    pub piv: PivApp,
-    #[cfg(feature = "provisioner-app")]
-    pub provisioner: ProvisionerApp,
-}
+pub type ProvisionerNonPortable = apps::ProvisionerNonPortable<Store, FlashStorage>;
 
-impl Apps {
-    pub fn new(
-        trussed: &mut trussed::Service<crate::Board>,
-        #[cfg(feature = "provisioner-app")] provisioner: ProvisionerNonPortable,
-    ) -> Self {
-        #[cfg(feature = "admin-app")]
-        let admin = AdminApp::with(trussed, ());
-        #[cfg(feature = "fido-authenticator")]
-        let fido = FidoApp::with(trussed, ());
-        #[cfg(feature = "oath-authenticator")]
-        let oath = OathApp::with(trussed, ());
-        #[cfg(feature = "piv-authenticator")]
-        let piv = PivApp::with(trussed, ());
-        #[cfg(feature = "ndef-app")]
-        let ndef = NdefApp::new();
-        #[cfg(feature = "provisioner-app")]
-        let provisioner = ProvisionerApp::with(trussed, provisioner);
-
-        Self {
-            #[cfg(feature = "admin-app")]
-            admin,
-            #[cfg(feature = "fido-authenticator")]
-            fido,
-            #[cfg(feature = "oath-authenticator")]
-            oath,
-            #[cfg(feature = "ndef-app")]
-            ndef,
-            #[cfg(feature = "piv-authenticator")]
-            piv,
-            #[cfg(feature = "provisioner-app")]
-            provisioner,
-        }
-    }
-
-    #[inline(never)]
-    pub fn apdu_dispatch<F, T>(&mut self, f: F) -> T
-    where
-        F: FnOnce(&mut [&mut dyn ApduApp<CommandSize, ResponseSize>]) -> T,
-    {
-        f(&mut [
-            #[cfg(feature = "ndef-app")]
-            &mut self.ndef,
-            #[cfg(feature = "piv-authenticator")]
-            &mut self.piv,
-            #[cfg(feature = "oath-authenticator")]
-            &mut self.oath,
-            #[cfg(feature = "fido-authenticator")]
-            &mut self.fido,
-            #[cfg(feature = "admin-app")]
-            &mut self.admin,
-            #[cfg(feature = "provisioner-app")]
-            &mut self.provisioner,
-        ])
-    }
-
-    #[inline(never)]
-    pub fn ctaphid_dispatch<F, T>(&mut self, f: F) -> T
-    where
-        F: FnOnce(&mut [&mut dyn CtaphidApp]) -> T,
-    {
-        f(&mut [
-            #[cfg(feature = "fido-authenticator")]
-            &mut self.fido,
-            #[cfg(feature = "admin-app")]
-            &mut self.admin,
-        ])
-    }
-}
+pub type DynamicClockController = board::clock_controller::DynamicClockController;
+pub type NfcWaitExtender = timer::Timer<ctimer::Ctimer0<hal::typestates::init_state::Enabled>>;
+pub type PerformanceTimer = timer::Timer<ctimer::Ctimer4<hal::typestates::init_state::Enabled>>;
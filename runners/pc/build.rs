@@ -0,0 +1,31 @@
+use std::{env, fs, path::Path};
+
+/// Where the internal filesystem starts on the LPC55,
+/// keep in sync with `CONFIG_FILESYSTEM_BOUNDARY` in its `build.rs`.
+const DEFAULT_FILESYSTEM_BOUNDARY: usize = 0x8_0000;
+
+/// Parses decimal, or hexadecimal with a `0x` prefix (`_` separators allowed).
+fn parse(value: &str) -> Option<usize> {
+    let value = value.trim().replace('_', "");
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SOLO2_FILESYSTEM_BOUNDARY");
+
+    // littlefs takes the geometry as constants, so it is chosen at build time
+    let boundary = match env::var("SOLO2_FILESYSTEM_BOUNDARY") {
+        Ok(value) => parse(&value)
+            .unwrap_or_else(|| panic!("SOLO2_FILESYSTEM_BOUNDARY {:?} is not a number", value)),
+        Err(_) => DEFAULT_FILESYSTEM_BOUNDARY,
+    };
+
+    let out_dir = env::var("OUT_DIR").expect("No out dir");
+    fs::write(
+        Path::new(&out_dir).join("build_constants.rs"),
+        format!("pub mod build_constants {{\n    pub const CONFIG_FILESYSTEM_BOUNDARY: usize = {:#x};\n}}\n", boundary),
+    ).expect("Could not write build_constants.rs");
+}
@@ -1,245 +1,204 @@
-use std::{fs::File, io::Write};
-pub use embedded_hal::blocking::rng;
-use littlefs2::{const_ram_storage, consts};
-use littlefs2::fs::{Allocation, Filesystem};
-use trussed::types::{LfsResult, LfsStorage};
-
-use trussed::platform::{
-    ui,
-    reboot,
-    consent,
-};
-use trussed::{platform, store};
-
-pub use generic_array::{
-    GenericArray,
-    typenum::{U16, U512},
-};
-
-use generic_array::typenum::{U256, U1022};
-
-
-const SOLO_STATE: &'static str = "solo-state.bin";
-
-#[allow(non_camel_case_types)]
-pub mod littlefs_params {
-    use super::*;
-    pub const READ_SIZE: usize = 16;
-    pub const WRITE_SIZE: usize = 512;
-    pub const BLOCK_SIZE: usize = 512;
-
-    pub const BLOCK_COUNT: usize = 256;
-    // no wear-leveling for now
-    pub const BLOCK_CYCLES: isize = -1;
-
-    pub type CACHE_SIZE = U512;
-    pub type LOOKAHEADWORDS_SIZE = U16;
-    /// TODO: We can't actually be changed currently
-    pub type FILENAME_MAX_PLUS_ONE = U256;
-    pub type PATH_MAX_PLUS_ONE = U256;
-    pub const FILEBYTES_MAX: usize = littlefs2::ll::LFS_FILE_MAX as _;
-    /// TODO: We can't actually be changed currently
-    pub type ATTRBYTES_MAX = U1022;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::Parser;
+
+use solo_pc::control::{ClockMode, Controls};
+use solo_pc::device::{Config, Device, Interfaces};
+use solo_pc::flash::{self, FileFlash};
+use solo_pc::trace::Trace;
+use solo_pc::transport::{uhid::CtaphidSocket, usbip::UsbClasses, vpcd::{self, Vpcd}, Transport};
+use solo_pc::types::AppSelection;
+use solo_pc::ui::Presence;
+
+/// Virtual Solo 2: the firmware's apps on a simulated Trussed platform.
+#[derive(Parser)]
+#[clap(version)]
+struct Args {
+    /// File persisting the internal filesystem
+    #[clap(long, default_value = flash::SOLO_STATE)]
+    state: PathBuf,
+
+    /// Seed for the RNG, as 32 bytes of hex, or `random`
+    #[clap(long, default_value = "random")]
+    seed: Seed,
+
+    /// How the host reaches the device: usbip, uhid, vpcd (uhid and vpcd can be combined)
+    #[clap(long, default_value = "usbip", use_value_delimiter = true)]
+    transport: Vec<Transport>,
+
+    /// Apps to run, comma-separated: admin, fido, oath, ndef, piv, provisioner, or all
+    #[clap(long, default_value = "all")]
+    apps: AppSelection,
+
+    /// How user presence checks are answered: always, never, prompt, script
+    #[clap(long, default_value = "always")]
+    presence: Presence,
+
+    /// File of presence decisions for `--presence script`, one per line:
+    /// grant, strong, deny or timeout <ms>
+    #[clap(long)]
+    presence_script: Option<PathBuf>,
+
+    /// Unix socket to control the device from tests (see `solo_pc::control`)
+    #[clap(long)]
+    control: Option<PathBuf>,
+
+    /// Source of uptime: real, or virtual (advanced via the control socket)
+    #[clap(long, default_value = "real")]
+    clock: ClockMode,
+
+    /// File to write a JSON-lines trace of status changes and LED colors to
+    #[clap(long)]
+    trace: Option<PathBuf>,
+
+    /// Device UUID, as 16 bytes of hex
+    #[clap(long, parse(try_from_str = parse_uuid))]
+    uuid: Option<[u8; 16]>,
+
+    /// Unix socket for the uhid transport
+    #[clap(long, default_value = "solo2-ctaphid.sock")]
+    ctaphid_socket: PathBuf,
+
+    /// Address of vpcd, for the vpcd transport
+    #[clap(long, default_value = vpcd::DEFAULT_ADDRESS)]
+    vpcd: String,
+
+    /// ATR of the virtual smartcard, in hex
+    #[clap(long)]
+    atr: Option<HexBytes>,
 }
 
-pub struct FileFlash {
-    state: [u8; 128 * 1024],
-}
-impl FileFlash {
-    pub fn new() -> Self {
-        let mut state = [0u8; 128 * 1024];
-
-        if let Ok(contents) = std::fs::read(SOLO_STATE) {
-            println!("loaded {}", SOLO_STATE);
-            state.copy_from_slice( contents.as_slice() );
-            Self {state}
-        } else {
-            println!("No state yet, creating");
-            Self {state}
-        }
+struct HexBytes(Vec<u8>);
+
+impl FromStr for HexBytes {
+    type Err = String;
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        parse_hex(hex).map(HexBytes)
     }
 }
 
-impl littlefs2::driver::Storage for FileFlash {
-    const READ_SIZE: usize = littlefs_params::READ_SIZE;
-    const WRITE_SIZE: usize = littlefs_params::WRITE_SIZE;
-    const BLOCK_SIZE: usize = littlefs_params::BLOCK_SIZE;
-
-    const BLOCK_COUNT: usize = littlefs_params::BLOCK_COUNT;
-    const BLOCK_CYCLES: isize = littlefs_params::BLOCK_CYCLES;
-
-    type CACHE_SIZE = littlefs_params::CACHE_SIZE;
-    type LOOKAHEADWORDS_SIZE = littlefs_params::LOOKAHEADWORDS_SIZE;
-
-
-    fn read(&self, off: usize, buf: &mut [u8]) -> LfsResult<usize> {
-        for i in 0 .. buf.len() {
-            buf[i] = self.state[i + off];
-        }
-        Ok(buf.len())
-    }
+enum Seed {
+    Random,
+    Fixed([u8; 32]),
+}
 
-    fn write(&mut self, off: usize, data: &[u8]) -> LfsResult<usize> {
-        for i in 0 .. data.len() {
-            self.state[i + off] = data[i];
+impl FromStr for Seed {
+    type Err = String;
+    fn from_str(seed: &str) -> Result<Self, Self::Err> {
+        if seed == "random" {
+            return Ok(Seed::Random);
         }
-        let mut buffer = File::create(SOLO_STATE).unwrap();
-        buffer.write(&self.state).unwrap();
-
-        Ok(data.len())
-    }
-
-    fn erase(&mut self, off: usize, len: usize) -> LfsResult<usize> {
-        for i in 0 .. len {
-            self.state[i + off] = 0;
+        let mut fixed = [0u8; 32];
+        let bytes = parse_hex(seed)?;
+        if bytes.len() != fixed.len() {
+            return Err(format!("seed must be 32 bytes, not {}", bytes.len()));
         }
-        let mut buffer = File::create(SOLO_STATE).unwrap();
-        buffer.write(&self.state).unwrap();
-        Ok(len)
+        fixed.copy_from_slice(&bytes);
+        Ok(Seed::Fixed(fixed))
     }
-
 }
 
-// 8KB of RAM
-const_ram_storage!(
-    name=VolatileStorage,
-    trait=LfsStorage,
-    erase_value=0x00,
-    read_size=1,
-    write_size=1,
-    cache_size_ty=consts::U128,
-    // this is a limitation of littlefs
-    // https://git.io/JeHp9
-    block_size=128,
-    // block_size=128,
-    block_count=8192/128,
-    lookaheadwords_size_ty=consts::U8,
-    filename_max_plus_one_ty=consts::U256,
-    path_max_plus_one_ty=consts::U256,
-    result=LfsResult,
-);
-
-// minimum: 2 blocks
-// TODO: make this optional
-const_ram_storage!(ExternalStorage, 1024);
-
-store!(Store,
-    Internal: FileFlash,
-    External: ExternalStorage,
-    Volatile: VolatileStorage
-);
-
-
-
-// #[derive(Default)]
-// pub struct Rng {
-//     count: u64,
-// }
-
-// impl rng::Read for Rng {
-//     type Error = core::convert::Infallible;
-//     fn read(&mut self, buffer: &mut [u8]) -> core::result::Result<(), Self::Error> {
-//         // bad
-//         for i in 0 .. buffer.len() {
-//             self.count += 1;
-//             buffer[i] = (self.count & 0xff) as u8;
-//         }
-//         Ok(())
-//     }
-// }
-
-
-#[derive(Default)]
-pub struct UserInterface {
-}
+fn main () {
+    let args = Args::parse();
 
-impl trussed::platform::UserInterface for UserInterface
-{
-    fn check_user_presence(&mut self) -> consent::Level {
-        consent::Level::Normal
+    let usbip = args.transport.contains(&Transport::Usbip);
+    if usbip && args.transport.len() > 1 {
+        eprintln!("usbip already serves CCID and CTAPHID, it can't be combined with other transports");
+        std::process::exit(2);
     }
 
-    fn set_status(&mut self, status: ui::Status) {
-
-        println!("Set status: {:?}", status);
-
+    let filesystem = FileFlash::open(&args.state).unwrap_or_else(|error| {
+        eprintln!("{}: {}", args.state.display(), error);
+        std::process::exit(1);
+    });
+
+    let controls = Controls::new(args.clock);
+    if let Some(script) = &args.presence_script {
+        controls.load_script(script).unwrap_or_else(|error| {
+            eprintln!("{}: {}", script.display(), error);
+            std::process::exit(1);
+        });
+    }
+    if let Some(control) = &args.control {
+        controls.serve(control).expect("could not bind control socket");
+        println!("control socket on {}", control.display());
+    }
+    let trace = args.trace.as_ref().map(|path| Trace::create(path).unwrap_or_else(|error| {
+        eprintln!("{}: {}", path.display(), error);
+        std::process::exit(1);
+    }));
+    let uuid = args.uuid.unwrap_or(solo_pc::DEFAULT_UUID);
+
+    let (mut device, interfaces) = Device::boot(Config {
+        filesystem,
+        seed: match args.seed {
+            Seed::Random => None,
+            Seed::Fixed(seed) => Some(seed),
+        },
+        apps: args.apps,
+        presence: args.presence,
+        controls: controls.clone(),
+        trace,
+        uuid,
+    });
+    let Interfaces { contact: contact_requester, ctaphid: ctaphid_requester } = interfaces;
+
+    let mut usb_classes = None;
+    let mut ctaphid_socket = None;
+    let mut vpcd = None;
+    if usbip {
+        usb_classes = Some(UsbClasses::new(contact_requester, ctaphid_requester, uuid));
+        println!("USB/IP server running, attach with `usbip attach -r localhost -b 1-1`");
+    } else {
+        if args.transport.contains(&Transport::Uhid) {
+            ctaphid_socket = Some(CtaphidSocket::bind(&args.ctaphid_socket, ctaphid_requester)
+                .expect("could not bind CTAPHID socket"));
+            println!("CTAPHID on {}, bridge with `uhid-bridge.py`", args.ctaphid_socket.display());
+        }
+        if args.transport.contains(&Transport::Vpcd) {
+            let atr = args.atr.map(|atr| atr.0).unwrap_or_else(|| vpcd::DEFAULT_ATR.to_vec());
+            vpcd = Some(Vpcd::new(&args.vpcd, &atr, contact_requester));
+            println!("smartcard via vpcd on {}", args.vpcd);
+        }
     }
 
-    fn refresh(&mut self) {
+    loop {
+        if let Some(usb_classes) = usb_classes.as_mut() {
+            usb_classes.poll(controls.uptime().as_millis() as u32);
+        }
+        if let Some(ctaphid_socket) = ctaphid_socket.as_mut() {
+            ctaphid_socket.poll();
+        }
+        if let Some(vpcd) = vpcd.as_mut() {
+            vpcd.poll();
+        }
 
-    }
+        device.poll();
 
-    fn uptime(&mut self) -> core::time::Duration {
-        core::time::Duration::from_millis(1000)
+        std::thread::sleep(Duration::from_micros(100));
     }
+}
 
-    fn reboot(&mut self, to: reboot::To) -> ! {
-        println!("Restart!  ({:?})", to);
-        std::process::exit(25);
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() % 2 != 0 {
+        return Err("odd number of hex digits".into());
     }
-
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2)
+            .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            .ok_or_else(|| format!("invalid hex {:?}", hex)))
+        .collect()
 }
 
-platform!(Board,
-    R: chacha20::ChaCha8Rng,
-    S: Store,
-    UI: UserInterface,
-);
-
-fn main () {
-
-    let filesystem = FileFlash::new();
-
-    static mut INTERNAL_STORAGE: Option<FileFlash> = None;
-    unsafe { INTERNAL_STORAGE = Some(filesystem); }
-    static mut INTERNAL_FS_ALLOC: Option<Allocation<FileFlash>> = None;
-    unsafe { INTERNAL_FS_ALLOC = Some(Filesystem::allocate()); }
-
-    static mut EXTERNAL_STORAGE: ExternalStorage = ExternalStorage::new();
-    static mut EXTERNAL_FS_ALLOC: Option<Allocation<ExternalStorage>> = None;
-    unsafe { EXTERNAL_FS_ALLOC = Some(Filesystem::allocate()); }
-
-    static mut VOLATILE_STORAGE: VolatileStorage = VolatileStorage::new();
-    static mut VOLATILE_FS_ALLOC: Option<Allocation<VolatileStorage>> = None;
-    unsafe { VOLATILE_FS_ALLOC = Some(Filesystem::allocate()); }
-
-
-    let store = Store::claim().unwrap();
-
-    let result = store.mount(
-        unsafe { INTERNAL_FS_ALLOC.as_mut().unwrap() },
-        // unsafe { &mut INTERNAL_STORAGE },
-        unsafe { INTERNAL_STORAGE.as_mut().unwrap() },
-        unsafe { EXTERNAL_FS_ALLOC.as_mut().unwrap() },
-        unsafe { &mut EXTERNAL_STORAGE },
-        unsafe { VOLATILE_FS_ALLOC.as_mut().unwrap() },
-        unsafe { &mut VOLATILE_STORAGE },
-        // to trash existing data, set to true
-        false,
-    );
-
-    if result.is_err() {
-        println!("Not yet formatted!  Formatting..");
-        store.mount(
-            unsafe { INTERNAL_FS_ALLOC.as_mut().unwrap() },
-            // unsafe { &mut INTERNAL_STORAGE },
-            unsafe { INTERNAL_STORAGE.as_mut().unwrap() },
-            unsafe { EXTERNAL_FS_ALLOC.as_mut().unwrap() },
-            unsafe { &mut EXTERNAL_STORAGE },
-            unsafe { VOLATILE_FS_ALLOC.as_mut().unwrap() },
-            unsafe { &mut VOLATILE_STORAGE },
-            // to trash existing data, set to true
-            true,
-        ).unwrap();
+fn parse_uuid(hex: &str) -> Result<[u8; 16], String> {
+    let mut uuid = [0u8; 16];
+    let bytes = parse_hex(hex)?;
+    if bytes.len() != uuid.len() {
+        return Err(format!("UUID must be 16 bytes, not {}", bytes.len()));
     }
-
-
-    use trussed::service::SeedableRng;
-    let rng = chacha20::ChaCha8Rng::from_seed([0u8; 32]);
-    let pc_interface: UserInterface = Default::default();
-
-    let board = Board::new(rng, store, pc_interface);
-    let mut _trussed = trussed::service::Service::new(board);
-
-    println!("hello trussed");
+    uuid.copy_from_slice(&bytes);
+    Ok(uuid)
 }
@@ -0,0 +1,219 @@
+//! Test control of the virtual device.
+//!
+//! Tests script what the "user" does via a line-based Unix socket
+//! (or, for presence decisions, a file read at startup).
+//! Each line is one command, answered with `ok` or `error: <reason>`:
+//!
+//! - `presence grant`: the next user presence check is granted
+//! - `presence strong`: ... granted with both buttons pressed
+//! - `presence deny`: ... denied
+//! - `presence timeout <ms>`: ... is not answered, the virtual clock moves `ms` milliseconds
+//!   (the check times out when Trussed's timeout passed)
+//! - `presence clear`: forget all queued decisions
+//! - `clock advance <ms>`: move the virtual clock forward
+//! - `clock get`: answered with `ok <uptime in ms>`
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Outcome of one user presence check.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Decision {
+    Grant,
+    Strong,
+    Deny,
+    Timeout(Duration),
+}
+
+impl FromStr for Decision {
+    type Err = String;
+    fn from_str(decision: &str) -> Result<Self, Self::Err> {
+        let mut words = decision.split_whitespace();
+        let decision = match (words.next(), words.next()) {
+            (Some("grant"), None) => Decision::Grant,
+            (Some("strong"), None) => Decision::Strong,
+            (Some("deny"), None) => Decision::Deny,
+            (Some("timeout"), Some(milliseconds)) => Decision::Timeout(Duration::from_millis(
+                milliseconds.parse().map_err(|_| format!("invalid timeout {:?}", milliseconds))?,
+            )),
+            _ => return Err(format!("unknown decision {:?}, use grant, strong, deny or timeout <ms>", decision)),
+        };
+        match words.next() {
+            None => Ok(decision),
+            Some(extra) => Err(format!("unexpected {:?}", extra)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClockMode {
+    /// Uptime is the time since startup.
+    Real,
+    /// Uptime only moves when told to, for deterministic tests.
+    Virtual,
+}
+
+impl Default for ClockMode {
+    fn default() -> Self {
+        ClockMode::Real
+    }
+}
+
+impl FromStr for ClockMode {
+    type Err = String;
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "real" => Ok(ClockMode::Real),
+            "virtual" => Ok(ClockMode::Virtual),
+            other => Err(format!("unknown clock {:?}, use real or virtual", other)),
+        }
+    }
+}
+
+enum Clock {
+    Real(Instant),
+    Virtual(Duration),
+}
+
+struct State {
+    decisions: VecDeque<Decision>,
+    clock: Clock,
+}
+
+/// Handle on the controls, shared between the control socket and the platform.
+#[derive(Clone)]
+pub struct Controls {
+    inner: Arc<Mutex<State>>,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Self::new(ClockMode::default())
+    }
+}
+
+impl Controls {
+    pub fn new(clock: ClockMode) -> Self {
+        let clock = match clock {
+            ClockMode::Real => Clock::Real(Instant::now()),
+            ClockMode::Virtual => Clock::Virtual(Duration::from_millis(0)),
+        };
+        let state = State { decisions: VecDeque::new(), clock };
+        Self { inner: Arc::new(Mutex::new(state)) }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        match self.inner.lock().unwrap().clock {
+            Clock::Real(start) => start.elapsed(),
+            Clock::Virtual(uptime) => uptime,
+        }
+    }
+
+    /// Move the virtual clock forward, has no effect on the real clock.
+    pub fn advance(&self, duration: Duration) {
+        if let Clock::Virtual(uptime) = &mut self.inner.lock().unwrap().clock {
+            *uptime += duration;
+        }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        matches!(self.inner.lock().unwrap().clock, Clock::Virtual(_))
+    }
+
+    pub fn push_decision(&self, decision: Decision) {
+        self.inner.lock().unwrap().decisions.push_back(decision);
+    }
+
+    /// Take the next queued decision, if there is one.
+    pub fn take_decision(&self) -> Option<Decision> {
+        self.inner.lock().unwrap().decisions.pop_front()
+    }
+
+    /// Queue the decisions in a file, one per line (`#` starts a comment).
+    pub fn load_script(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let script = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        for (number, line) in script.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let decision = line.parse().map_err(|error| format!("line {}: {}", number + 1, error))?;
+            self.push_decision(decision);
+        }
+        Ok(())
+    }
+
+    /// Listen for control connections on `path`, in a background thread.
+    pub fn serve(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        // a stale socket from a previous run would make the bind fail
+        std::fs::remove_file(&path).ok();
+        let listener = UnixListener::bind(&path)?;
+        let controls = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let controls = controls.clone();
+                std::thread::spawn(move || controls.handle_connection(stream));
+            }
+        });
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: UnixStream) {
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => return,
+        };
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = match self.execute(line.trim()) {
+                Ok(None) => "ok".to_string(),
+                Ok(Some(value)) => format!("ok {}", value),
+                Err(error) => format!("error: {}", error),
+            };
+            if writeln!(writer, "{}", reply).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn execute(&self, command: &str) -> Result<Option<String>, String> {
+        let (topic, rest) = match command.find(char::is_whitespace) {
+            Some(i) => (&command[..i], command[i..].trim()),
+            None => (command, ""),
+        };
+        match topic {
+            "presence" if rest == "clear" => {
+                self.inner.lock().unwrap().decisions.clear();
+                Ok(None)
+            }
+            "presence" => {
+                self.push_decision(rest.parse()?);
+                Ok(None)
+            }
+            "clock" if rest == "get" => Ok(Some(self.uptime().as_millis().to_string())),
+            "clock" => {
+                let milliseconds = rest
+                    .strip_prefix("advance")
+                    .and_then(|milliseconds| milliseconds.trim().parse().ok())
+                    .ok_or_else(|| format!("unknown clock command {:?}, use advance <ms> or get", rest))?;
+                if let Clock::Real(_) = self.inner.lock().unwrap().clock {
+                    return Err("the clock is real, start with --clock virtual".into());
+                }
+                self.advance(Duration::from_millis(milliseconds));
+                Ok(None)
+            }
+            other => Err(format!("unknown command {:?}", other)),
+        }
+    }
+}
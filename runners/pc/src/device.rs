@@ -0,0 +1,147 @@
+//! The virtual device: Trussed, the apps and their dispatches, without any transport.
+//!
+//! `main` connects the `Interfaces` to USB/IP, a socket or vpcd;
+//! the integration tests in `tests/` drive them directly, in-process.
+
+use std::time::Instant;
+
+use apdu_dispatch::interchanges::{Contact, Contactless};
+use ctaphid_dispatch::types::HidInterchange;
+use interchange::{Interchange, Requester};
+use littlefs2::fs::{Allocation, Filesystem};
+
+use crate::control::Controls;
+use crate::flash::{self, FileFlash};
+use crate::trace::Trace;
+use crate::types::{self, AppSelection, ExternalStorage, Store, VolatileStorage};
+use crate::ui::{Presence, UserInterface};
+
+const REFRESH_MILLISECS: u128 = 50;
+
+pub struct Config {
+    pub filesystem: FileFlash,
+    /// Seed for the RNG, or `None` for a random one
+    pub seed: Option<[u8; 32]>,
+    pub apps: AppSelection,
+    pub presence: Presence,
+    pub controls: Controls,
+    pub trace: Option<Trace>,
+    pub uuid: [u8; 16],
+}
+
+/// The host's side of the device: the USB interfaces, minus USB.
+pub struct Interfaces {
+    pub contact: Requester<Contact>,
+    pub ctaphid: Requester<HidInterchange>,
+}
+
+pub struct Device {
+    store: Store,
+    apps: types::Apps,
+    apdu_dispatch: types::ApduDispatch,
+    ctaphid_dispatch: types::CtaphidDispatch,
+    last_refresh: Instant,
+}
+
+/// Mounts the filesystems (formatting them if needed) on the installed internal flash.
+///
+/// Also returns the internal flash again, for the provisioner app to steal.
+fn mount(filesystem: FileFlash) -> (Store, &'static mut FileFlash) {
+    let internal_storage = flash::install(filesystem) as *mut FileFlash;
+    static mut INTERNAL_FS_ALLOC: Option<Allocation<FileFlash>> = None;
+    unsafe { INTERNAL_FS_ALLOC = Some(Filesystem::allocate()); }
+
+    static mut EXTERNAL_STORAGE: ExternalStorage = ExternalStorage::new();
+    static mut EXTERNAL_FS_ALLOC: Option<Allocation<ExternalStorage>> = None;
+    unsafe { EXTERNAL_FS_ALLOC = Some(Filesystem::allocate()); }
+
+    static mut VOLATILE_STORAGE: VolatileStorage = VolatileStorage::new();
+    static mut VOLATILE_FS_ALLOC: Option<Allocation<VolatileStorage>> = None;
+    unsafe { VOLATILE_FS_ALLOC = Some(Filesystem::allocate()); }
+
+    let store = Store::claim().expect("there can only be one device per process");
+
+    let try_mount = |format| store.mount(
+        unsafe { INTERNAL_FS_ALLOC.as_mut().unwrap() },
+        unsafe { &mut *internal_storage },
+        unsafe { EXTERNAL_FS_ALLOC.as_mut().unwrap() },
+        unsafe { &mut EXTERNAL_STORAGE },
+        unsafe { VOLATILE_FS_ALLOC.as_mut().unwrap() },
+        unsafe { &mut VOLATILE_STORAGE },
+        // to trash existing data, set to true
+        format,
+    );
+
+    if try_mount(false).is_err() {
+        println!("Not yet formatted!  Formatting..");
+        try_mount(true).unwrap();
+    }
+
+    (store, unsafe { &mut *internal_storage })
+}
+
+impl Device {
+    /// Boots the device, as there are singletons involved this only works once per process.
+    pub fn boot(config: Config) -> (Self, Interfaces) {
+        let (store, stolen_filesystem) = mount(config.filesystem);
+
+        use trussed::service::SeedableRng;
+        let rng = match config.seed {
+            None => chacha20::ChaCha8Rng::from_entropy(),
+            Some(seed) => chacha20::ChaCha8Rng::from_seed(seed),
+        };
+        let pc_interface = UserInterface::new(config.presence, config.controls, config.trace);
+
+        let board = types::Board::new(rng, store, pc_interface);
+        types::install_trussed(trussed::service::Service::new(board));
+
+        // creating the clients makes no syscalls
+        let apps = types::with_trussed(|trussed| types::Apps::new(
+            trussed,
+            config.apps,
+            config.uuid,
+            types::ProvisionerNonPortable {
+                store,
+                stolen_filesystem,
+                nfc_powered: false,
+            },
+        ));
+
+        let (contact, contact_responder) = Contact::claim()
+            .expect("could not setup ccid ApduInterchange");
+        // no NFC on the PC, but the dispatch wants both interfaces
+        let (_contactless, contactless_responder) = Contactless::claim()
+            .expect("could not setup iso14443 ApduInterchange");
+        let (ctaphid, ctaphid_responder) = HidInterchange::claim()
+            .expect("could not setup HidInterchange");
+
+        let device = Self {
+            store,
+            apps,
+            apdu_dispatch: types::ApduDispatch::new(contact_responder, contactless_responder),
+            ctaphid_dispatch: types::CtaphidDispatch::new(ctaphid_responder),
+            last_refresh: Instant::now(),
+        };
+        (device, Interfaces { contact, ctaphid })
+    }
+
+    /// Lets the apps process pending requests, then persists the state.
+    pub fn poll(&mut self) {
+        let apdu_dispatch = &mut self.apdu_dispatch;
+        let ctaphid_dispatch = &mut self.ctaphid_dispatch;
+        self.apps.apdu_dispatch(|apps| apdu_dispatch.poll(apps));
+        self.apps.ctaphid_dispatch(|apps| ctaphid_dispatch.poll(apps));
+
+        flash::flush();
+
+        if self.last_refresh.elapsed().as_millis() >= REFRESH_MILLISECS {
+            types::with_trussed(|trussed| trussed.update_ui());
+            self.last_refresh = Instant::now();
+        }
+    }
+
+    /// The store the apps use, to inspect the filesystems.
+    pub fn store(&self) -> Store {
+        self.store
+    }
+}
@@ -0,0 +1,337 @@
+//! Internal flash of the PC runner, persisted in a state file.
+//!
+//! The file starts with a small header recording the geometry, so a state file from
+//! a differently configured runner is rejected instead of being misread by littlefs.
+//! Writes and erases only touch memory and mark the blocks they hit, `flush` then persists
+//! those blocks. The first flush atomically creates the state file (write to a temporary
+//! file, then rename). Later ones write the changed blocks to a journal (atomically, the same
+//! way), update them in place, and remove the journal; a journal left behind by a crash
+//! is replayed on `open`. So a crash at any point leaves either the old or the new state,
+//! just like power loss on the device.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use trussed::types::LfsResult;
+
+use crate::types::littlefs_params;
+
+pub const SOLO_STATE: &'static str = "solo-state.bin";
+
+const MAGIC: [u8; 8] = *b"SOLO2FS\0";
+const FORMAT_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 20;
+
+/// The journal is this magic, then for each changed block its index (`u32`) and contents.
+const JOURNAL_MAGIC: [u8; 8] = *b"SOLO2JN\0";
+
+/// Value of erased NOR flash.
+const ERASED: u8 = 0xff;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// No header, e.g. a state file from before the header was introduced.
+    NotAStateFile,
+    UnsupportedVersion(u32),
+    GeometryMismatch {
+        block_size: usize,
+        block_count: usize,
+    },
+    Truncated,
+    /// A journal that is not a whole number of blocks.
+    CorruptJournal,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "could not access state file: {}", error),
+            Error::NotAStateFile => write!(f, "not a state file (or from an older runner), remove it to start over"),
+            Error::UnsupportedVersion(version) => write!(f, "state file has unsupported format version {}", version),
+            Error::GeometryMismatch { block_size, block_count } => write!(
+                f,
+                "state file has {} blocks of {} bytes, but this runner uses {} blocks of {} bytes \
+                 (see SOLO2_FILESYSTEM_BOUNDARY)",
+                block_count, block_size, littlefs_params::BLOCK_COUNT, littlefs_params::BLOCK_SIZE,
+            ),
+            Error::Truncated => write!(f, "state file is truncated"),
+            Error::CorruptJournal => write!(f, "state file journal is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct FileFlash {
+    path: PathBuf,
+    state: Vec<u8>,
+    /// Which blocks changed since the last flush.
+    dirty: Vec<bool>,
+    /// Whether the state file exists, until then flushes write the whole image.
+    persisted: bool,
+}
+
+impl FileFlash {
+    /// Load the state file at `path`, or start with erased flash if there is none yet.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let size = littlefs_params::BLOCK_SIZE * littlefs_params::BLOCK_COUNT;
+
+        let (state, persisted) = match fs::read(&path) {
+            Ok(contents) => {
+                let state = Self::parse(&contents)?;
+                println!("loaded {}", path.display());
+                (state.to_vec(), true)
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                println!("No state yet, creating");
+                (vec![ERASED; size], false)
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut flash = Self {
+            path,
+            state,
+            dirty: vec![false; littlefs_params::BLOCK_COUNT],
+            persisted,
+        };
+        if persisted {
+            flash.replay_journal()?;
+        }
+        Ok(flash)
+    }
+
+    fn parse(contents: &[u8]) -> Result<&[u8], Error> {
+        if contents.len() < HEADER_SIZE || contents[..8] != MAGIC {
+            return Err(Error::NotAStateFile);
+        }
+        let field = |i: usize| u32::from_le_bytes([contents[i], contents[i + 1], contents[i + 2], contents[i + 3]]);
+
+        let version = field(8);
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let block_size = field(12) as usize;
+        let block_count = field(16) as usize;
+        if block_size != littlefs_params::BLOCK_SIZE || block_count != littlefs_params::BLOCK_COUNT {
+            return Err(Error::GeometryMismatch { block_size, block_count });
+        }
+
+        let data = &contents[HEADER_SIZE..];
+        if data.len() != block_size * block_count {
+            return Err(Error::Truncated);
+        }
+        Ok(data)
+    }
+
+    /// Finish the flush a crash interrupted, if any.
+    fn replay_journal(&mut self) -> Result<(), Error> {
+        let journal = match fs::read(self.sibling(".journal")) {
+            Ok(journal) => journal,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        // only complete journals are renamed into place
+        let entry_size = 4 + littlefs_params::BLOCK_SIZE;
+        if journal.len() < JOURNAL_MAGIC.len() || journal[..8] != JOURNAL_MAGIC
+            || (journal.len() - JOURNAL_MAGIC.len()) % entry_size != 0
+        {
+            return Err(Error::CorruptJournal);
+        }
+        for entry in journal[JOURNAL_MAGIC.len()..].chunks(entry_size) {
+            let block = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize;
+            if block >= littlefs_params::BLOCK_COUNT {
+                return Err(Error::CorruptJournal);
+            }
+            self.block_mut(block).copy_from_slice(&entry[4..]);
+            self.dirty[block] = true;
+        }
+
+        println!("replaying the journal of {}", self.path.display());
+        self.flush()?;
+        Ok(())
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.contains(&true)
+    }
+
+    /// Persist all changes since the last flush, atomically.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if !self.is_dirty() {
+            return Ok(());
+        }
+
+        if self.persisted {
+            self.write_blocks()?;
+        } else {
+            self.write_image()?;
+            self.persisted = true;
+        }
+
+        for dirty in &mut self.dirty {
+            *dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Create the state file with the whole image.
+    fn write_image(&self) -> io::Result<()> {
+        let temporary = self.sibling(".tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&MAGIC)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        file.write_all(&(littlefs_params::BLOCK_SIZE as u32).to_le_bytes())?;
+        file.write_all(&(littlefs_params::BLOCK_COUNT as u32).to_le_bytes())?;
+        file.write_all(&self.state)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&temporary, &self.path)?;
+        self.sync_directory();
+        Ok(())
+    }
+
+    /// Update the changed blocks of the state file, through the journal.
+    fn write_blocks(&self) -> io::Result<()> {
+        let dirty: Vec<usize> = (0..self.dirty.len()).filter(|&block| self.dirty[block]).collect();
+
+        let journal = self.sibling(".journal");
+        let temporary = self.sibling(".journal.tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&JOURNAL_MAGIC)?;
+        for &block in &dirty {
+            file.write_all(&(block as u32).to_le_bytes())?;
+            file.write_all(self.block(block))?;
+        }
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temporary, &journal)?;
+        self.sync_directory();
+
+        // from here on, a crash is recovered by replaying the journal
+        let file = OpenOptions::new().write(true).open(&self.path)?;
+        for &block in &dirty {
+            let offset = HEADER_SIZE + block * littlefs_params::BLOCK_SIZE;
+            file.write_all_at(self.block(block), offset as u64)?;
+        }
+        file.sync_all()?;
+        drop(file);
+
+        fs::remove_file(&journal)?;
+        self.sync_directory();
+        Ok(())
+    }
+
+    /// The state file's path with `suffix` appended.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(suffix);
+        PathBuf::from(path)
+    }
+
+    /// Make renames and removals in the state file's directory durable.
+    fn sync_directory(&self) {
+        if let Some(directory) = self.path.parent() {
+            let directory = if directory.as_os_str().is_empty() { Path::new(".") } else { directory };
+            File::open(directory).and_then(|directory| directory.sync_all()).ok();
+        }
+    }
+
+    fn block(&self, block: usize) -> &[u8] {
+        let start = block * littlefs_params::BLOCK_SIZE;
+        &self.state[start..start + littlefs_params::BLOCK_SIZE]
+    }
+
+    fn block_mut(&mut self, block: usize) -> &mut [u8] {
+        let start = block * littlefs_params::BLOCK_SIZE;
+        &mut self.state[start..start + littlefs_params::BLOCK_SIZE]
+    }
+
+    fn check_bounds(&self, off: usize, len: usize) -> LfsResult<()> {
+        match off.checked_add(len) {
+            Some(end) if end <= self.state.len() => Ok(()),
+            _ => Err(littlefs2::io::Error::Io),
+        }
+    }
+
+    /// Mark the blocks `len` bytes at `off` touch as changed.
+    fn mark_dirty(&mut self, off: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let first = off / littlefs_params::BLOCK_SIZE;
+        let last = (off + len - 1) / littlefs_params::BLOCK_SIZE;
+        for dirty in &mut self.dirty[first..=last] {
+            *dirty = true;
+        }
+    }
+}
+
+impl littlefs2::driver::Storage for FileFlash {
+    const READ_SIZE: usize = littlefs_params::READ_SIZE;
+    const WRITE_SIZE: usize = littlefs_params::WRITE_SIZE;
+    const BLOCK_SIZE: usize = littlefs_params::BLOCK_SIZE;
+
+    const BLOCK_COUNT: usize = littlefs_params::BLOCK_COUNT;
+    const BLOCK_CYCLES: isize = littlefs_params::BLOCK_CYCLES;
+
+    type CACHE_SIZE = littlefs_params::CACHE_SIZE;
+    type LOOKAHEADWORDS_SIZE = littlefs_params::LOOKAHEADWORDS_SIZE;
+
+
+    fn read(&self, off: usize, buf: &mut [u8]) -> LfsResult<usize> {
+        self.check_bounds(off, buf.len())?;
+        buf.copy_from_slice(&self.state[off..off + buf.len()]);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> LfsResult<usize> {
+        self.check_bounds(off, data.len())?;
+        self.state[off..off + data.len()].copy_from_slice(data);
+        self.mark_dirty(off, data.len());
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> LfsResult<usize> {
+        self.check_bounds(off, len)?;
+        for byte in &mut self.state[off..off + len] {
+            *byte = ERASED;
+        }
+        self.mark_dirty(off, len);
+        Ok(len)
+    }
+
+}
+
+static mut INTERNAL_STORAGE: Option<FileFlash> = None;
+
+/// Moves the internal flash to its final location, so it can be flushed from anywhere.
+pub fn install(flash: FileFlash) -> &'static mut FileFlash {
+    unsafe {
+        INTERNAL_STORAGE = Some(flash);
+        INTERNAL_STORAGE.as_mut().unwrap()
+    }
+}
+
+/// Flush the installed internal flash, if any.
+pub fn flush() {
+    if let Some(flash) = unsafe { INTERNAL_STORAGE.as_mut() } {
+        if let Err(error) = flash.flush() {
+            println!("could not persist state: {}", error);
+        }
+    }
+}
@@ -1 +1,39 @@
+//! PC runner: the Solo 2 app set on a simulated Trussed platform.
+//!
+//! The device is exposed to the host via one of the `transport`s,
+//! so the usual host tooling (`tests/*.py`, `solo2-cli`, browsers) can talk to it.
 
+pub mod control;
+pub mod device;
+pub mod flash;
+pub mod trace;
+pub mod transport;
+pub mod types;
+pub mod ui;
+
+const fn parse_version_component(component: &str) -> u32 {
+    let bytes = component.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = 10 * value + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    value
+}
+
+pub const VERSION_MAJOR: u32 = parse_version_component(env!("CARGO_PKG_VERSION_MAJOR"));
+pub const VERSION_MINOR: u32 = parse_version_component(env!("CARGO_PKG_VERSION_MINOR"));
+pub const VERSION_PATCH: u32 = parse_version_component(env!("CARGO_PKG_VERSION_PATCH"));
+
+/// Integer version, in the same encoding the LPC55 runner's `build.rs` uses.
+pub const VERSION: u32 = (VERSION_MAJOR << 22) | (VERSION_MINOR << 6) | VERSION_PATCH;
+
+/// There is no hardware UUID, so the PC runner makes one up.
+pub const DEFAULT_UUID: [u8; 16] = *b"solo2-pc-runner\0";
+
+/// Persist the state, then exit (the PC runner's "reboot").
+pub fn exit(code: i32) -> ! {
+    flash::flush();
+    std::process::exit(code)
+}
@@ -0,0 +1,53 @@
+//! Trace of what the device shows the user, for tests and debugging.
+//!
+//! One JSON object per line, each with the `uptime` in milliseconds:
+//!
+//! ```text
+//! {"uptime":1050,"status":"Processing"}
+//! {"uptime":1100,"led":{"red":0,"green":15,"blue":2}}
+//! {"uptime":1200,"wink":8000}
+//! ```
+//!
+//! LED entries are only written when the color changes.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use status_led::Intensities;
+use trussed::platform::ui;
+
+pub struct Trace {
+    out: BufWriter<File>,
+}
+
+impl Trace {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self { out: BufWriter::new(File::create(path)?) })
+    }
+
+    pub fn status(&mut self, uptime: Duration, status: ui::Status) {
+        self.line(uptime, format_args!("\"status\":\"{:?}\"", status));
+    }
+
+    pub fn led(&mut self, uptime: Duration, color: Intensities) {
+        self.line(uptime, format_args!(
+            "\"led\":{{\"red\":{},\"green\":{},\"blue\":{}}}",
+            color.red, color.green, color.blue,
+        ));
+    }
+
+    pub fn wink(&mut self, uptime: Duration, duration: Duration) {
+        self.line(uptime, format_args!("\"wink\":{}", duration.as_millis()));
+    }
+
+    fn line(&mut self, uptime: Duration, entry: std::fmt::Arguments) {
+        // flushed per line, so the trace can be followed while the runner is up
+        let written = writeln!(self.out, "{{\"uptime\":{},{}}}", uptime.as_millis(), entry)
+            .and_then(|_| self.out.flush());
+        if let Err(error) = written {
+            println!("could not write trace: {}", error);
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! The ways the host can reach the virtual device.
+
+use std::str::FromStr;
+
+pub mod uhid;
+pub mod usbip;
+pub mod vpcd;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Transport {
+    /// CCID + CTAPHID as a USB device, see `usbip`.
+    Usbip,
+    /// CTAPHID on a Unix socket, see `uhid`.
+    Uhid,
+    /// CCID as a PC/SC reader, see `vpcd`.
+    Vpcd,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+    fn from_str(transport: &str) -> Result<Self, Self::Err> {
+        match transport {
+            "usbip" => Ok(Transport::Usbip),
+            "uhid" => Ok(Transport::Uhid),
+            "vpcd" => Ok(Transport::Vpcd),
+            other => Err(format!("unknown transport {:?}, use usbip, uhid or vpcd", other)),
+        }
+    }
+}
@@ -0,0 +1,346 @@
+//! CTAPHID over a Unix domain socket.
+//!
+//! The socket carries raw 64 byte HID reports (without report ID) in both directions,
+//! so a small shim (see `uhid-bridge.py`) can forward them to and from `/dev/uhid`,
+//! making the virtual key show up as a regular FIDO HID device.
+//!
+//! Only the framing lives here: INIT and PING are answered directly, everything else
+//! goes through the same `ctaphid_dispatch` as on the device. As `usbd-ctaphid` does over USB,
+//! a message that stalls is dropped with a timeout error, and requests being processed get
+//! keepalives. A client that goes away takes its transaction with it.
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use core::convert::TryFrom;
+
+use ctaphid_dispatch::command::Command;
+use ctaphid_dispatch::types::{HidInterchange, Message};
+use interchange::Requester;
+
+pub const PACKET_SIZE: usize = 64;
+
+const INIT_PAYLOAD_SIZE: usize = PACKET_SIZE - 7;
+const CONTINUATION_PAYLOAD_SIZE: usize = PACKET_SIZE - 5;
+
+const BROADCAST_CHANNEL: u32 = 0xffff_ffff;
+
+const CTAPHID_PROTOCOL_VERSION: u8 = 2;
+
+const CAPABILITY_WINK: u8 = 0x01;
+const CAPABILITY_CBOR: u8 = 0x04;
+
+const KEEPALIVE: u8 = 0x3b;
+/// User presence checks block the main loop, so there is no `STATUS_UPNEEDED` to send.
+const STATUS_PROCESSING: u8 = 0x01;
+
+/// How long the next packet of a message may take before the message is dropped.
+pub const MESSAGE_TIMEOUT: Duration = Duration::from_millis(550);
+/// How often a request being processed is kept alive.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Error {
+    InvalidCommand = 0x01,
+    InvalidLength = 0x03,
+    InvalidSeq = 0x04,
+    MessageTimeout = 0x05,
+    ChannelBusy = 0x06,
+    InvalidChannel = 0x0b,
+    Other = 0x7f,
+}
+
+enum State {
+    Idle,
+    Receiving {
+        channel: u32,
+        command: u8,
+        length: usize,
+        next_seq: u8,
+        buffer: Vec<u8>,
+        last_packet: Instant,
+    },
+    WaitingOnApp {
+        channel: u32,
+        command: u8,
+        last_keepalive: Instant,
+    },
+}
+
+pub struct CtaphidSocket {
+    listener: UnixListener,
+    stream: Option<UnixStream>,
+    incoming: Vec<u8>,
+    interchange: Requester<HidInterchange>,
+    state: State,
+    last_channel: u32,
+    version: usbd_ctaphid::Version,
+}
+
+impl CtaphidSocket {
+    pub fn bind(path: impl AsRef<Path>, interchange: Requester<HidInterchange>) -> io::Result<Self> {
+        // a stale socket from a previous run would make the bind fail
+        std::fs::remove_file(&path).ok();
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            stream: None,
+            incoming: Vec::new(),
+            interchange,
+            state: State::Idle,
+            last_channel: 0,
+            version: usbd_ctaphid::Version {
+                major: crate::VERSION_MAJOR as u8,
+                minor: crate::VERSION_MINOR as u8,
+                build: crate::VERSION_PATCH as u8,
+            },
+        })
+    }
+
+    /// Accept a client, read its reports, and send back app responses.
+    pub fn poll(&mut self) {
+        if self.stream.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                stream.set_nonblocking(true).ok();
+                println!("CTAPHID socket: client connected");
+                self.stream = Some(stream);
+            }
+        }
+
+        self.read_packets();
+        self.check_timeout();
+
+        if let State::WaitingOnApp { channel, command, .. } = self.state {
+            if let Some(response) = self.interchange.take_response() {
+                self.state = State::Idle;
+                match response {
+                    Ok(message) => self.send_message(channel, command, &message),
+                    Err(ctaphid_dispatch::app::Error::InvalidCommand) => {
+                        self.send_error(channel, Error::InvalidCommand)
+                    }
+                    Err(ctaphid_dispatch::app::Error::InvalidLength) => {
+                        self.send_error(channel, Error::InvalidLength)
+                    }
+                    Err(ctaphid_dispatch::app::Error::NoResponse) => {}
+                }
+            }
+        }
+        self.keep_alive();
+    }
+
+    /// Drop a message whose next packet is overdue.
+    fn check_timeout(&mut self) {
+        if let State::Receiving { channel, last_packet, .. } = self.state {
+            if last_packet.elapsed() >= MESSAGE_TIMEOUT {
+                self.state = State::Idle;
+                self.send_error(channel, Error::MessageTimeout);
+            }
+        }
+    }
+
+    fn keep_alive(&mut self) {
+        if let State::WaitingOnApp { channel, last_keepalive, .. } = &mut self.state {
+            if last_keepalive.elapsed() >= KEEPALIVE_INTERVAL {
+                *last_keepalive = Instant::now();
+                let channel = *channel;
+                self.send_message(channel, KEEPALIVE, &[STATUS_PROCESSING]);
+            }
+        }
+    }
+
+    /// Abort the ongoing transaction, if any, cancelling its request to the apps.
+    fn abort(&mut self) {
+        if let State::WaitingOnApp { .. } = self.state {
+            // still pending, or already answered
+            if self.interchange.cancel().is_err() {
+                self.interchange.take_response();
+            }
+        }
+        self.state = State::Idle;
+    }
+
+    /// Forget the client, and whatever it was in the middle of.
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.incoming.clear();
+        self.abort();
+    }
+
+    fn read_packets(&mut self) {
+        let mut buffer = [0u8; PACKET_SIZE];
+        loop {
+            let read = match self.stream.as_mut() {
+                Some(stream) => stream.read(&mut buffer),
+                None => return,
+            };
+            match read {
+                Ok(0) => {
+                    println!("CTAPHID socket: client disconnected");
+                    self.disconnect();
+                    return;
+                }
+                Ok(n) => self.incoming.extend_from_slice(&buffer[..n]),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    println!("CTAPHID socket: {}", error);
+                    self.disconnect();
+                    return;
+                }
+            }
+        }
+
+        // a packet may drop the client, and with it the rest
+        while self.incoming.len() >= PACKET_SIZE {
+            let packet: Vec<u8> = self.incoming.drain(..PACKET_SIZE).collect();
+            self.handle_packet(&packet);
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &[u8]) {
+        let channel = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]);
+
+        if packet[4] & 0x80 != 0 {
+            let command = packet[4] & 0x7f;
+            let length = u16::from_be_bytes([packet[5], packet[6]]) as usize;
+
+            if let Ok(Command::Init) = Command::try_from(command) {
+                // INIT may always abort an ongoing transaction on its channel
+                self.handle_init(channel, length, &packet[7..]);
+                return;
+            }
+            if channel == 0 || channel == BROADCAST_CHANNEL {
+                self.send_error(channel, Error::InvalidChannel);
+                return;
+            }
+            if !matches!(self.state, State::Idle) {
+                self.send_error(channel, Error::ChannelBusy);
+                return;
+            }
+            if length > usbd_ctaphid::constants::MESSAGE_SIZE {
+                self.send_error(channel, Error::InvalidLength);
+                return;
+            }
+
+            let mut buffer = Vec::with_capacity(length);
+            buffer.extend_from_slice(&packet[7..7 + length.min(INIT_PAYLOAD_SIZE)]);
+            self.state = State::Receiving {
+                channel, command, length, next_seq: 0, buffer, last_packet: Instant::now(),
+            };
+        } else {
+            let seq = packet[4];
+            match &mut self.state {
+                State::Receiving { channel: expected, next_seq, length, buffer, last_packet, .. } if *expected == channel => {
+                    if seq != *next_seq {
+                        self.state = State::Idle;
+                        self.send_error(channel, Error::InvalidSeq);
+                        return;
+                    }
+                    *next_seq += 1;
+                    *last_packet = Instant::now();
+                    let missing = *length - buffer.len();
+                    buffer.extend_from_slice(&packet[5..5 + missing.min(CONTINUATION_PAYLOAD_SIZE)]);
+                }
+                // spurious continuation packets are ignored, as the spec demands
+                _ => return,
+            }
+        }
+
+        if let State::Receiving { channel, command, length, buffer, .. } = &self.state {
+            if buffer.len() == *length {
+                let (channel, command) = (*channel, *command);
+                let message = Message::from_slice(buffer).unwrap();
+                self.state = State::Idle;
+                self.dispatch(channel, command, message);
+            }
+        }
+    }
+
+    fn handle_init(&mut self, channel: u32, length: usize, data: &[u8]) {
+        if length != 8 {
+            self.send_error(channel, Error::InvalidLength);
+            return;
+        }
+
+        let new_channel = if channel == BROADCAST_CHANNEL {
+            // allocating a channel leaves the transaction of another one alone
+            self.last_channel += 1;
+            self.last_channel
+        } else {
+            let ongoing = match self.state {
+                State::Idle => None,
+                State::Receiving { channel, .. } | State::WaitingOnApp { channel, .. } => Some(channel),
+            };
+            if ongoing == Some(channel) {
+                self.abort();
+            }
+            channel
+        };
+
+        let mut response = Vec::with_capacity(17);
+        response.extend_from_slice(&data[..8]);
+        response.extend_from_slice(&new_channel.to_be_bytes());
+        response.push(CTAPHID_PROTOCOL_VERSION);
+        response.push(self.version.major);
+        response.push(self.version.minor);
+        response.push(self.version.build);
+        response.push(CAPABILITY_WINK | CAPABILITY_CBOR);
+        self.send_message(channel, Command::Init.into(), &response);
+    }
+
+    fn dispatch(&mut self, channel: u32, command: u8, message: Message) {
+        match Command::try_from(command) {
+            Ok(Command::Ping) => self.send_message(channel, command, &message),
+            Ok(Command::Lock) => self.send_message(channel, command, &[]),
+            Ok(Command::Cancel) => {
+                // only meaningful while an app is processing, and then it does not get here
+            }
+            Ok(app_command) => {
+                if self.interchange.request(&(app_command, message)).is_ok() {
+                    self.state = State::WaitingOnApp { channel, command, last_keepalive: Instant::now() };
+                } else {
+                    self.send_error(channel, Error::Other);
+                }
+            }
+            Err(_) => self.send_error(channel, Error::InvalidCommand),
+        }
+    }
+
+    fn send_error(&mut self, channel: u32, error: Error) {
+        self.send_message(channel, Command::Error.into(), &[error as u8]);
+    }
+
+    fn send_message(&mut self, channel: u32, command: u8, data: &[u8]) {
+        let mut packet = [0u8; PACKET_SIZE];
+        packet[..4].copy_from_slice(&channel.to_be_bytes());
+        packet[4] = command | 0x80;
+        packet[5..7].copy_from_slice(&(data.len() as u16).to_be_bytes());
+        let first = data.len().min(INIT_PAYLOAD_SIZE);
+        packet[7..7 + first].copy_from_slice(&data[..first]);
+        self.write_packet(&packet);
+
+        for (seq, chunk) in data[first..].chunks(CONTINUATION_PAYLOAD_SIZE).enumerate() {
+            let mut packet = [0u8; PACKET_SIZE];
+            packet[..4].copy_from_slice(&channel.to_be_bytes());
+            packet[4] = seq as u8;
+            packet[5..5 + chunk.len()].copy_from_slice(chunk);
+            self.write_packet(&packet);
+        }
+    }
+
+    fn write_packet(&mut self, packet: &[u8; PACKET_SIZE]) {
+        if let Some(stream) = self.stream.as_mut() {
+            // the socket is non-blocking for reads, but a report must go out in one piece
+            stream.set_nonblocking(false).ok();
+            let result = stream.write_all(packet);
+            stream.set_nonblocking(true).ok();
+            if result.is_err() {
+                println!("CTAPHID socket: could not send, dropping client");
+                self.disconnect();
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! USB/IP transport: a composite CCID + CTAPHID device, as on the LPC55.
+//!
+//! Attach from Linux with `usbip attach -r localhost -b 1-1`
+//! (after `modprobe vhci-hcd`).
+
+use usb_device::bus::UsbBusAllocator;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usbip_device::UsbIpBus;
+
+pub type CcidClass = usbd_ccid::Ccid<
+    UsbIpBus,
+    apdu_dispatch::interchanges::Contact,
+    { apdu_dispatch::interchanges::SIZE },
+>;
+pub type CtapHidClass = usbd_ctaphid::CtapHid<'static, UsbIpBus>;
+
+type Usbd = UsbDevice<'static, UsbIpBus>;
+
+pub struct UsbClasses {
+    pub usbd: Usbd,
+    pub ccid: CcidClass,
+    pub ctaphid: CtapHidClass,
+}
+
+impl UsbClasses {
+    pub fn new(
+        contact_requester: interchange::Requester<apdu_dispatch::interchanges::Contact>,
+        ctaphid_requester: interchange::Requester<ctaphid_dispatch::types::HidInterchange>,
+        uuid: [u8; 16],
+    ) -> Self {
+        static mut USB_BUS: Option<UsbBusAllocator<UsbIpBus>> = None;
+        let usb_bus = unsafe {
+            USB_BUS = Some(UsbBusAllocator::new(UsbIpBus::new()));
+            USB_BUS.as_ref().unwrap()
+        };
+
+        // our USB classes (must be allocated in order that they're passed in `.poll(...)` later!)
+        let ccid = usbd_ccid::Ccid::new(usb_bus, contact_requester, Some(b"Solo 2"));
+        let mut ctaphid = usbd_ctaphid::CtapHid::new(usb_bus, ctaphid_requester, 0)
+            .implements_ctap1()
+            .implements_ctap2()
+            .implements_wink();
+
+        ctaphid.set_version(usbd_ctaphid::Version {
+            major: crate::VERSION_MAJOR as u8,
+            minor: crate::VERSION_MINOR as u8,
+            build: crate::VERSION_PATCH as u8,
+        });
+
+        let serial_number: String = uuid.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+        let usbd = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x1209, 0xbeee))
+            .manufacturer("SoloKeys")
+            .product("Solo 2 (PC runner)")
+            .serial_number(Box::leak(serial_number.into_boxed_str()))
+            .device_release(((crate::VERSION_MAJOR as u16) << 8) | (crate::VERSION_MINOR as u16))
+            .max_packet_size_0(64)
+            .composite_with_iads()
+            .build();
+
+        Self { usbd, ccid, ctaphid }
+    }
+
+    /// Move USB traffic along, `uptime` in milliseconds drives the CTAPHID timeouts.
+    pub fn poll(&mut self, uptime: u32) {
+        self.ctaphid.check_timeout(uptime);
+        self.ctaphid.check_for_app_response();
+        self.ccid.check_for_app_response();
+        self.usbd.poll(&mut [
+            &mut self.ccid,
+            &mut self.ctaphid,
+        ]);
+    }
+}
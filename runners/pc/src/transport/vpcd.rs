@@ -0,0 +1,169 @@
+//! Virtual smartcard reader, via the vsmartcard `vpcd` protocol.
+//!
+//! `vpcd` is a `pcscd` driver that listens on TCP port 35963 and presents whatever
+//! connects to it as a reader with an inserted card. Each message in either direction
+//! is a big endian `u16` length followed by that many bytes. One byte messages from
+//! `vpcd` are control messages, everything else is a command APDU, answered with the
+//! response APDU.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use apdu_dispatch::interchanges::{Contact, Data};
+use interchange::Requester;
+
+pub const DEFAULT_ADDRESS: &'static str = "127.0.0.1:35963";
+
+/// T=1, with the Solo 2 card issuer's data in the historical bytes, as over CCID.
+pub const DEFAULT_ATR: [u8; 17] = [
+    0x3b, 0x8c, 0x80, 0x01,
+    0x80, 0x73, 0xc0, 0x21, 0xc0, 0x56, b'S', b'o', b'l', b'o', b' ', b'2',
+    0xa4,
+];
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+const POWER_OFF: u8 = 0;
+const POWER_ON: u8 = 1;
+const RESET: u8 = 2;
+const GET_ATR: u8 = 4;
+
+pub struct Vpcd {
+    address: String,
+    atr: Vec<u8>,
+    stream: Option<TcpStream>,
+    last_attempt: Option<Instant>,
+    incoming: Vec<u8>,
+    interchange: Requester<Contact>,
+    waiting_on_app: bool,
+}
+
+impl Vpcd {
+    pub fn new(address: &str, atr: &[u8], interchange: Requester<Contact>) -> Self {
+        Self {
+            address: address.into(),
+            atr: atr.into(),
+            stream: None,
+            last_attempt: None,
+            incoming: Vec::new(),
+            interchange,
+            waiting_on_app: false,
+        }
+    }
+
+    /// (Re)connect to `vpcd`, pass on APDUs, and send back app responses.
+    pub fn poll(&mut self) {
+        if self.stream.is_none() {
+            self.connect();
+        }
+
+        self.read_messages();
+
+        if self.waiting_on_app {
+            if let Some(response) = self.interchange.take_response() {
+                self.waiting_on_app = false;
+                self.send(&response);
+            }
+        }
+    }
+
+    fn connect(&mut self) {
+        if let Some(last_attempt) = self.last_attempt {
+            if last_attempt.elapsed() < RECONNECT_INTERVAL {
+                return;
+            }
+        }
+        self.last_attempt = Some(Instant::now());
+
+        if let Ok(stream) = TcpStream::connect(&self.address) {
+            stream.set_nodelay(true).ok();
+            stream.set_nonblocking(true).ok();
+            println!("vpcd: connected to {}", self.address);
+            self.stream = Some(stream);
+            self.incoming.clear();
+        }
+    }
+
+    fn read_messages(&mut self) {
+        let mut buffer = [0u8; 1024];
+        loop {
+            let read = match self.stream.as_mut() {
+                Some(stream) => stream.read(&mut buffer),
+                None => return,
+            };
+            match read {
+                Ok(0) => {
+                    println!("vpcd: disconnected");
+                    self.stream = None;
+                    return;
+                }
+                Ok(n) => self.incoming.extend_from_slice(&buffer[..n]),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    println!("vpcd: {}", error);
+                    self.stream = None;
+                    return;
+                }
+            }
+        }
+
+        while self.incoming.len() >= 2 {
+            let length = u16::from_be_bytes([self.incoming[0], self.incoming[1]]) as usize;
+            if self.incoming.len() < 2 + length {
+                break;
+            }
+            let message: Vec<u8> = self.incoming.drain(..2 + length).skip(2).collect();
+            self.handle_message(&message);
+        }
+    }
+
+    fn handle_message(&mut self, message: &[u8]) {
+        match message {
+            [POWER_OFF] | [POWER_ON] | [RESET] => {
+                // a "new" card, so drop anything still pending
+                if self.waiting_on_app {
+                    self.interchange.cancel().ok();
+                    self.waiting_on_app = false;
+                }
+            }
+            [GET_ATR] => {
+                let atr = self.atr.clone();
+                self.send(&atr);
+            }
+            [_] => {
+                println!("vpcd: unknown control message {:?}", message);
+            }
+            apdu => {
+                if self.waiting_on_app {
+                    // vpcd is synchronous, this should not happen
+                    self.send(&[0x6f, 0x00]);
+                    return;
+                }
+                match Data::from_slice(apdu) {
+                    Ok(command) if self.interchange.request(&command).is_ok() => {
+                        self.waiting_on_app = true;
+                    }
+                    // UnspecifiedCheckingError, as the NFC transport does
+                    _ => self.send(&[0x6f, 0x00]),
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        if let Some(stream) = self.stream.as_mut() {
+            let mut message = Vec::with_capacity(2 + data.len());
+            message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            message.extend_from_slice(data);
+
+            stream.set_nonblocking(false).ok();
+            let result = stream.write_all(&message);
+            stream.set_nonblocking(true).ok();
+            if result.is_err() {
+                println!("vpcd: could not send, dropping connection");
+                self.stream = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,146 @@
+include!(concat!(env!("OUT_DIR"), "/build_constants.rs"));
+
+use littlefs2::{const_ram_storage, consts};
+use trussed::types::{LfsResult, LfsStorage};
+use trussed::{platform, store};
+use std::sync::{Mutex, TryLockError};
+
+pub use generic_array::{
+    GenericArray,
+    typenum::{U16, U512},
+};
+
+use generic_array::typenum::{U256, U1022};
+
+use crate::flash::FileFlash;
+
+#[allow(non_camel_case_types)]
+pub mod littlefs_params {
+    use super::*;
+    pub const READ_SIZE: usize = 16;
+    pub const WRITE_SIZE: usize = 512;
+    pub const BLOCK_SIZE: usize = 512;
+
+    /// Where the internal filesystem starts, as on the LPC55 unless built
+    /// with another `SOLO2_FILESYSTEM_BOUNDARY` (see `build.rs`).
+    pub const FILESYSTEM_BOUNDARY: usize = build_constants::CONFIG_FILESYSTEM_BOUNDARY;
+    /// The LPC55 internal filesystem ends at 631.5KB.
+    pub const FILESYSTEM_END: usize = 631 * 1024 + 512;
+    /// Same geometry as the LPC55 internal filesystem.
+    pub const BLOCK_COUNT: usize = (FILESYSTEM_END - FILESYSTEM_BOUNDARY) / BLOCK_SIZE;
+
+    const _: () = assert!(FILESYSTEM_BOUNDARY % BLOCK_SIZE == 0, "the filesystem boundary must be block aligned");
+    // littlefs needs at least two blocks, for its superblocks
+    const _: () = assert!(FILESYSTEM_BOUNDARY + 2 * BLOCK_SIZE <= FILESYSTEM_END, "the filesystem boundary leaves no room");
+    // no wear-leveling for now
+    pub const BLOCK_CYCLES: isize = -1;
+
+    pub type CACHE_SIZE = U512;
+    pub type LOOKAHEADWORDS_SIZE = U16;
+    /// TODO: We can't actually be changed currently
+    pub type FILENAME_MAX_PLUS_ONE = U256;
+    pub type PATH_MAX_PLUS_ONE = U256;
+    pub const FILEBYTES_MAX: usize = littlefs2::ll::LFS_FILE_MAX as _;
+    /// TODO: We can't actually be changed currently
+    pub type ATTRBYTES_MAX = U1022;
+}
+
+// 8KB of RAM
+const_ram_storage!(
+    name=VolatileStorage,
+    trait=LfsStorage,
+    erase_value=0x00,
+    read_size=1,
+    write_size=1,
+    cache_size_ty=consts::U128,
+    // this is a limitation of littlefs
+    // https://git.io/JeHp9
+    block_size=128,
+    // block_size=128,
+    block_count=8192/128,
+    lookaheadwords_size_ty=consts::U8,
+    filename_max_plus_one_ty=consts::U256,
+    path_max_plus_one_ty=consts::U256,
+    result=LfsResult,
+);
+
+// minimum: 2 blocks
+// TODO: make this optional
+const_ram_storage!(ExternalStorage, 1024);
+
+store!(Store,
+    Internal: FileFlash,
+    External: ExternalStorage,
+    Volatile: VolatileStorage
+);
+
+platform!(Board,
+    R: chacha20::ChaCha8Rng,
+    S: Store,
+    UI: crate::ui::UserInterface,
+);
+
+pub type Trussed = trussed::Service<Board>;
+pub type TrussedClient = trussed::ClientImplementation<Syscall>;
+
+// There is no OS_EVENT interrupt to pend on the PC: clients let the service
+// process their request directly. The service is only reachable through
+// `with_trussed`, so the syscalls and the UI updates never alias it.
+struct Installed(Option<Trussed>);
+
+// Only ever used behind `TRUSSED`'s lock; the tests move the device between threads.
+unsafe impl Send for Installed {}
+
+static TRUSSED: Mutex<Installed> = Mutex::new(Installed(None));
+
+/// Moves the Trussed service to its final location, where `with_trussed` can find it.
+pub fn install_trussed(trussed: Trussed) {
+    with_installed(|installed| *installed = Some(trussed));
+}
+
+/// Runs `f` on the installed Trussed service.
+///
+/// Panics if the service is not installed, or if `f` makes a syscall.
+pub fn with_trussed<T>(f: impl FnOnce(&mut Trussed) -> T) -> T {
+    with_installed(|installed| f(installed.as_mut().expect("Trussed service not installed")))
+}
+
+fn with_installed<T>(f: impl FnOnce(&mut Option<Trussed>) -> T) -> T {
+    let mut installed = match TRUSSED.try_lock() {
+        Ok(installed) => installed,
+        Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(TryLockError::WouldBlock) => panic!("Trussed service is already in use"),
+    };
+    f(&mut installed.0)
+}
+
+#[derive(Default)]
+pub struct Syscall {}
+
+impl trussed::client::Syscall for Syscall {
+    #[inline]
+    fn syscall(&mut self) {
+        with_trussed(|trussed| trussed.process());
+    }
+}
+
+pub type ApduDispatch = apdu_dispatch::dispatch::ApduDispatch;
+pub type CtaphidDispatch = ctaphid_dispatch::dispatch::Dispatch;
+
+/// The PC as seen by the shared app composition.
+pub struct Runner;
+
+impl apps::Runner for Runner {
+    type Syscall = Syscall;
+    type Reboot = crate::ui::Reboot;
+
+    type Store = Store;
+    type Filesystem = FileFlash;
+
+    const VERSION: u32 = crate::VERSION;
+}
+
+/// Same app set as the LPC55 runner, with each app selectable at runtime.
+pub type Apps = apps::Apps<Runner>;
+pub type ProvisionerNonPortable = apps::ProvisionerNonPortable<Store, FileFlash>;
+pub use apps::AppSelection;
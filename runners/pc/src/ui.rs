@@ -0,0 +1,245 @@
+//! Implementation of `trussed::platform::UserInterface` for the PC runner.
+
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use std::time::{Duration, Instant};
+
+use trussed::platform::{
+    ui,
+    reboot,
+    consent,
+};
+
+use status_led::{Intensities, RgbLed};
+
+use crate::control::{Controls, Decision};
+use crate::trace::Trace;
+
+/// How user presence checks get answered, as there are no buttons.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Presence {
+    /// Grant every check, like a `no-buttons` build.
+    Always,
+    /// Deny every check.
+    Never,
+    /// Ask on the terminal.
+    Prompt,
+    /// Take the next decision queued via `control`.
+    Script,
+}
+
+impl Default for Presence {
+    fn default() -> Self {
+        Presence::Always
+    }
+}
+
+impl FromStr for Presence {
+    type Err = String;
+    fn from_str(presence: &str) -> Result<Self, Self::Err> {
+        match presence {
+            "always" => Ok(Presence::Always),
+            "never" => Ok(Presence::Never),
+            "prompt" => Ok(Presence::Prompt),
+            "script" => Ok(Presence::Script),
+            other => Err(format!("unknown presence mode {:?}, use always, never, prompt or script", other)),
+        }
+    }
+}
+
+/// How long a scripted presence check waits for a decision to be queued.
+const SCRIPT_WAIT: Duration = Duration::from_secs(30);
+
+/// How far the virtual clock moves each time a check is polled again without being
+/// satisfied, so it runs into Trussed's timeout like a user who does not press.
+const VIRTUAL_WAIT_STEP: Duration = Duration::from_millis(100);
+
+/// The scripted presence check being polled.
+#[derive(Copy, Clone, Debug)]
+struct ScriptedCheck {
+    started: Instant,
+    /// The decision taken for this check, answered until the check ends.
+    level: Option<consent::Level>,
+}
+
+/// Stands in for the RGB LED, remembering what it was last set to.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct VirtualLed {
+    pub color: Intensities,
+}
+
+impl RgbLed for VirtualLed {
+    fn red(&mut self, intensity: u8) {
+        self.color.red = intensity;
+    }
+
+    fn green(&mut self, intensity: u8) {
+        self.color.green = intensity;
+    }
+
+    fn blue(&mut self, intensity: u8) {
+        self.color.blue = intensity;
+    }
+}
+
+pub struct UserInterface {
+    presence: Presence,
+    controls: Controls,
+    status: ui::Status,
+    wink_until: Duration,
+    led: VirtualLed,
+    trace: Option<Trace>,
+    scripted_check: Option<ScriptedCheck>,
+}
+
+impl UserInterface {
+    pub fn new(presence: Presence, controls: Controls, trace: Option<Trace>) -> Self {
+        Self {
+            presence,
+            controls,
+            status: ui::Status::Idle,
+            wink_until: Duration::new(0, 0),
+            led: VirtualLed::default(),
+            trace,
+            scripted_check: None,
+        }
+    }
+
+    /// Same as the board's LED, but traced whenever the color changes.
+    fn set_led(&mut self, color: Intensities) {
+        if color == self.led.color {
+            return;
+        }
+        self.led.set(color);
+        if let Some(trace) = self.trace.as_mut() {
+            trace.led(self.controls.uptime(), color);
+        }
+    }
+
+    /// Trussed polls until the check succeeds or times out, so this must not block:
+    /// the check is denied until a decision is queued, or `SCRIPT_WAIT` passed.
+    /// The decision then holds until the check ends (see `set_status`).
+    fn scripted_user_presence(&mut self) -> consent::Level {
+        let now = Instant::now();
+        let check = self.scripted_check.get_or_insert(ScriptedCheck { started: now, level: None });
+        if let Some(level) = check.level {
+            // polled again, so the decision did not satisfy the check
+            self.controls.advance(VIRTUAL_WAIT_STEP);
+            return level;
+        }
+
+        let level = match self.controls.take_decision() {
+            Some(Decision::Grant) => consent::Level::Normal,
+            Some(Decision::Strong) => consent::Level::Strong,
+            Some(Decision::Deny) => consent::Level::None,
+            Some(Decision::Timeout(after)) => {
+                // no effect on the real clock, where Trussed's own timeout applies
+                self.controls.advance(after);
+                consent::Level::None
+            }
+            None if now - check.started >= SCRIPT_WAIT => {
+                println!("no presence decision queued, timing out");
+                consent::Level::None
+            }
+            None => {
+                std::thread::yield_now();
+                return consent::Level::None;
+            }
+        };
+        check.level = Some(level);
+        level
+    }
+}
+
+fn prompt_user_presence() -> consent::Level {
+    print!("User presence requested, [y]es / [s]trong (both buttons) / [n]o: ");
+    io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return consent::Level::None;
+    }
+    match answer.trim() {
+        "y" | "" => consent::Level::Normal,
+        "s" => consent::Level::Strong,
+        _ => consent::Level::None,
+    }
+}
+
+impl trussed::platform::UserInterface for UserInterface
+{
+    fn check_user_presence(&mut self) -> consent::Level {
+        match self.presence {
+            Presence::Always => consent::Level::Normal,
+            Presence::Never => consent::Level::None,
+            Presence::Prompt => prompt_user_presence(),
+            Presence::Script => self.scripted_user_presence(),
+        }
+    }
+
+    fn set_status(&mut self, status: ui::Status) {
+
+        println!("Set status: {:?}", status);
+
+        // Trussed only sets the status around a check, not while polling it: either the
+        // check ended, or a new one starts (after a timeout, the status is not reset)
+        self.scripted_check = None;
+        self.status = status;
+        if let Some(trace) = self.trace.as_mut() {
+            trace.status(self.controls.uptime(), status);
+        }
+        self.set_led(status_led::status_color(status));
+    }
+
+    fn status(&self) -> ui::Status {
+        self.status
+    }
+
+    fn refresh(&mut self) {
+        let uptime = self.controls.uptime();
+        let winking = uptime < self.wink_until;
+        // there are no buttons to hold down
+        let color = status_led::animate(self.status, uptime.as_millis() as u32, winking, false);
+        self.set_led(color);
+    }
+
+    fn uptime(&mut self) -> core::time::Duration {
+        self.controls.uptime()
+    }
+
+    fn wink(&mut self, duration: Duration) {
+        let uptime = self.controls.uptime();
+        self.wink_until = uptime + duration;
+        if let Some(trace) = self.trace.as_mut() {
+            trace.wink(uptime, duration);
+        }
+    }
+
+    fn reboot(&mut self, to: reboot::To) -> ! {
+        println!("Restart!  ({:?})", to);
+        crate::exit(25);
+    }
+
+}
+
+/// What the admin and provisioner apps use to reboot the "device".
+pub struct Reboot;
+
+impl admin_app::Reboot for Reboot {
+    fn reboot() -> ! {
+        println!("Restart!");
+        crate::exit(25);
+    }
+    fn reboot_to_firmware_update() -> ! {
+        println!("Restart to bootrom!");
+        crate::exit(26);
+    }
+    fn reboot_to_firmware_update_destructive() -> ! {
+        println!("Restart to bootrom (destructive)!");
+        crate::exit(27);
+    }
+    fn locked() -> bool {
+        false
+    }
+}
@@ -0,0 +1,18 @@
+mod common;
+
+use common::{with_device, UUID};
+
+const VERSION: u8 = 0x61;
+const UUID_COMMAND: u8 = 0x62;
+
+#[test]
+fn version() {
+    let version = with_device(|device| device.ctaphid(VERSION, &[])).unwrap();
+    assert_eq!(version, solo_pc::VERSION.to_be_bytes());
+}
+
+#[test]
+fn uuid() {
+    let uuid = with_device(|device| device.ctaphid(UUID_COMMAND, &[])).unwrap();
+    assert_eq!(uuid, UUID);
+}
@@ -0,0 +1,39 @@
+//! Parsing of the command line values.
+
+use solo_pc::control::ClockMode;
+use solo_pc::types::AppSelection;
+use solo_pc::ui::Presence;
+
+#[test]
+fn app_selection() {
+    assert_eq!("all".parse(), Ok(AppSelection::ALL));
+    assert_eq!("fido".parse(), Ok(AppSelection { fido: true, ..AppSelection::NONE }));
+    assert_eq!(
+        "fido, oath,piv".parse(),
+        Ok(AppSelection { fido: true, oath: true, piv: true, ..AppSelection::NONE }),
+    );
+    assert_eq!(
+        "admin,ndef,provisioner".parse(),
+        Ok(AppSelection { admin: true, ndef: true, provisioner: true, ..AppSelection::NONE }),
+    );
+    assert_eq!("piv,all".parse(), Ok(AppSelection::ALL));
+    assert_eq!(AppSelection::default(), AppSelection::ALL);
+}
+
+#[test]
+fn unknown_app() {
+    let error = "fido,u2f".parse::<AppSelection>().unwrap_err();
+    assert_eq!(error, "unknown app, use admin, fido, oath, ndef, piv, provisioner or all");
+    assert!("".parse::<AppSelection>().is_err());
+    assert!("FIDO".parse::<AppSelection>().is_err());
+}
+
+#[test]
+fn presence_and_clock() {
+    assert_eq!("script".parse(), Ok(Presence::Script));
+    assert_eq!("never".parse(), Ok(Presence::Never));
+    assert!("sometimes".parse::<Presence>().is_err());
+
+    assert_eq!("virtual".parse(), Ok(ClockMode::Virtual));
+    assert!("fast".parse::<ClockMode>().is_err());
+}
@@ -0,0 +1,140 @@
+//! In-process harness: boots the apps on the PC platform and talks to them
+//! through the same interchanges the transports use.
+//!
+//! Trussed, the store and the interchanges are singletons, so there is one device per
+//! test binary, shared by its tests (one at a time). Tests must not rely on a fresh device.
+
+#![allow(dead_code)]
+
+use std::convert::TryFrom;
+use std::sync::Mutex;
+
+use apdu_dispatch::interchanges::Data;
+use ctaphid_dispatch::command::Command;
+use ctaphid_dispatch::types::Message;
+
+use solo_pc::control::{ClockMode, Controls};
+use solo_pc::device::{Config, Device, Interfaces};
+use solo_pc::flash::FileFlash;
+use solo_pc::types::{AppSelection, Store};
+use solo_pc::ui::Presence;
+
+pub const UUID: [u8; 16] = *b"solo2-test-uuid\0";
+
+/// Polls before a request is considered lost, generous as the apps are quick.
+const MAX_POLLS: usize = 10_000;
+
+pub struct TestDevice {
+    device: Device,
+    interfaces: Interfaces,
+    controls: Controls,
+    presence: Presence,
+}
+
+// Only ever used behind `DEVICE`'s lock, which serializes access to the singletons.
+unsafe impl Send for TestDevice {}
+
+static DEVICE: Mutex<Option<TestDevice>> = Mutex::new(None);
+
+/// Runs `f` on the test binary's device, booting it on first use.
+pub fn with_device<T>(f: impl FnOnce(&mut TestDevice) -> T) -> T {
+    with_device_presence(Presence::Always, f)
+}
+
+/// Same as `with_device`, with user presence checks answered as `presence` says,
+/// which must be the same for all tests of the binary.
+pub fn with_device_presence<T>(presence: Presence, f: impl FnOnce(&mut TestDevice) -> T) -> T {
+    // a failed test must not take the others down with it
+    let mut device = DEVICE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let device = device.get_or_insert_with(|| TestDevice::boot(presence));
+    assert_eq!(device.presence, presence, "one presence mode per test binary");
+    f(device)
+}
+
+pub struct Response {
+    pub data: Vec<u8>,
+    pub status: u16,
+}
+
+impl TestDevice {
+    fn boot(presence: Presence) -> Self {
+        let state = std::env::temp_dir()
+            .join(format!("solo-pc-test-{}.bin", std::process::id()));
+        std::fs::remove_file(&state).ok();
+
+        let controls = Controls::new(ClockMode::Virtual);
+        let (device, interfaces) = Device::boot(Config {
+            filesystem: FileFlash::open(&state).unwrap(),
+            seed: Some([0x42; 32]),
+            apps: AppSelection::ALL,
+            presence,
+            controls: controls.clone(),
+            trace: None,
+            uuid: UUID,
+        });
+        Self { device, interfaces, controls, presence }
+    }
+
+    pub fn store(&self) -> Store {
+        self.device.store()
+    }
+
+    pub fn controls(&self) -> &Controls {
+        &self.controls
+    }
+
+    /// Sends a CTAPHID message, as the transports do after reassembling it.
+    pub fn ctaphid(&mut self, command: u8, data: &[u8]) -> Result<Vec<u8>, ctaphid_dispatch::app::Error> {
+        let command = Command::try_from(command).expect("unknown CTAPHID command");
+        let message = Message::from_slice(data).expect("message too long");
+        self.interfaces.ctaphid.request(&(command, message)).expect("interchange busy");
+        for _ in 0..MAX_POLLS {
+            self.device.poll();
+            if let Some(response) = self.interfaces.ctaphid.take_response() {
+                return response.map(|message| message.to_vec());
+            }
+        }
+        panic!("no CTAPHID response");
+    }
+
+    /// Sends a command APDU over the contact interface.
+    pub fn apdu(&mut self, apdu: &[u8]) -> Response {
+        let command = Data::from_slice(apdu).expect("APDU too long");
+        self.interfaces.contact.request(&command).expect("interchange busy");
+        for _ in 0..MAX_POLLS {
+            self.device.poll();
+            if let Some(response) = self.interfaces.contact.take_response() {
+                let (data, status) = response.split_at(response.len() - 2);
+                return Response {
+                    data: data.to_vec(),
+                    status: u16::from_be_bytes([status[0], status[1]]),
+                };
+            }
+        }
+        panic!("no APDU response");
+    }
+
+    /// SELECT by AID.
+    pub fn select(&mut self, aid: &[u8]) -> Response {
+        let mut apdu = vec![0x00, 0xa4, 0x04, 0x00, aid.len() as u8];
+        apdu.extend_from_slice(aid);
+        self.apdu(&apdu)
+    }
+}
+
+/// A short (case 3) command APDU.
+pub fn apdu(cla: u8, ins: u8, p1: u8, p2: u8, data: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![cla, ins, p1, p2];
+    if !data.is_empty() {
+        apdu.push(data.len() as u8);
+        apdu.extend_from_slice(data);
+    }
+    apdu
+}
+
+/// Simple TLV with a one byte length, as used by OATH and the provisioner.
+pub fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut tlv = vec![tag, value.len() as u8];
+    tlv.extend_from_slice(value);
+    tlv
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use serde_cbor::Value;
+
+use common::with_device;
+
+const CBOR: u8 = 0x10;
+
+const MAKE_CREDENTIAL: u8 = 0x01;
+const GET_ASSERTION: u8 = 0x02;
+
+const RP_ID: &str = "example.com";
+
+/// Flags in the authenticator data.
+const USER_PRESENT: u8 = 0x01;
+const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+fn text(text: &str) -> Value {
+    Value::Text(text.into())
+}
+
+fn map(entries: Vec<(Value, Value)>) -> Value {
+    Value::Map(entries.into_iter().collect::<BTreeMap<_, _>>())
+}
+
+/// Sends a CTAP2 command, returning the response map on success.
+fn ctap2(command: u8, parameters: Value) -> BTreeMap<Value, Value> {
+    let mut request = vec![command];
+    request.extend(serde_cbor::to_vec(&parameters).unwrap());
+
+    let response = with_device(|device| device.ctaphid(CBOR, &request)).unwrap();
+    assert_eq!(response[0], 0x00, "CTAP2 error {:#04x}", response[0]);
+    match serde_cbor::from_slice(&response[1..]).unwrap() {
+        Value::Map(map) => map,
+        other => panic!("unexpected response {:?}", other),
+    }
+}
+
+fn bytes(response: &BTreeMap<Value, Value>, key: i128) -> Vec<u8> {
+    match response.get(&Value::Integer(key)) {
+        Some(Value::Bytes(bytes)) => bytes.clone(),
+        other => panic!("expected bytes at {}, got {:?}", key, other),
+    }
+}
+
+fn make_credential() -> Vec<u8> {
+    let response = ctap2(MAKE_CREDENTIAL, map(vec![
+        (Value::Integer(1), Value::Bytes(vec![0x11; 32])),
+        (Value::Integer(2), map(vec![(text("id"), text(RP_ID))])),
+        (Value::Integer(3), map(vec![
+            (text("id"), Value::Bytes(b"user".to_vec())),
+            (text("name"), text("user")),
+        ])),
+        (Value::Integer(4), Value::Array(vec![map(vec![
+            (text("alg"), Value::Integer(-7)),
+            (text("type"), text("public-key")),
+        ])])),
+    ]));
+
+    // rpIdHash (32), flags (1), signCount (4), AAGUID (16), credential ID length (2), ...
+    let auth_data = bytes(&response, 2);
+    assert_eq!(auth_data[32] & (USER_PRESENT | ATTESTED_CREDENTIAL_DATA), USER_PRESENT | ATTESTED_CREDENTIAL_DATA);
+    let length = u16::from_be_bytes([auth_data[53], auth_data[54]]) as usize;
+    auth_data[55..55 + length].to_vec()
+}
+
+#[test]
+fn make_credential_then_get_assertion() {
+    let credential_id = make_credential();
+
+    let response = ctap2(GET_ASSERTION, map(vec![
+        (Value::Integer(1), text(RP_ID)),
+        (Value::Integer(2), Value::Bytes(vec![0x22; 32])),
+        (Value::Integer(3), Value::Array(vec![map(vec![
+            (text("id"), Value::Bytes(credential_id.clone())),
+            (text("type"), text("public-key")),
+        ])])),
+    ]));
+
+    let credential = match response.get(&Value::Integer(1)) {
+        Some(Value::Map(credential)) => credential,
+        other => panic!("expected a credential, got {:?}", other),
+    };
+    assert_eq!(credential.get(&text("id")), Some(&Value::Bytes(credential_id)));
+
+    let auth_data = bytes(&response, 2);
+    assert_eq!(auth_data[32] & USER_PRESENT, USER_PRESENT);
+    assert!(!bytes(&response, 3).is_empty(), "no signature");
+}
+
+#[test]
+fn get_assertion_without_credentials() {
+    let mut request = vec![GET_ASSERTION];
+    request.extend(serde_cbor::to_vec(&map(vec![
+        (Value::Integer(1), text("unknown.example.com")),
+        (Value::Integer(2), Value::Bytes(vec![0x33; 32])),
+    ])).unwrap());
+
+    let response = with_device(|device| device.ctaphid(CBOR, &request)).unwrap();
+    // CTAP2_ERR_NO_CREDENTIALS
+    assert_eq!(response, [0x2e]);
+}
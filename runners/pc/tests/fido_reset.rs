@@ -0,0 +1,25 @@
+//! The reset window, in a test binary of its own as a reset wipes all credentials.
+
+mod common;
+
+use std::time::Duration;
+
+use common::with_device;
+
+const CBOR: u8 = 0x10;
+
+const RESET: u8 = 0x07;
+
+const CTAP2_ERR_NOT_ALLOWED: u8 = 0x30;
+
+#[test]
+fn reset_only_within_ten_seconds_of_power_up() {
+    with_device(|device| {
+        // the virtual clock starts at zero
+        device.controls().advance(Duration::from_secs(9));
+        assert_eq!(device.ctaphid(CBOR, &[RESET]).unwrap(), [0x00]);
+
+        device.controls().advance(Duration::from_secs(2));
+        assert_eq!(device.ctaphid(CBOR, &[RESET]).unwrap(), [CTAP2_ERR_NOT_ALLOWED]);
+    });
+}
@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+
+use littlefs2::driver::Storage;
+
+use solo_pc::flash::{Error, FileFlash};
+use solo_pc::types::littlefs_params::{BLOCK_COUNT, BLOCK_SIZE};
+
+/// A state file path of its own for each test.
+fn state_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir()
+        .join(format!("solo-pc-flash-{}-{}.bin", name, std::process::id()));
+    std::fs::remove_file(&path).ok();
+    path
+}
+
+fn header(version: u32, block_size: usize, block_count: usize) -> Vec<u8> {
+    let mut header = b"SOLO2FS\0".to_vec();
+    header.extend_from_slice(&version.to_le_bytes());
+    header.extend_from_slice(&(block_size as u32).to_le_bytes());
+    header.extend_from_slice(&(block_count as u32).to_le_bytes());
+    header
+}
+
+fn open_error(name: &str, contents: &[u8]) -> Error {
+    let path = state_file(name);
+    std::fs::write(&path, contents).unwrap();
+    let result = FileFlash::open(&path);
+    std::fs::remove_file(&path).ok();
+    match result {
+        Ok(_) => panic!("{} state file accepted", name),
+        Err(error) => error,
+    }
+}
+
+#[test]
+fn wrong_magic() {
+    let mut contents = header(1, BLOCK_SIZE, BLOCK_COUNT);
+    contents[..8].copy_from_slice(b"NOTSOLO2");
+    contents.resize(contents.len() + BLOCK_SIZE * BLOCK_COUNT, 0xff);
+    assert!(matches!(open_error("magic", &contents), Error::NotAStateFile));
+
+    // a state file from before the header
+    let contents = vec![0xff; BLOCK_SIZE * BLOCK_COUNT];
+    assert!(matches!(open_error("headerless", &contents), Error::NotAStateFile));
+}
+
+#[test]
+fn unsupported_version() {
+    let mut contents = header(2, BLOCK_SIZE, BLOCK_COUNT);
+    contents.resize(contents.len() + BLOCK_SIZE * BLOCK_COUNT, 0xff);
+    assert!(matches!(open_error("version", &contents), Error::UnsupportedVersion(2)));
+}
+
+#[test]
+fn geometry_mismatch() {
+    let mut contents = header(1, BLOCK_SIZE, BLOCK_COUNT + 1);
+    contents.resize(contents.len() + BLOCK_SIZE * (BLOCK_COUNT + 1), 0xff);
+    match open_error("geometry", &contents) {
+        Error::GeometryMismatch { block_size, block_count } => {
+            assert_eq!((block_size, block_count), (BLOCK_SIZE, BLOCK_COUNT + 1));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn truncated() {
+    let mut contents = header(1, BLOCK_SIZE, BLOCK_COUNT);
+    contents.resize(contents.len() + BLOCK_SIZE * BLOCK_COUNT - 1, 0xff);
+    assert!(matches!(open_error("truncated", &contents), Error::Truncated));
+
+    // not even a whole header
+    assert!(matches!(open_error("short", &header(1, BLOCK_SIZE, BLOCK_COUNT)[..12]), Error::NotAStateFile));
+}
+
+#[test]
+fn changes_persist() {
+    let path = state_file("persist");
+    let mut flash = FileFlash::open(&path).unwrap();
+    flash.write(0, &[0x01; 16]).unwrap();
+    flash.flush().unwrap();
+
+    // the state file exists now, so only the changed blocks are written
+    flash.write(2 * BLOCK_SIZE, &[0x02; 16]).unwrap();
+    flash.erase(0, BLOCK_SIZE).unwrap();
+    assert!(flash.is_dirty());
+    flash.flush().unwrap();
+    assert!(!flash.is_dirty());
+
+    let flash = FileFlash::open(&path).unwrap();
+    let mut buf = [0; 16];
+    flash.read(0, &mut buf).unwrap();
+    assert_eq!(buf, [0xff; 16]);
+    flash.read(2 * BLOCK_SIZE, &mut buf).unwrap();
+    assert_eq!(buf, [0x02; 16]);
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn journal_left_behind_is_replayed() {
+    let path = state_file("journal");
+    let mut flash = FileFlash::open(&path).unwrap();
+    flash.write(0, &[0x00; 16]).unwrap();
+    flash.flush().unwrap();
+    drop(flash);
+
+    // as if the runner crashed after writing the journal, before updating block 1
+    let mut journal = b"SOLO2JN\0".to_vec();
+    journal.extend_from_slice(&1u32.to_le_bytes());
+    journal.extend_from_slice(&[0x03; BLOCK_SIZE]);
+    let mut journal_path = path.clone().into_os_string();
+    journal_path.push(".journal");
+    std::fs::write(&journal_path, &journal).unwrap();
+
+    let flash = FileFlash::open(&path).unwrap();
+    assert!(!PathBuf::from(&journal_path).exists(), "journal not removed");
+    let mut buf = [0; BLOCK_SIZE];
+    flash.read(BLOCK_SIZE, &mut buf).unwrap();
+    assert_eq!(buf, [0x03; BLOCK_SIZE]);
+    flash.read(0, &mut buf[..16]).unwrap();
+    assert_eq!(buf[..16], [0x00; 16]);
+    std::fs::remove_file(&path).ok();
+}
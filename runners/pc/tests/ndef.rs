@@ -0,0 +1,247 @@
+mod common;
+
+use littlefs2::path::PathBuf;
+use trussed::store;
+use trussed::types::{Location, Message};
+
+use common::{apdu, with_device};
+
+const NDEF_AID: [u8; 7] = [0xd2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+
+const SELECT: u8 = 0xa4;
+const READ_BINARY: u8 = 0xb0;
+const UPDATE_BINARY: u8 = 0xd6;
+const WRITE_NDEF: u8 = 0x10;
+const CONFIGURE_OTP: u8 = 0x11;
+const SET_WRITE_ACCESS: u8 = 0xdc;
+
+/// "https://solokeys.com/", the NDEF file unless another was written.
+const DEFAULT_NDEF: [u8; 20] = [
+    0x00, 0x12, 0xd1, 0x01, 0x0e, 0x55, 0x04, 0x73, 0x6f, 0x6c,
+    0x6f, 0x6b, 0x65, 0x79, 0x73, 0x2e, 0x63, 0x6f, 0x6d, 0x2f,
+];
+
+fn read_ndef(device: &mut common::TestDevice) -> Vec<u8> {
+    assert_eq!(device.select(&NDEF_AID).status, 0x9000);
+    assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x0c, &[0xe1, 0x04])).status, 0x9000);
+    let response = device.apdu(&[0x00, READ_BINARY, 0x00, 0x00, 0x00]);
+    assert_eq!(response.status, 0x9000);
+    response.data
+}
+
+#[test]
+fn write_ndef_records() {
+    with_device(|device| {
+        assert_eq!(read_ndef(device), DEFAULT_NDEF);
+
+        // "https://" is abbreviated as 0x04
+        let response = device.apdu(&apdu(0x00, WRITE_NDEF, 0x01, 0x00, b"https://example.com/enroll"));
+        assert_eq!(response.status, 0x9000);
+        let mut expected = vec![0x00, 0x17, 0xd1, 0x01, 0x13, b'U', 0x04];
+        expected.extend_from_slice(b"example.com/enroll");
+        assert_eq!(read_ndef(device), expected);
+
+        let response = device.apdu(&apdu(0x00, WRITE_NDEF, 0x02, 0x02, b"enHello"));
+        assert_eq!(response.status, 0x9000);
+        let mut expected = vec![0x00, 0x0c, 0xd1, 0x01, 0x08, b'T', 0x02];
+        expected.extend_from_slice(b"enHello");
+        assert_eq!(read_ndef(device), expected);
+
+        let response = device.apdu(&apdu(0x00, WRITE_NDEF, 0x03, 0x0a, b"text/plainhi"));
+        assert_eq!(response.status, 0x9000);
+        let mut expected = vec![0x00, 0x0f, 0xd2, 0x0a, 0x02];
+        expected.extend_from_slice(b"text/plainhi");
+        assert_eq!(read_ndef(device), expected);
+
+        // the language code can't be longer than the data
+        let response = device.apdu(&apdu(0x00, WRITE_NDEF, 0x02, 0x10, b"en"));
+        assert_eq!(response.status, 0x6a80);
+        // the text record has six bits for its length
+        let response = device.apdu(&apdu(0x00, WRITE_NDEF, 0x02, 0x40, &[b'a'; 0x41]));
+        assert_eq!(response.status, 0x6700);
+
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_NDEF, 0x00, 0x00, &[])).status, 0x9000);
+        assert_eq!(read_ndef(device), DEFAULT_NDEF);
+    });
+}
+
+fn read_internal(device: &common::TestDevice, path: &str) -> Option<Vec<u8>> {
+    let contents: Option<Message> = store::read(device.store(), Location::Internal, &PathBuf::from(path)).ok();
+    contents.map(|contents| contents.to_vec())
+}
+
+/// The NDEF file with a URI record of "https://" and `uri`.
+fn uri_record(uri: &[u8]) -> Vec<u8> {
+    let mut expected = vec![0x00, 4 + uri.len() as u8 + 1, 0xd1, 0x01, uri.len() as u8 + 1, b'U', 0x04];
+    expected.extend_from_slice(uri);
+    expected
+}
+
+#[test]
+fn one_time_code_per_tap() {
+    with_device(|device| {
+        // the secret of the RFC 4226 test vectors
+        let secret = b"12345678901234567890";
+        let mut data = vec![secret.len() as u8];
+        data.extend_from_slice(secret);
+        data.extend_from_slice(b"https://example.com/t/");
+        assert_eq!(device.apdu(&apdu(0x00, CONFIGURE_OTP, 0x01, 0x09, &data)).status, 0x6a80);
+        assert_eq!(device.apdu(&apdu(0x00, CONFIGURE_OTP, 0x01, 0x06, &data)).status, 0x9000);
+
+        // only the key ID is stored with the configuration, the secret is a Trussed key
+        let configuration = read_internal(device, "/ndef/dat/otp").unwrap();
+        assert!(!configuration.windows(secret.len()).any(|window| window == secret));
+        let key = format!("/ndef/sec/{}", std::str::from_utf8(&configuration[5..37]).unwrap());
+        assert!(read_internal(device, &key).is_some(), "no key at {}", key);
+
+        // RFC 4226, appendix D: one code per select of the NDEF file, with the counter up front
+        for uri in [
+            &b"example.com/t/00000001287082"[..],
+            b"example.com/t/00000002359152",
+            b"example.com/t/00000003969429",
+        ] {
+            assert_eq!(read_ndef(device), uri_record(uri));
+        }
+        let configuration = read_internal(device, "/ndef/dat/otp").unwrap();
+        assert_eq!(configuration[1..5], 3u32.to_be_bytes());
+
+        // configuring again starts over, with a new key
+        assert_eq!(device.apdu(&apdu(0x00, CONFIGURE_OTP, 0x01, 0x06, &data)).status, 0x9000);
+        assert!(read_internal(device, &key).is_none(), "previous key left behind");
+        assert_eq!(read_ndef(device), uri_record(b"example.com/t/00000001287082"));
+
+        let configuration = read_internal(device, "/ndef/dat/otp").unwrap();
+        let key = format!("/ndef/sec/{}", std::str::from_utf8(&configuration[5..37]).unwrap());
+        assert_eq!(device.apdu(&apdu(0x00, CONFIGURE_OTP, 0x00, 0x00, &[])).status, 0x9000);
+        assert!(read_internal(device, &key).is_none(), "key left behind");
+        assert_eq!(read_internal(device, "/ndef/dat/otp"), None);
+        assert_eq!(read_ndef(device), DEFAULT_NDEF);
+    });
+}
+
+/// The write access byte of the capability container.
+fn write_access(device: &mut common::TestDevice) -> u8 {
+    assert_eq!(device.select(&NDEF_AID).status, 0x9000);
+    assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x0c, &[0xe1, 0x03])).status, 0x9000);
+    let response = device.apdu(&[0x00, READ_BINARY, 0x00, 0x00, 0x0f]);
+    assert_eq!(response.status, 0x9000);
+    response.data[14]
+}
+
+#[test]
+fn update_binary() {
+    with_device(|device| {
+        let message = [0xd1, 0x01, 0x07, b'T', 0x02, b'e', b'n', b'h', b'e', b'y'];
+
+        assert_eq!(write_access(device), 0xff);
+        assert_eq!(read_ndef(device), DEFAULT_NDEF);
+        let response = device.apdu(&apdu(0x00, UPDATE_BINARY, 0x00, 0x00, &[0x00, 0x00]));
+        assert_eq!(response.status, 0x6982);
+
+        assert_eq!(device.apdu(&apdu(0x00, SET_WRITE_ACCESS, 0x00, 0x00, &[])).status, 0x9000);
+        assert_eq!(write_access(device), 0x00);
+
+        // as a reader writes: clear NLEN, write the message, set NLEN
+        assert_eq!(read_ndef(device), DEFAULT_NDEF);
+        assert_eq!(device.apdu(&apdu(0x00, UPDATE_BINARY, 0x00, 0x00, &[0x00, 0x00])).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, UPDATE_BINARY, 0x00, 0x02, &message)).status, 0x9000);
+        // cut short, the message is empty, also once loaded from the store again
+        assert_eq!(read_ndef(device), [0x00, 0x00]);
+
+        assert_eq!(device.apdu(&apdu(0x00, UPDATE_BINARY, 0x00, 0x00, &[0x00, 0x00])).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, UPDATE_BINARY, 0x00, 0x02, &message)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, UPDATE_BINARY, 0x00, 0x00, &[0x00, 0x0a])).status, 0x9000);
+        let mut expected = vec![0x00, 0x0a];
+        expected.extend_from_slice(&message);
+        assert_eq!(read_ndef(device), expected);
+
+        // NLEN past the end of the file changes nothing
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x0c, &[0xe1, 0x04])).status, 0x9000);
+        let response = device.apdu(&apdu(0x00, UPDATE_BINARY, 0x00, 0x00, &[0x03, 0xff]));
+        assert_eq!(response.status, 0x6a80);
+        let response = device.apdu(&[0x00, READ_BINARY, 0x00, 0x00, 0x00]);
+        assert_eq!(response.data, expected);
+        assert_eq!(read_ndef(device), expected);
+
+        // past the end of the file
+        let response = device.apdu(&apdu(0x00, UPDATE_BINARY, 0x03, 0xff, &[0x00, 0x00]));
+        assert_eq!(response.status, 0x6700);
+        let response = device.apdu(&apdu(0x00, UPDATE_BINARY, 0x04, 0x00, &[0x00]));
+        assert_eq!(response.status, 0x6a86);
+
+        assert_eq!(device.apdu(&apdu(0x00, SET_WRITE_ACCESS, 0xff, 0x00, &[])).status, 0x9000);
+        assert_eq!(write_access(device), 0xff);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_NDEF, 0x00, 0x00, &[])).status, 0x9000);
+    });
+}
+
+#[test]
+fn select_and_read_binary() {
+    with_device(|device| {
+        assert_eq!(device.select(&NDEF_AID).status, 0x9000);
+        // no file selected yet
+        assert_eq!(device.apdu(&[0x00, READ_BINARY, 0x00, 0x00, 0x0f]).status, 0x6985);
+
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x0c, &[0xe1, 0x05])).status, 0x6a82);
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x0c, &[0xe1, 0x03, 0x00])).status, 0x6700);
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x02, 0x0c, &[0xe1, 0x03])).status, 0x6a86);
+
+        // read-only, MLe and MLc 255, 1024 bytes of NDEF file
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x0c, &[0xe1, 0x03])).status, 0x9000);
+        let response = device.apdu(&[0x00, READ_BINARY, 0x00, 0x00, 0x0f]);
+        assert_eq!(response.status, 0x9000);
+        assert_eq!(response.data, [
+            0x00, 0x0f, 0x20, 0x00, 0xff, 0x00, 0xff,
+            0x04, 0x06, 0xe1, 0x04, 0x04, 0x00, 0x00, 0xff,
+        ]);
+        // Le past the end
+        let response = device.apdu(&[0x00, READ_BINARY, 0x00, 0x0d, 0x0f]);
+        assert_eq!(response.data, [0x00, 0xff]);
+
+        assert_eq!(device.apdu(&[0x00, READ_BINARY, 0x00, 0x0f, 0x01]).status, 0x6a86);
+        assert_eq!(device.apdu(&[0x00, READ_BINARY, 0x7f, 0xff, 0x01]).status, 0x6a86);
+        // a short EF identifier, not an offset
+        assert_eq!(device.apdu(&[0x00, READ_BINARY, 0x81, 0x00, 0x01]).status, 0x6a86);
+
+        // selecting the application by name again
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x04, 0x00, &NDEF_AID)).status, 0x9000);
+        assert_eq!(device.apdu(&[0x00, READ_BINARY, 0x00, 0x00, 0x0f]).status, 0x6985);
+    });
+}
+
+#[test]
+fn ndef_file_past_255_bytes() {
+    with_device(|device| {
+        let payload = [0x5a; 300];
+        let mut file = vec![0x00, 0x00, 0xc2, 0x0a];
+        file.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        file.extend_from_slice(b"text/plain");
+        file.extend_from_slice(&payload);
+        let length = (file.len() - 2) as u16;
+
+        assert_eq!(device.apdu(&apdu(0x00, SET_WRITE_ACCESS, 0x00, 0x00, &[])).status, 0x9000);
+        assert_eq!(read_ndef(device), DEFAULT_NDEF);
+        for (index, chunk) in file.chunks(0xff).enumerate() {
+            let offset = (index * 0xff) as u16;
+            let [p1, p2] = offset.to_be_bytes();
+            assert_eq!(device.apdu(&apdu(0x00, UPDATE_BINARY, p1, p2, chunk)).status, 0x9000);
+        }
+        let [p1, p2] = length.to_be_bytes();
+        assert_eq!(device.apdu(&apdu(0x00, UPDATE_BINARY, 0x00, 0x00, &[p1, p2])).status, 0x9000);
+        file[..2].copy_from_slice(&length.to_be_bytes());
+
+        assert_eq!(device.select(&NDEF_AID).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x0c, &[0xe1, 0x04])).status, 0x9000);
+        let mut read = Vec::new();
+        while read.len() < file.len() {
+            let [p1, p2] = (read.len() as u16).to_be_bytes();
+            let response = device.apdu(&[0x00, READ_BINARY, p1, p2, 0x00]);
+            assert_eq!(response.status, 0x9000);
+            read.extend_from_slice(&response.data);
+        }
+        assert_eq!(read, file);
+
+        assert_eq!(device.apdu(&apdu(0x00, SET_WRITE_ACCESS, 0xff, 0x00, &[])).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_NDEF, 0x00, 0x00, &[])).status, 0x9000);
+    });
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{apdu, tlv, with_device};
+
+const OATH_AID: [u8; 7] = [0xa0, 0x00, 0x00, 0x05, 0x27, 0x21, 0x01];
+
+const PUT: u8 = 0x01;
+const CALCULATE: u8 = 0xa2;
+
+const TAG_NAME: u8 = 0x71;
+const TAG_KEY: u8 = 0x73;
+const TAG_CHALLENGE: u8 = 0x74;
+const TAG_TRUNCATED_RESPONSE: u8 = 0x76;
+
+const TOTP_SHA1: u8 = 0x21;
+
+/// The SHA-1 secret of the RFC 6238 test vectors.
+const SECRET: &[u8] = b"12345678901234567890";
+
+#[test]
+fn calculate_totp() {
+    with_device(|device| {
+        assert_eq!(device.select(&OATH_AID).status, 0x9000);
+
+        let mut key = vec![TOTP_SHA1, 6];
+        key.extend_from_slice(SECRET);
+        let mut data = tlv(TAG_NAME, b"rfc6238");
+        data.extend(tlv(TAG_KEY, &key));
+        assert_eq!(device.apdu(&apdu(0x00, PUT, 0x00, 0x00, &data)).status, 0x9000);
+
+        // the host computes the challenge from the time, T = 59 s is step 1
+        let mut data = tlv(TAG_NAME, b"rfc6238");
+        data.extend(tlv(TAG_CHALLENGE, &1u64.to_be_bytes()));
+        let response = device.apdu(&apdu(0x00, CALCULATE, 0x00, 0x01, &data));
+        assert_eq!(response.status, 0x9000);
+
+        let response = response.data;
+        assert_eq!(&response[..3], &[TAG_TRUNCATED_RESPONSE, 5, 6]);
+        let truncated = u32::from_be_bytes([response[3], response[4], response[5], response[6]]);
+        // RFC 6238, appendix B: 94287082 at T = 59 s, truncated to 6 digits
+        assert_eq!((truncated & 0x7fff_ffff) % 1_000_000, 287082);
+    });
+}
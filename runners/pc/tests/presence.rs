@@ -0,0 +1,97 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde_cbor::Value;
+
+use solo_pc::control::Decision;
+use solo_pc::ui::Presence;
+
+use common::with_device_presence;
+
+const CBOR: u8 = 0x10;
+
+const MAKE_CREDENTIAL: u8 = 0x01;
+
+const CTAP2_ERR_USER_ACTION_TIMEOUT: u8 = 0x2f;
+
+/// Flags in the authenticator data.
+const USER_PRESENT: u8 = 0x01;
+
+fn text(text: &str) -> Value {
+    Value::Text(text.into())
+}
+
+fn map(entries: Vec<(Value, Value)>) -> Value {
+    Value::Map(entries.into_iter().collect::<BTreeMap<_, _>>())
+}
+
+/// Queues `decisions`, then sends a makeCredential for each, returning the responses.
+fn make_credentials(decisions: &[Decision]) -> Vec<Vec<u8>> {
+    let mut request = vec![MAKE_CREDENTIAL];
+    request.extend(serde_cbor::to_vec(&map(vec![
+        (Value::Integer(1), Value::Bytes(vec![0x11; 32])),
+        (Value::Integer(2), map(vec![(text("id"), text("example.com"))])),
+        (Value::Integer(3), map(vec![
+            (text("id"), Value::Bytes(b"user".to_vec())),
+            (text("name"), text("user")),
+        ])),
+        (Value::Integer(4), Value::Array(vec![map(vec![
+            (text("alg"), Value::Integer(-7)),
+            (text("type"), text("public-key")),
+        ])])),
+    ])).unwrap());
+
+    with_device_presence(Presence::Script, |device| {
+        for decision in decisions {
+            device.controls().push_decision(*decision);
+        }
+        let responses = decisions.iter()
+            .map(|_| device.ctaphid(CBOR, &request).unwrap())
+            .collect();
+        assert_eq!(device.controls().take_decision(), None, "decisions left over");
+        responses
+    })
+}
+
+fn assert_user_present(response: &[u8]) {
+    assert_eq!(response[0], 0x00, "CTAP2 error {:#04x}", response[0]);
+    let response: BTreeMap<Value, Value> = serde_cbor::from_slice(&response[1..]).unwrap();
+    match response.get(&Value::Integer(2)) {
+        // rpIdHash (32), flags (1), ...
+        Some(Value::Bytes(auth_data)) => assert_eq!(auth_data[32] & USER_PRESENT, USER_PRESENT),
+        other => panic!("expected authenticator data, got {:?}", other),
+    }
+}
+
+#[test]
+fn grant() {
+    let responses = make_credentials(&[Decision::Grant]);
+    assert_user_present(&responses[0]);
+}
+
+#[test]
+fn strong() {
+    let responses = make_credentials(&[Decision::Strong]);
+    assert_user_present(&responses[0]);
+}
+
+#[test]
+fn deny_holds_for_the_whole_check() {
+    // Trussed polls the denied check until it times out, the grant is for the next one
+    let responses = make_credentials(&[Decision::Deny, Decision::Grant]);
+    assert_eq!(responses[0], [CTAP2_ERR_USER_ACTION_TIMEOUT]);
+    assert_user_present(&responses[1]);
+}
+
+#[test]
+fn timeout() {
+    let before = with_device_presence(Presence::Script, |device| device.controls().uptime());
+    let responses = make_credentials(&[Decision::Timeout(Duration::from_secs(5)), Decision::Grant]);
+    assert_eq!(responses[0], [CTAP2_ERR_USER_ACTION_TIMEOUT]);
+    assert_user_present(&responses[1]);
+
+    let after = with_device_presence(Presence::Script, |device| device.controls().uptime());
+    assert!(after - before >= Duration::from_secs(5), "virtual clock did not move");
+}
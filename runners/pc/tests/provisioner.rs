@@ -0,0 +1,581 @@
+mod common;
+
+use littlefs2::path::PathBuf;
+use trussed::store;
+use trussed::types::{Location, Message};
+
+use common::{apdu, with_device, UUID};
+
+const PROVISIONER_AID: [u8; 9] = [0xa0, 0x00, 0x00, 0x08, 0x47, 0x01, 0x00, 0x00, 0x01];
+
+const SELECT: u8 = 0xa4;
+const WRITE_BINARY: u8 = 0xd0;
+const WRITE_FILE: u8 = 0xbf;
+const READ_FILE: u8 = 0xb4;
+const LIST_DIRECTORY: u8 = 0xb3;
+const DELETE_FILE: u8 = 0xb2;
+const GET_UUID: u8 = 0x62;
+const GENERATE_P256_KEY: u8 = 0xbc;
+const GENERATE_ED255_KEY: u8 = 0xbb;
+const GENERATE_X255_KEY: u8 = 0xb7;
+const SAVE_ED255_CERTIFICATE: u8 = 0xb9;
+const APPEND_INTERMEDIATE: u8 = 0xa7;
+const READ_CHAIN: u8 = 0xaf;
+const SELF_TEST: u8 = 0xab;
+const READ_LOG: u8 = 0xaa;
+const IMPORT_MANIFEST: u8 = 0xa9;
+const IMPORT_ATTESTATION_KEY: u8 = 0xa8;
+const GENERATE_CSR: u8 = 0xb1;
+const GET_RESPONSE: u8 = 0xc0;
+
+const FILENAME_ID: [u8; 2] = [0xe1, 0x01];
+const FILE_ID: [u8; 2] = [0xe1, 0x02];
+
+#[test]
+fn select_returns_uuid() {
+    let response = with_device(|device| device.select(&PROVISIONER_AID));
+    assert_eq!(response.status, 0x9000);
+    assert_eq!(response.data, UUID);
+}
+
+#[test]
+fn get_uuid() {
+    let response = with_device(|device| {
+        device.select(&PROVISIONER_AID);
+        device.apdu(&apdu(0x00, GET_UUID, 0x00, 0x00, &[]))
+    });
+    assert_eq!(response.status, 0x9000);
+    assert_eq!(response.data, UUID);
+}
+
+fn write(device: &mut common::TestDevice, filename: &[u8], contents: &[u8]) {
+    assert_eq!(device.select(&PROVISIONER_AID).status, 0x9000);
+
+    assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILENAME_ID)).status, 0x9000);
+    assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, filename)).status, 0x9000);
+    assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILE_ID)).status, 0x9000);
+    assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, contents)).status, 0x9000);
+    assert_eq!(device.apdu(&apdu(0x00, WRITE_FILE, 0x00, 0x00, &[])).status, 0x9000);
+}
+
+/// A case 4 APDU, expecting up to `le` bytes.
+fn apdu_le(ins: u8, p1: u8, p2: u8, data: &[u8], le: u8) -> Vec<u8> {
+    let mut apdu = apdu(0x00, ins, p1, p2, data);
+    apdu.push(le);
+    apdu
+}
+
+/// Sends a case 4 APDU, and collects a response longer than 256 bytes with GET RESPONSE.
+fn apdu_chained(device: &mut common::TestDevice, ins: u8, p1: u8, p2: u8, data: &[u8]) -> common::Response {
+    let mut response = device.apdu(&apdu_le(ins, p1, p2, data, 0));
+    let mut collected = response.data.clone();
+    while response.status >> 8 == 0x61 {
+        response = device.apdu(&apdu_le(GET_RESPONSE, 0x00, 0x00, &[], response.status as u8));
+        collected.extend_from_slice(&response.data);
+    }
+    response.data = collected;
+    response
+}
+
+#[test]
+fn write_file() {
+    let filename: &[u8] = b"/test/provisioned";
+    let contents = b"written by the provisioner";
+
+    with_device(|device| {
+        write(device, filename, contents);
+
+        let written: Message = store::read(device.store(), Location::Internal, &PathBuf::from(filename))
+            .expect("file not written");
+        assert_eq!(&written[..], &contents[..]);
+    });
+}
+
+#[test]
+fn read_file_in_chunks() {
+    let filename: &[u8] = b"/test/chunked";
+    let contents: Vec<u8> = (0..250).collect();
+
+    with_device(|device| {
+        write(device, filename, &contents);
+
+        let mut read = Vec::new();
+        loop {
+            let offset = (read.len() as u16).to_be_bytes();
+            let response = device.apdu(&apdu_le(READ_FILE, offset[0], offset[1], filename, 100));
+            assert_eq!(response.status, 0x9000);
+            read.extend_from_slice(&response.data);
+            if response.data.len() < 100 {
+                break;
+            }
+        }
+        assert_eq!(read, contents);
+    });
+}
+
+#[test]
+fn secrets_are_not_readable() {
+    with_device(|device| {
+        device.select(&PROVISIONER_AID);
+        assert_eq!(device.apdu(&apdu(0x00, GENERATE_P256_KEY, 0x00, 0x00, &[])).status, 0x9000);
+        let response = device.apdu(&apdu_le(READ_FILE, 0x00, 0x00, b"/attn/sec/01", 0));
+        assert_eq!(response.status, 0x6982);
+        assert!(response.data.is_empty());
+        // nor by a detour
+        for path in [&b"/attn/pub/../sec/01"[..], b"/attn//sec/01", b"/attn/./sec/01", b"/attn/sec/01/"] {
+            assert_eq!(device.apdu(&apdu_le(READ_FILE, 0x00, 0x00, path, 0)).status, 0x6a80);
+        }
+    });
+}
+
+#[test]
+fn list_and_delete() {
+    with_device(|device| {
+        write(device, b"/listed/file", b"12345");
+
+        let response = device.apdu(&apdu_le(LIST_DIRECTORY, 0x00, 0x00, b"/listed", 0));
+        assert_eq!(response.status, 0x9000);
+        // file, 5 bytes, named "file"
+        assert_eq!(response.data, [&[0, 0, 0, 0, 5, 4][..], b"file"].concat());
+
+        assert_eq!(device.apdu(&apdu(0x00, DELETE_FILE, 0x00, 0x00, b"/listed/file")).status, 0x9000);
+        let response = device.apdu(&apdu_le(LIST_DIRECTORY, 0x00, 0x00, b"/listed", 0));
+        assert!(response.data.is_empty());
+        assert_eq!(device.apdu(&apdu(0x00, DELETE_FILE, 0x00, 0x00, b"/listed/file")).status, 0x6a82);
+    });
+}
+
+#[test]
+fn write_binary_at_offsets() {
+    let filename: &[u8] = b"/test/offsets";
+
+    with_device(|device| {
+        assert_eq!(device.select(&PROVISIONER_AID).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILENAME_ID)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, filename)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILE_ID)).status, 0x9000);
+        // out of order, and with a retransmission
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, b"hello ")).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x06, b"world")).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, b"hello ")).status, 0x9000);
+        // gaps are refused
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x20, b"!")).status, 0x6a86);
+
+        // CRC-32 of "hello world"
+        let crc = 0x0d4a_1185u32.to_be_bytes();
+        let wrong = 0x0d4a_1186u32.to_be_bytes();
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_FILE, 0x01, 0x00, &wrong)).status, 0x6300);
+        // a corrupted transfer is dropped and has to be repeated
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_FILE, 0x01, 0x00, &crc)).status, 0x6a80);
+
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILENAME_ID)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, filename)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILE_ID)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, b"hello world")).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_FILE, 0x01, 0x00, &crc)).status, 0x9000);
+    });
+}
+
+#[test]
+fn shorter_transfer_after_an_interrupted_one() {
+    let filename: &[u8] = b"/test/shorter";
+
+    with_device(|device| {
+        // a longer filename and contents, never written
+        assert_eq!(device.select(&PROVISIONER_AID).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILENAME_ID)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, b"/test/shorter/than/this")).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILE_ID)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, &[0x55; 200])).status, 0x9000);
+
+        // selecting the buffers again starts over, without the stale tails
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILENAME_ID)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, filename)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, SELECT, 0x00, 0x00, &FILE_ID)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_BINARY, 0x00, 0x00, b"short")).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, WRITE_FILE, 0x00, 0x00, &[])).status, 0x9000);
+
+        assert_eq!(read_internal(device, "/test/shorter").as_deref(), Some(&b"short"[..]));
+        assert_eq!(read_internal(device, "/test/shorter/than/this"), None);
+    });
+}
+
+/// Splits off the DER TLV at the start of `data`: its tag, the whole TLV, its value, and the rest.
+fn split_der(data: &[u8]) -> (u8, &[u8], &[u8], &[u8]) {
+    let (header, length) = match data[1] {
+        0x81 => (3, data[2] as usize),
+        0x82 => (4, u16::from_be_bytes([data[2], data[3]]) as usize),
+        length => (2, length as usize),
+    };
+    let (tlv, rest) = data.split_at(header + length);
+    (data[0], tlv, &tlv[header..], rest)
+}
+
+#[test]
+fn generate_csr() {
+    use std::convert::TryFrom;
+
+    let serial_number: String = UUID.iter().map(|byte| format!("{:02X}", byte)).collect();
+
+    with_device(|device| {
+        device.select(&PROVISIONER_AID);
+        for algorithm in [0x01, 0x02] {
+            let response = apdu_chained(device, GENERATE_CSR, algorithm, 0x00, &[]);
+            assert_eq!(response.status, 0x9000);
+            // a SEQUENCE spanning the entire response
+            let (tag, _, request, rest) = split_der(&response.data);
+            assert_eq!(tag, 0x30);
+            assert!(rest.is_empty());
+            assert!(response.data
+                .windows(serial_number.len())
+                .any(|window| window == serial_number.as_bytes()));
+
+            if algorithm != 0x02 {
+                continue;
+            }
+            // the Ed25519 request is signed by the key it requests a certificate for
+            let (_, request_info, fields, rest) = split_der(request);
+            let (_, _, _, rest) = split_der(rest);
+            let (tag, _, signature, _) = split_der(rest);
+            assert_eq!(tag, 0x03);
+            // after the version and the subject
+            let (_, _, _, fields) = split_der(fields);
+            let (_, _, _, fields) = split_der(fields);
+            let (_, _, public_key_info, _) = split_der(fields);
+            let (_, _, _, rest) = split_der(public_key_info);
+            let (tag, _, public_key, _) = split_der(rest);
+            assert_eq!(tag, 0x03);
+
+            let public_key = salty::PublicKey::try_from(<&[u8; 32]>::try_from(&public_key[1..]).unwrap()).unwrap();
+            let signature = salty::Signature::from(&<[u8; 64]>::try_from(&signature[1..]).unwrap());
+            assert!(public_key.verify(request_info, &signature).is_ok());
+        }
+
+        let response = device.apdu(&apdu_le(READ_FILE, 0x00, 0x00, b"/attn/sec/02", 0));
+        assert_eq!(response.status, 0x6982);
+
+        // X25519 keys can't sign
+        assert_eq!(device.apdu(&apdu(0x00, GENERATE_CSR, 0x03, 0x00, &[])).status, 0x6a86);
+    });
+}
+
+/// DER TLV, with lengths up to 255 bytes.
+fn der(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut tlv = vec![tag];
+    if value.len() >= 0x80 {
+        tlv.push(0x81);
+    }
+    tlv.push(value.len() as u8);
+    tlv.extend_from_slice(value);
+    tlv
+}
+
+/// A self-issued Ed25519 certificate for `public_key`, with an empty issuer, validity and subject.
+fn ed25519_certificate(public_key: &[u8]) -> Vec<u8> {
+    let ed25519 = der(0x30, &der(0x06, &[0x2b, 0x65, 0x70]));
+    let public_key_info = der(0x30, &[ed25519.clone(), der(0x03, &[&[0][..], public_key].concat())].concat());
+    let tbs = der(0x30, &[
+        der(0xa0, &der(0x02, &[2])),
+        der(0x02, &[1]),
+        ed25519.clone(),
+        der(0x30, &[]),
+        der(0x30, &[]),
+        der(0x30, &[]),
+        public_key_info,
+    ].concat());
+    der(0x30, &[tbs, ed25519, der(0x03, &[0u8; 65])].concat())
+}
+
+#[test]
+fn certificates_must_match_the_key() {
+    with_device(|device| {
+        device.select(&PROVISIONER_AID);
+        let response = device.apdu(&apdu(0x00, GENERATE_ED255_KEY, 0x00, 0x00, &[]));
+        assert_eq!(response.status, 0x9000);
+        let public_key = response.data;
+
+        let garbage = [0x30u8; 120];
+        assert_eq!(device.apdu(&apdu(0x00, SAVE_ED255_CERTIFICATE, 0x00, 0x00, &garbage)).status, 0x6a80);
+
+        let other = ed25519_certificate(&[0x42; 32]);
+        assert_eq!(device.apdu(&apdu(0x00, SAVE_ED255_CERTIFICATE, 0x00, 0x00, &other)).status, 0x6985);
+
+        let certificate = ed25519_certificate(&public_key);
+        assert_eq!(device.apdu(&apdu(0x00, SAVE_ED255_CERTIFICATE, 0x00, 0x00, &certificate)).status, 0x9000);
+        let saved: Message = store::read(device.store(), Location::Internal, &PathBuf::from("/attn/x5c/02"))
+            .expect("certificate not saved");
+        assert_eq!(&saved[..], &certificate[..]);
+    });
+}
+
+#[test]
+fn certificate_chain() {
+    with_device(|device| {
+        device.select(&PROVISIONER_AID);
+        let public_key = device.apdu(&apdu(0x00, GENERATE_ED255_KEY, 0x00, 0x00, &[])).data;
+        let leaf = ed25519_certificate(&public_key);
+        let intermediate = ed25519_certificate(&[0x01; 32]);
+        let root = ed25519_certificate(&[0x02; 32]);
+
+        assert_eq!(device.apdu(&apdu(0x00, SAVE_ED255_CERTIFICATE, 0x00, 0x00, &leaf)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, APPEND_INTERMEDIATE, 0x02, 0x00, &intermediate)).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, APPEND_INTERMEDIATE, 0x02, 0x00, &root)).status, 0x9000);
+
+        let response = apdu_chained(device, READ_CHAIN, 0x02, 0x00, &[]);
+        assert_eq!(response.status, 0x9000);
+        assert_eq!(response.data, [&leaf[..], &intermediate, &root].concat());
+
+        // a new leaf starts a new chain
+        assert_eq!(device.apdu(&apdu(0x00, SAVE_ED255_CERTIFICATE, 0x00, 0x00, &leaf)).status, 0x9000);
+        let response = apdu_chained(device, READ_CHAIN, 0x02, 0x00, &[]);
+        assert_eq!(response.data, leaf);
+
+        assert_eq!(device.apdu(&apdu(0x00, APPEND_INTERMEDIATE, 0x04, 0x00, &root)).status, 0x6a86);
+        assert_eq!(device.apdu(&apdu_le(READ_CHAIN, 0x04, 0x00, &[], 0)).status, 0x6a86);
+    });
+}
+
+#[test]
+fn self_test() {
+    use serde_cbor::Value;
+
+    let field = |slot: &Value, name: &str| match slot {
+        Value::Map(map) => map.get(&Value::Text(name.into())).cloned(),
+        _ => panic!("slot is not a map"),
+    };
+
+    with_device(|device| {
+        device.select(&PROVISIONER_AID);
+        let public_key = device.apdu(&apdu(0x00, GENERATE_ED255_KEY, 0x00, 0x00, &[])).data;
+        let certificate = ed25519_certificate(&public_key);
+        assert_eq!(device.apdu(&apdu(0x00, SAVE_ED255_CERTIFICATE, 0x00, 0x00, &certificate)).status, 0x9000);
+
+        let response = device.apdu(&apdu_le(SELF_TEST, 0x00, 0x00, &[], 0));
+        assert_eq!(response.status, 0x9000);
+        let slots = match serde_cbor::from_slice(&response.data).unwrap() {
+            Value::Array(slots) => slots,
+            _ => panic!("report is not an array"),
+        };
+        assert_eq!(slots.len(), 3);
+        for (index, slot) in slots.iter().enumerate() {
+            assert_eq!(field(slot, "slot"), Some(Value::Integer(index as i128 + 1)));
+        }
+        for name in ["key", "cert", "verified"] {
+            assert_eq!(field(&slots[1], name), Some(Value::Bool(true)));
+        }
+    });
+}
+
+#[test]
+fn provisioning_is_logged() {
+    use serde_cbor::Value;
+
+    let filename = "/test/logged";
+    let contents = b"worth remembering";
+
+    let log = with_device(|device| {
+        write(device, filename.as_bytes(), contents);
+        write(device, b"/attn/sec/logged", contents);
+        let imported = manifest(&[("/test/manifest-logged", contents), ("/attn/sec/manifest-logged", contents)], &[], &[]);
+        assert_eq!(device.apdu(&apdu_le(IMPORT_MANIFEST, 0x00, 0x00, &imported, 0)).status, 0x9000);
+
+        let mut log = Vec::new();
+        loop {
+            let offset = (log.len() as u16).to_be_bytes();
+            let response = device.apdu(&apdu_le(READ_LOG, offset[0], offset[1], &[], 200));
+            assert_eq!(response.status, 0x9000);
+            log.extend_from_slice(&response.data);
+            if response.data.len() < 200 {
+                break;
+            }
+        }
+        log
+    });
+
+    let entries: Vec<Value> = serde_cbor::Deserializer::from_slice(&log)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let field = |entry: &Value, key: i128| match entry {
+        Value::Map(map) => map.get(&Value::Integer(key)).cloned(),
+        _ => panic!("entry is not a map"),
+    };
+    let logged = |path: &str| entries.iter().rev()
+        .find(|entry| field(entry, 2) == Some(Value::Text(path.into())))
+        .expect("not logged");
+    let digest = {
+        use sha2::Digest;
+        sha2::Sha256::digest(contents).to_vec()
+    };
+
+    let entry = logged(filename);
+    assert_eq!(field(entry, 1), Some(Value::Integer(WRITE_FILE as i128)));
+    assert_eq!(field(entry, 3), Some(Value::Integer(contents.len() as i128)));
+    assert_eq!(field(entry, 4), Some(Value::Bytes(digest.clone())));
+    assert_eq!(field(entry, 6), Some(Value::Integer(0x9000)));
+
+    // secrets only by their length
+    let entry = logged("/attn/sec/logged");
+    assert_eq!(field(entry, 3), Some(Value::Integer(contents.len() as i128)));
+    assert_eq!(field(entry, 4), None);
+
+    // an entry per item of a manifest
+    let entry = logged("/test/manifest-logged");
+    assert_eq!(field(entry, 1), Some(Value::Integer(IMPORT_MANIFEST as i128)));
+    assert_eq!(field(entry, 4), Some(Value::Bytes(digest)));
+    assert_eq!(field(entry, 6), Some(Value::Integer(0x9000)));
+    let entry = logged("/attn/sec/manifest-logged");
+    assert_eq!(field(entry, 1), Some(Value::Integer(IMPORT_MANIFEST as i128)));
+    assert_eq!(field(entry, 4), None);
+}
+
+/// The CBOR manifest of `ImportManifest`.
+fn manifest(files: &[(&str, &[u8])], keys: &[i128], certs: &[(i128, &[u8])]) -> Vec<u8> {
+    use serde_cbor::Value;
+    use std::collections::BTreeMap;
+
+    let mut manifest = BTreeMap::new();
+    manifest.insert(Value::Text("files".into()), Value::Map(files.iter()
+        .map(|(path, contents)| (Value::Text(path.to_string()), Value::Bytes(contents.to_vec())))
+        .collect()));
+    manifest.insert(Value::Text("keys".into()), Value::Array(keys.iter()
+        .map(|slot| Value::Integer(*slot))
+        .collect()));
+    manifest.insert(Value::Text("certs".into()), Value::Map(certs.iter()
+        .map(|(slot, cert)| (Value::Integer(*slot), Value::Bytes(cert.to_vec())))
+        .collect()));
+    serde_cbor::to_vec(&Value::Map(manifest)).unwrap()
+}
+
+fn read_internal(device: &common::TestDevice, path: &str) -> Option<Vec<u8>> {
+    let contents: Option<Message> = store::read(device.store(), Location::Internal, &PathBuf::from(path)).ok();
+    contents.map(|contents| contents.to_vec())
+}
+
+fn store_internal(device: &common::TestDevice, path: &str, contents: &[u8]) {
+    store::store(device.store(), Location::Internal, &PathBuf::from(path), contents).unwrap();
+}
+
+#[test]
+fn import_manifest() {
+    use serde_cbor::Value;
+
+    with_device(|device| {
+        device.select(&PROVISIONER_AID);
+
+        let imported = manifest(&[("/manifest/a", b"one"), ("/manifest/b", b"two")], &[2], &[]);
+        let response = device.apdu(&apdu_le(IMPORT_MANIFEST, 0x00, 0x00, &imported, 0));
+        assert_eq!(response.status, 0x9000);
+        let public_keys = match serde_cbor::from_slice(&response.data).unwrap() {
+            Value::Map(public_keys) => public_keys,
+            _ => panic!("reply is not a map"),
+        };
+        assert!(matches!(public_keys.get(&Value::Integer(2)), Some(Value::Bytes(key)) if key.len() == 32));
+        assert_eq!(read_internal(device, "/manifest/a").as_deref(), Some(&b"one"[..]));
+        assert_eq!(read_internal(device, "/manifest/b").as_deref(), Some(&b"two"[..]));
+        assert_eq!(read_internal(device, "/attn/sec/02.new"), None);
+
+        // nothing is applied if anything is wrong
+        let rejected = manifest(&[("/manifest/a", b"changed"), ("/manifest/c", b"three")], &[], &[(1, &[0x30, 0x00])]);
+        let response = device.apdu(&apdu_le(IMPORT_MANIFEST, 0x00, 0x00, &rejected, 0));
+        assert_eq!(response.status, 0x6a80);
+        assert_eq!(read_internal(device, "/manifest/a").as_deref(), Some(&b"one"[..]));
+        assert_eq!(read_internal(device, "/manifest/a.new"), None);
+        assert_eq!(read_internal(device, "/manifest/c"), None);
+    });
+}
+
+#[test]
+fn interrupted_manifest_import() {
+    let interrupted = manifest(&[("/interrupted/a", b"new"), ("/interrupted/b", b"new")], &[], &[]);
+
+    with_device(|device| {
+        // power lost after replacing a, before replacing b
+        store_internal(device, "/interrupted/a", b"new");
+        store_internal(device, "/interrupted/a.old", b"old");
+        store_internal(device, "/interrupted/b", b"old");
+        store_internal(device, "/interrupted/b.new", b"new");
+        store_internal(device, "/attn/manifest.commit", &interrupted);
+        assert_eq!(device.select(&PROVISIONER_AID).status, 0x9000);
+        assert_eq!(read_internal(device, "/interrupted/a").as_deref(), Some(&b"old"[..]));
+        assert_eq!(read_internal(device, "/interrupted/b").as_deref(), Some(&b"old"[..]));
+        for leftover in ["/interrupted/a.old", "/interrupted/b.new", "/attn/manifest.commit"] {
+            assert_eq!(read_internal(device, leftover), None);
+        }
+
+        // power lost after replacing both, before removing the originals
+        store_internal(device, "/interrupted/a", b"new");
+        store_internal(device, "/interrupted/a.old", b"old");
+        store_internal(device, "/interrupted/b", b"new");
+        store_internal(device, "/interrupted/b.old", b"old");
+        store_internal(device, "/attn/manifest.done", &interrupted);
+        let response = device.apdu(&apdu_le(IMPORT_MANIFEST, 0x00, 0x00, &manifest(&[], &[], &[]), 0));
+        assert_eq!(response.status, 0x9000);
+        assert_eq!(read_internal(device, "/interrupted/a").as_deref(), Some(&b"new"[..]));
+        assert_eq!(read_internal(device, "/interrupted/b").as_deref(), Some(&b"new"[..]));
+        for leftover in ["/interrupted/a.old", "/interrupted/b.old", "/attn/manifest.done"] {
+            assert_eq!(read_internal(device, leftover), None);
+        }
+    });
+}
+
+/// Wraps the seed to the device's X25519 attestation key, for the given slot.
+fn wrap_key(device_public: &[u8], slot: u8, seed: &[u8; 32]) -> Vec<u8> {
+    use chacha20poly1305::aead::{AeadInPlace, NewAead};
+    use chacha20poly1305::ChaCha20Poly1305;
+    use sha2::{Digest, Sha256};
+
+    let ephemeral = salty::agreement::SecretKey::from_seed(&[0x42; 32]);
+    let ephemeral_public = salty::agreement::PublicKey::from(&ephemeral).to_bytes();
+    let mut device = [0u8; 32];
+    device.copy_from_slice(device_public);
+    let shared = ephemeral.agree(&salty::agreement::PublicKey::from(device));
+    let key = Sha256::new()
+        .chain(shared.to_bytes())
+        .chain(ephemeral_public)
+        .chain(device_public)
+        .finalize();
+    let mut aad = vec![slot];
+    aad.extend_from_slice(&UUID);
+
+    let nonce = [0x24; 12];
+    let mut encrypted = seed.to_vec();
+    let tag = ChaCha20Poly1305::new(&key)
+        .encrypt_in_place_detached(&nonce.into(), &aad, &mut encrypted)
+        .unwrap();
+
+    let mut wrapped = ephemeral_public.to_vec();
+    wrapped.extend_from_slice(&nonce);
+    wrapped.extend_from_slice(&encrypted);
+    wrapped.extend_from_slice(&tag);
+    wrapped
+}
+
+#[test]
+fn import_attestation_key() {
+    with_device(|device| {
+        device.select(&PROVISIONER_AID);
+        let device_public = device.apdu(&apdu(0x00, GENERATE_X255_KEY, 0x00, 0x00, &[])).data;
+        let seed = [0x17; 32];
+        let wrapped = wrap_key(&device_public, 0x02, &seed);
+
+        // wrapped for the Ed25519 slot only
+        let response = device.apdu(&apdu(0x00, IMPORT_ATTESTATION_KEY, 0x01, 0x00, &wrapped));
+        assert_eq!(response.status, 0x6300);
+        let mut tampered = wrapped.clone();
+        tampered[50] ^= 1;
+        let response = device.apdu(&apdu(0x00, IMPORT_ATTESTATION_KEY, 0x02, 0x00, &tampered));
+        assert_eq!(response.status, 0x6300);
+        let response = device.apdu(&apdu(0x00, IMPORT_ATTESTATION_KEY, 0x02, 0x02, &wrapped));
+        assert_eq!(response.status, 0x6a86);
+
+        let response = device.apdu(&apdu(0x00, IMPORT_ATTESTATION_KEY, 0x02, 0x00, &wrapped));
+        assert_eq!(response.status, 0x9000);
+        let public_key = salty::Keypair::from(&seed).public;
+        assert_eq!(response.data, public_key.as_bytes());
+
+        // the imported key is used like a generated one
+        let certificate = ed25519_certificate(&response.data);
+        assert_eq!(device.apdu(&apdu(0x00, SAVE_ED255_CERTIFICATE, 0x00, 0x00, &certificate)).status, 0x9000);
+    });
+}
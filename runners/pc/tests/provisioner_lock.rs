@@ -0,0 +1,36 @@
+//! Locking is permanent, so it gets a device (test binary) of its own.
+
+mod common;
+
+use common::{apdu, with_device, UUID};
+
+const PROVISIONER_AID: [u8; 9] = [0xa0, 0x00, 0x00, 0x08, 0x47, 0x01, 0x00, 0x00, 0x01];
+
+const WRITE_BINARY: u8 = 0xd0;
+const LIST_DIRECTORY: u8 = 0xb3;
+const DELETE_FILE: u8 = 0xb2;
+const REFORMAT_FILESYSTEM: u8 = 0xbd;
+const GET_UUID: u8 = 0x62;
+const GENERATE_P256_KEY: u8 = 0xbc;
+const LOCK_PROVISIONING: u8 = 0xae;
+
+#[test]
+fn locked_provisioner_is_read_only() {
+    with_device(|device| {
+        assert_eq!(device.select(&PROVISIONER_AID).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, GENERATE_P256_KEY, 0x00, 0x00, &[])).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, LOCK_PROVISIONING, 0x00, 0x00, &[])).status, 0x9000);
+
+        for ins in [WRITE_BINARY, REFORMAT_FILESYSTEM, GENERATE_P256_KEY, LOCK_PROVISIONING] {
+            assert_eq!(device.apdu(&apdu(0x00, ins, 0x00, 0x00, &[])).status, 0x6982);
+        }
+        assert_eq!(device.apdu(&apdu(0x00, DELETE_FILE, 0x00, 0x00, b"/attn/locked")).status, 0x6982);
+
+        let response = device.apdu(&apdu(0x00, GET_UUID, 0x00, 0x00, &[]));
+        assert_eq!(response.status, 0x9000);
+        assert_eq!(response.data, UUID);
+        let mut list = apdu(0x00, LIST_DIRECTORY, 0x00, 0x00, b"/attn/sec");
+        list.push(0);
+        assert_eq!(device.apdu(&list).status, 0x9000);
+    });
+}
@@ -0,0 +1,56 @@
+//! Saving the T1 intermediate public key makes it the factory key, for good,
+//! so sessions get a device (test binary) of their own.
+
+mod common;
+
+use common::{apdu, with_device, UUID};
+
+const PROVISIONER_AID: [u8; 9] = [0xa0, 0x00, 0x00, 0x08, 0x47, 0x01, 0x00, 0x00, 0x01];
+
+const GET_UUID: u8 = 0x62;
+const GENERATE_P256_KEY: u8 = 0xbc;
+const SAVE_T1_PUBLIC_KEY: u8 = 0xb5;
+const GET_CHALLENGE: u8 = 0xad;
+const AUTHENTICATE: u8 = 0xac;
+
+fn authenticate(device: &mut common::TestDevice, factory_key: &salty::Keypair) -> u16 {
+    let response = device.apdu(&apdu(0x00, GET_CHALLENGE, 0x00, 0x00, &[]));
+    assert_eq!(response.status, 0x9000);
+    assert_eq!(response.data.len(), 32);
+    let message = [&b"solo2 provisioning session\0"[..], &response.data, &UUID].concat();
+    let signature = factory_key.sign(&message).to_bytes();
+    device.apdu(&apdu(0x00, AUTHENTICATE, 0x00, 0x00, &signature)).status
+}
+
+#[test]
+fn mutating_instructions_need_a_session() {
+    let factory_key = salty::Keypair::from(&[0x17; 32]);
+    let impostor = salty::Keypair::from(&[0x18; 32]);
+
+    with_device(|device| {
+        assert_eq!(device.select(&PROVISIONER_AID).status, 0x9000);
+        // no factory key yet
+        assert_eq!(device.apdu(&apdu(0x00, GENERATE_P256_KEY, 0x00, 0x00, &[])).status, 0x9000);
+        let public_key = factory_key.public.as_bytes();
+        assert_eq!(device.apdu(&apdu(0x00, SAVE_T1_PUBLIC_KEY, 0x00, 0x00, public_key)).status, 0x9000);
+
+        assert_eq!(device.apdu(&apdu(0x00, GENERATE_P256_KEY, 0x00, 0x00, &[])).status, 0x6982);
+        assert_eq!(device.apdu(&apdu(0x00, GET_UUID, 0x00, 0x00, &[])).status, 0x9000);
+
+        assert_eq!(authenticate(device, &impostor), 0x6300);
+        assert_eq!(device.apdu(&apdu(0x00, GENERATE_P256_KEY, 0x00, 0x00, &[])).status, 0x6982);
+        // challenges are single use
+        assert_eq!(device.apdu(&apdu(0x00, AUTHENTICATE, 0x00, 0x00, &[0; 64])).status, 0x6985);
+        // signatures without the context are refused
+        let challenge = device.apdu(&apdu(0x00, GET_CHALLENGE, 0x00, 0x00, &[])).data;
+        let signature = factory_key.sign(&[&challenge[..], &UUID].concat()).to_bytes();
+        assert_eq!(device.apdu(&apdu(0x00, AUTHENTICATE, 0x00, 0x00, &signature)).status, 0x6300);
+
+        assert_eq!(authenticate(device, &factory_key), 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, GENERATE_P256_KEY, 0x00, 0x00, &[])).status, 0x9000);
+
+        // selecting ends the session
+        assert_eq!(device.select(&PROVISIONER_AID).status, 0x9000);
+        assert_eq!(device.apdu(&apdu(0x00, GENERATE_P256_KEY, 0x00, 0x00, &[])).status, 0x6982);
+    });
+}
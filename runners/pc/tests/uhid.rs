@@ -0,0 +1,159 @@
+//! The CTAPHID socket, with this test playing both the client and the apps.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ctaphid_dispatch::command::Command;
+use ctaphid_dispatch::types::{HidInterchange, Message};
+use interchange::{Interchange, Responder};
+
+use solo_pc::transport::uhid::{CtaphidSocket, KEEPALIVE_INTERVAL, MESSAGE_TIMEOUT, PACKET_SIZE};
+
+const CHANNEL: u32 = 0x0102_0304;
+
+const PING: u8 = 0x01;
+const CBOR: u8 = 0x10;
+const KEEPALIVE: u8 = 0x3b;
+const ERROR: u8 = 0x3f;
+
+const ERR_MSG_TIMEOUT: u8 = 0x05;
+
+struct Harness {
+    path: PathBuf,
+    socket: CtaphidSocket,
+    apps: Responder<HidInterchange>,
+}
+
+// The interchange is a singleton, the tests take turns.
+static HARNESS: Mutex<Option<Harness>> = Mutex::new(None);
+
+fn with_socket<T>(f: impl FnOnce(&mut Harness) -> T) -> T {
+    let mut harness = HARNESS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let harness = harness.get_or_insert_with(|| {
+        let path = std::env::temp_dir().join(format!("solo-pc-uhid-{}.sock", std::process::id()));
+        let (requester, apps) = HidInterchange::claim().unwrap();
+        let socket = CtaphidSocket::bind(&path, requester).unwrap();
+        Harness { path, socket, apps }
+    });
+    f(harness)
+}
+
+impl Harness {
+    fn connect(&mut self) -> UnixStream {
+        let client = UnixStream::connect(&self.path).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        // once to notice the previous client left, once to accept this one
+        self.socket.poll();
+        self.socket.poll();
+        client
+    }
+
+    /// Polls the socket until a packet arrives, for at most `timeout`.
+    fn receive(&mut self, client: &mut UnixStream, timeout: Duration) -> Option<[u8; PACKET_SIZE]> {
+        let start = Instant::now();
+        let mut packet = [0u8; PACKET_SIZE];
+        while start.elapsed() < timeout {
+            self.socket.poll();
+            if client.read_exact(&mut packet).is_ok() {
+                return Some(packet);
+            }
+        }
+        None
+    }
+}
+
+fn init_packet(command: u8, length: usize, data: &[u8]) -> [u8; PACKET_SIZE] {
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[..4].copy_from_slice(&CHANNEL.to_be_bytes());
+    packet[4] = command | 0x80;
+    packet[5..7].copy_from_slice(&(length as u16).to_be_bytes());
+    packet[7..7 + data.len()].copy_from_slice(data);
+    packet
+}
+
+/// A PING that fits into one packet goes through, so nothing is left over.
+fn assert_ping(harness: &mut Harness, client: &mut UnixStream) {
+    client.write_all(&init_packet(PING, 4, b"ping")).unwrap();
+    let response = harness.receive(client, Duration::from_secs(1)).expect("no PING response");
+    assert_eq!(response[..11], init_packet(PING, 4, b"ping")[..11]);
+}
+
+#[test]
+fn stalled_message_times_out() {
+    with_socket(|harness| {
+        let mut client = harness.connect();
+        // a 100 byte message, whose continuation packet never comes
+        client.write_all(&init_packet(PING, 100, &[0x55; 57])).unwrap();
+        assert_eq!(harness.receive(&mut client, MESSAGE_TIMEOUT / 2), None);
+
+        let response = harness.receive(&mut client, MESSAGE_TIMEOUT).expect("no timeout");
+        assert_eq!(response[..8], init_packet(ERROR, 1, &[ERR_MSG_TIMEOUT])[..8]);
+        assert_ping(harness, &mut client);
+    });
+}
+
+#[test]
+fn slow_request_is_kept_alive() {
+    with_socket(|harness| {
+        let mut client = harness.connect();
+        client.write_all(&init_packet(CBOR, 1, &[0x04])).unwrap();
+        assert_eq!(harness.receive(&mut client, Duration::from_millis(10)), None);
+        assert!(matches!(harness.apps.take_request(), Some((Command::Cbor, _))));
+
+        let keepalive = harness.receive(&mut client, 2 * KEEPALIVE_INTERVAL).expect("no keepalive");
+        // STATUS_PROCESSING
+        assert_eq!(keepalive[..8], init_packet(KEEPALIVE, 1, &[0x01])[..8]);
+
+        harness.apps.respond(&Ok(Message::from_slice(&[0x00]).unwrap())).unwrap();
+        let mut response = harness.receive(&mut client, Duration::from_secs(1)).unwrap();
+        while response[4] == KEEPALIVE | 0x80 {
+            response = harness.receive(&mut client, Duration::from_secs(1)).unwrap();
+        }
+        assert_eq!(response[..8], init_packet(CBOR, 1, &[0x00])[..8]);
+    });
+}
+
+#[test]
+fn closing_resets_the_channel() {
+    with_socket(|harness| {
+        // half a message
+        let mut client = harness.connect();
+        client.write_all(&init_packet(PING, 100, &[0x55; 57])).unwrap();
+        assert_eq!(harness.receive(&mut client, Duration::from_millis(10)), None);
+        drop(client);
+        harness.socket.poll();
+
+        let mut client = harness.connect();
+        assert_ping(harness, &mut client);
+        drop(client);
+        harness.socket.poll();
+
+        // a request the apps did not get to yet
+        let mut client = harness.connect();
+        client.write_all(&init_packet(CBOR, 1, &[0x04])).unwrap();
+        assert_eq!(harness.receive(&mut client, Duration::from_millis(10)), None);
+        drop(client);
+        harness.socket.poll();
+        assert!(harness.apps.take_request().is_none(), "request not cancelled");
+
+        // and one they answered, too late
+        let mut client = harness.connect();
+        client.write_all(&init_packet(CBOR, 1, &[0x04])).unwrap();
+        assert_eq!(harness.receive(&mut client, Duration::from_millis(10)), None);
+        assert!(harness.apps.take_request().is_some());
+        harness.apps.respond(&Ok(Message::from_slice(&[0x00]).unwrap())).unwrap();
+        drop(client);
+        harness.socket.poll();
+
+        let mut client = harness.connect();
+        client.write_all(&init_packet(CBOR, 1, &[0x04])).unwrap();
+        assert_eq!(harness.receive(&mut client, Duration::from_millis(10)), None);
+        assert!(harness.apps.take_request().is_some(), "interchange still busy");
+        harness.apps.respond(&Ok(Message::from_slice(&[0x00]).unwrap())).unwrap();
+        let response = harness.receive(&mut client, Duration::from_secs(1)).unwrap();
+        assert_eq!(response[..8], init_packet(CBOR, 1, &[0x00])[..8]);
+    });
+}
@@ -0,0 +1,121 @@
+//! The vpcd client, with this test playing both `vpcd` and the apps.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use apdu_dispatch::interchanges::{Contact, Data};
+use interchange::{Interchange, Responder};
+
+use solo_pc::transport::vpcd::{Vpcd, DEFAULT_ATR};
+
+const POWER_OFF: u8 = 0;
+const POWER_ON: u8 = 1;
+const RESET: u8 = 2;
+const GET_ATR: u8 = 4;
+
+/// SELECT of the PIV application.
+const SELECT_PIV: [u8; 10] = [0x00, 0xa4, 0x04, 0x00, 0x05, 0xa0, 0x00, 0x00, 0x03, 0x08];
+
+struct Harness {
+    vpcd: Vpcd,
+    /// The connection `vpcd` would accept.
+    reader: TcpStream,
+    apps: Responder<Contact>,
+}
+
+impl Harness {
+    /// The interchange is a singleton, so there is a single test.
+    fn connect() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (requester, apps) = Contact::claim().unwrap();
+        let mut vpcd = Vpcd::new(&address, &DEFAULT_ATR, requester);
+        vpcd.poll();
+        let (reader, _) = listener.accept().unwrap();
+        reader.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        Self { vpcd, reader, apps }
+    }
+
+    fn send(&mut self, message: &[u8]) {
+        let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(message);
+        self.reader.write_all(&framed).unwrap();
+    }
+
+    /// Polls until a message arrives, for at most `timeout`.
+    fn receive(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+        let start = Instant::now();
+        let mut length = [0u8; 2];
+        while start.elapsed() < timeout {
+            self.vpcd.poll();
+            if self.reader.read_exact(&mut length).is_ok() {
+                let mut message = vec![0; u16::from_be_bytes(length) as usize];
+                self.reader.set_read_timeout(None).unwrap();
+                self.reader.read_exact(&mut message).unwrap();
+                self.reader.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+                return Some(message);
+            }
+        }
+        None
+    }
+
+    /// Polls until the apps get a command, and answers it with `response`.
+    fn answer(&mut self, response: &[u8]) -> Vec<u8> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(1) {
+            self.vpcd.poll();
+            if let Some(command) = self.apps.take_request() {
+                self.apps.respond(&Data::from_slice(response).unwrap()).unwrap();
+                return command.to_vec();
+            }
+        }
+        panic!("no command");
+    }
+}
+
+#[test]
+fn vpcd_protocol() {
+    let mut harness = Harness::connect();
+
+    // control messages
+    harness.send(&[GET_ATR]);
+    assert_eq!(harness.receive(Duration::from_secs(1)).unwrap(), DEFAULT_ATR);
+    for control in [POWER_ON, RESET, 0x42] {
+        harness.send(&[control]);
+        assert_eq!(harness.receive(Duration::from_millis(50)), None, "control message {} answered", control);
+    }
+
+    // a command APDU, arriving in pieces
+    let mut framed = (SELECT_PIV.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(&SELECT_PIV);
+    for piece in [&framed[..1], &framed[1..6], &framed[6..]] {
+        harness.reader.write_all(piece).unwrap();
+        harness.reader.flush().unwrap();
+        harness.vpcd.poll();
+    }
+    assert_eq!(harness.answer(&[0x61, 0x11, 0x90, 0x00]), SELECT_PIV);
+    assert_eq!(harness.receive(Duration::from_secs(1)).unwrap(), [0x61, 0x11, 0x90, 0x00]);
+
+    // two back to back
+    let mut framed = Vec::new();
+    for apdu in [&[0x00, 0xcb, 0x3f, 0xff][..], &[0x00, 0xfd, 0x00, 0x00]] {
+        framed.extend_from_slice(&(apdu.len() as u16).to_be_bytes());
+        framed.extend_from_slice(apdu);
+    }
+    harness.reader.write_all(&framed).unwrap();
+    assert_eq!(harness.answer(&[0x6a, 0x82]), [0x00, 0xcb, 0x3f, 0xff]);
+    assert_eq!(harness.receive(Duration::from_secs(1)).unwrap(), [0x6a, 0x82]);
+    assert_eq!(harness.answer(&[0x90, 0x00]), [0x00, 0xfd, 0x00, 0x00]);
+    assert_eq!(harness.receive(Duration::from_secs(1)).unwrap(), [0x90, 0x00]);
+
+    // powering off drops the pending command, the card starts over
+    harness.send(&SELECT_PIV);
+    harness.send(&[POWER_OFF]);
+    assert_eq!(harness.receive(Duration::from_millis(50)), None);
+    assert!(harness.apps.take_request().is_none(), "command not cancelled");
+    harness.send(&[POWER_ON]);
+    harness.send(&SELECT_PIV);
+    assert_eq!(harness.answer(&[0x90, 0x00]), SELECT_PIV);
+    assert_eq!(harness.receive(Duration::from_secs(1)).unwrap(), [0x90, 0x00]);
+}
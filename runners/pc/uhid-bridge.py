@@ -0,0 +1,93 @@
+#!/usr/bin/env python3
+"""Bridge the PC runner's CTAPHID socket to /dev/uhid.
+
+Run the runner with the `uhid` transport, then this script (with access to /dev/uhid),
+and the virtual key shows up as a FIDO HID device.
+"""
+import os
+import select
+import socket
+import struct
+import sys
+
+SOCKET = sys.argv[1] if len(sys.argv) > 1 else "solo2-ctaphid.sock"
+
+UHID_DESTROY = 1
+UHID_OUTPUT = 6
+UHID_CREATE2 = 11
+UHID_INPUT2 = 12
+
+BUS_USB = 0x03
+EVENT_SIZE = 4380
+
+# FIDO usage page, 64 byte input and output reports
+REPORT_DESCRIPTOR = bytes.fromhex(
+    "06d0f1 0901 a101"
+    " 0920 1500 26ff00 7508 9540 8102"
+    " 0921 1500 26ff00 7508 9540 9102"
+    " c0".replace(" ", "")
+)
+
+
+def event(kind, payload):
+    return struct.pack("<I", kind) + payload.ljust(EVENT_SIZE - 4, b"\0")
+
+
+def create2():
+    return event(
+        UHID_CREATE2,
+        struct.pack(
+            "<128s64s64sHHIIII4096s",
+            b"SoloKeys Solo 2 (PC runner)",
+            b"",
+            b"",
+            len(REPORT_DESCRIPTOR),
+            BUS_USB,
+            0x1209,
+            0xBEEE,
+            0,
+            0,
+            REPORT_DESCRIPTOR,
+        ),
+    )
+
+
+def main():
+    uhid = os.open("/dev/uhid", os.O_RDWR)
+    os.write(uhid, create2())
+
+    runner = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+    runner.connect(SOCKET)
+
+    pending = b""
+    try:
+        while True:
+            readable, _, _ = select.select([uhid, runner], [], [])
+            if uhid in readable:
+                ev = os.read(uhid, EVENT_SIZE)
+                (kind,) = struct.unpack_from("<I", ev)
+                if kind == UHID_OUTPUT:
+                    size = struct.unpack_from("<H", ev, 4 + 4096)[0]
+                    report = ev[4 : 4 + size]
+                    # hidraw writes start with the (absent) report ID
+                    if size == 65:
+                        report = report[1:]
+                    runner.sendall(report.ljust(64, b"\0"))
+            if runner in readable:
+                data = runner.recv(4096)
+                if not data:
+                    break
+                pending += data
+                while len(pending) >= 64:
+                    report, pending = pending[:64], pending[64:]
+                    os.write(
+                        uhid,
+                        event(UHID_INPUT2, struct.pack("<H", 64) + report),
+                    )
+    finally:
+        os.write(uhid, event(UHID_DESTROY, b""))
+        os.close(uhid)
+
+
+if __name__ == "__main__":
+    main()